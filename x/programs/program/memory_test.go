@@ -4,6 +4,8 @@
 package program
 
 import (
+	"strconv"
+	"strings"
 	"testing"
 
 	"github.com/bytecodealliance/wasmtime-go/v14"
@@ -39,6 +41,32 @@ func TestMemory(t *testing.T) {
 	require.NoError(err)
 }
 
+// TestSmartPtrGolden decodes the packed pointers the Rust SDK is tested to
+// produce, so the layout can't drift on one side of the boundary alone.
+func TestSmartPtrGolden(t *testing.T) {
+	require := require.New(t)
+	golden := tests.ReadFixture(t, "../tests/fixture/host_ptr.golden")
+
+	for _, line := range strings.Split(strings.TrimSpace(string(golden)), "\n") {
+		if strings.HasPrefix(line, "#") {
+			continue
+		}
+		fields := strings.Fields(line)
+		require.Len(fields, 4, line)
+
+		offset, err := strconv.ParseUint(fields[1], 0, 32)
+		require.NoError(err)
+		length, err := strconv.ParseUint(fields[2], 0, 32)
+		require.NoError(err)
+		packed, err := strconv.ParseUint(fields[3], 16, 64)
+		require.NoError(err)
+
+		ptr := SmartPtr(int64(packed))
+		require.Equal(uint32(offset), ptr.PtrOffset(), fields[0])
+		require.Equal(uint32(length), ptr.Len(), fields[0])
+	}
+}
+
 func newTestMemory(t *testing.T) *Memory {
 	require := require.New(t)
 	wasmBytes := tests.ReadFixture(t, "../tests/fixture/memory.wasm")
@@ -14,7 +14,8 @@ pub fn initialize_address(context: Context, address: Address) -> bool {
     if program
         .state()
         .get::<i64>(StateKeys::Counter(address))
-        .is_ok()
+        .expect("failed to get counter")
+        .is_some()
     {
         panic!("counter already initialized for address")
     }
@@ -56,6 +57,7 @@ pub fn get_value(context: Context, of: Address) -> i64 {
         .state()
         .get(StateKeys::Counter(of))
         .expect("failed to get counter")
+        .expect("counter should be initialized")
 }
 
 /// Gets the count at the address for an external program.
@@ -1,8 +1,11 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use wasmlanche_sdk::Context;
-use wasmlanche_sdk::{public, state_keys, types::Address};
+use wasmlanche_sdk::{
+    public, state_keys,
+    types::{Address, Amount},
+};
 
-const INITIAL_SUPPLY: i64 = 123456789;
+const INITIAL_SUPPLY: Amount = Amount::new(123456789);
 
 /// The program state keys.
 #[state_keys]
@@ -51,6 +54,8 @@ pub fn get_total_supply(context: Context) -> i64 {
         .state()
         .get(StateKey::TotalSupply)
         .expect("failed to get total supply")
+        .map(into_param)
+        .expect("total supply should be initialized")
 }
 
 /// Transfers balance from the token owner to the recipient.
@@ -59,12 +64,17 @@ pub fn mint_to(context: Context, recipient: Address, amount: i64) -> bool {
     let Context { program } = context;
     let balance = program
         .state()
-        .get::<i64>(StateKey::Balance(recipient))
+        .get::<Amount>(StateKey::Balance(recipient))
+        .expect("failed to get balance")
         .unwrap_or_default();
 
+    let balance = balance
+        .checked_add(from_param(amount))
+        .expect("balance overflow");
+
     program
         .state()
-        .store(StateKey::Balance(recipient), &(balance + amount))
+        .store(StateKey::Balance(recipient), &balance)
         .expect("failed to store balance");
 
     true
@@ -90,25 +100,31 @@ pub fn transfer(context: Context, sender: Address, recipient: Address, amount: i
     // ensure the sender has adequate balance
     let sender_balance = program
         .state()
-        .get::<i64>(StateKey::Balance(sender))
-        .expect("failed to update balance");
+        .get::<Amount>(StateKey::Balance(sender))
+        .expect("failed to update balance")
+        .expect("sender should have a balance");
 
-    assert!(amount >= 0 && sender_balance >= amount, "invalid input");
+    assert!(amount >= 0, "invalid input");
+    let amount = from_param(amount);
+    let sender_balance = sender_balance.checked_sub(amount).expect("invalid input");
 
     let recipient_balance = program
         .state()
-        .get::<i64>(StateKey::Balance(recipient))
-        .unwrap_or_default();
+        .get::<Amount>(StateKey::Balance(recipient))
+        .expect("failed to get balance")
+        .unwrap_or_default()
+        .checked_add(amount)
+        .expect("balance overflow");
 
     // update balances
     program
         .state()
-        .store(StateKey::Balance(sender), &(sender_balance - amount))
+        .store(StateKey::Balance(sender), &sender_balance)
         .expect("failed to store balance");
 
     program
         .state()
-        .store(StateKey::Balance(recipient), &(recipient_balance + amount))
+        .store(StateKey::Balance(recipient), &recipient_balance)
         .expect("failed to store balance");
 
     true
@@ -137,7 +153,20 @@ pub fn get_balance(context: Context, recipient: Address) -> i64 {
     program
         .state()
         .get(StateKey::Balance(recipient))
-        .unwrap_or_default()
+        .expect("failed to get balance")
+        .map_or(0, into_param)
+}
+
+/// Converts an amount passed by the host into an [Amount].
+fn from_param(amount: i64) -> Amount {
+    u64::try_from(amount)
+        .map(Amount::from)
+        .expect("amount must be non-negative")
+}
+
+/// Converts an [Amount] into a value that can be returned to the host.
+fn into_param(amount: Amount) -> i64 {
+    i64::try_from(amount.get()).expect("amount exceeds the host return type")
 }
 
 #[cfg(test)]
@@ -252,7 +281,7 @@ mod tests {
             max_units: 0,
             params: vec![program_id.into()],
             require: Some(Require {
-                result: ResultAssertion::NumericEq(INITIAL_SUPPLY as u64),
+                result: ResultAssertion::NumericEq(INITIAL_SUPPLY.get() as u64),
             }),
         });
 
@@ -1,10 +1,10 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{quote, quote_spanned};
 use syn::{
-    parse_macro_input, parse_str, spanned::Spanned, Fields, FnArg, Ident, ItemEnum, ItemFn, Pat,
-    PatType, Path, Type, Visibility,
+    parse_macro_input, parse_str, spanned::Spanned, Field, Fields, FnArg, Ident, ItemEnum, ItemFn,
+    ItemStruct, Pat, PatType, Path, ReturnType, Type, Visibility,
 };
 
 const CONTEXT_TYPE: &str = "wasmlanche_sdk::Context";
@@ -14,14 +14,124 @@ const CONTEXT_TYPE: &str = "wasmlanche_sdk::Context";
 /// The wrapper function will have the same name as the original function, but with "_guest" appended to it.
 /// The wrapper functions parameters will be converted to WASM supported types. When called, the wrapper function
 /// calls the original function by converting the parameters back to their intended types using .into().
+/// A function returning a `Result<T, E>` returns a `HostPtr` to its [borsh] serialized result instead,
+/// so the host can tell a revert (variant `0`, followed by `E`) from a success (variant `1`, followed by `T`).
+/// The return type is recognized by its path, which must be `Result`, `core::result::Result` or
+/// `std::result::Result`. A bare `Result` is assumed to be the standard one or an alias of it, such as
+/// `type Result<T> = core::result::Result<T, Error>`; other `Result` types, such as `io::Result`, are not
+/// recognized and must be returned as a WASM type.
+/// Parameters must implement `BorshDeserialize` and the `T` and `E` of a returned `Result` must implement
+/// `BorshSerialize`; a type that doesn't is reported against the type itself.
+///
+///
+/// A function annotated with `#[public(multi_format)]`, which needs the `multi-format` feature of
+/// `wasmlanche-sdk`, lets each caller pick borsh or JSON. Its wrapper takes a single `HostPtr` after
+/// the context, to a tag byte selecting the format followed by every other parameter in that format,
+/// and a returned `Result` is encoded in the same format, after the same tag. See
+/// `wasmlanche_sdk::params::Format` for the tag values. Parameters must then also implement
+/// `serde::Deserialize`, and the `T` and `E` of a returned `Result` `serde::Serialize`.
 #[proc_macro_attribute]
-pub fn public(_: TokenStream, item: TokenStream) -> TokenStream {
+pub fn public(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let multi_format = match multi_format(attr, "public") {
+        Ok(multi_format) => multi_format,
+        Err(err) => return err.to_compile_error().into(),
+    };
     let input = parse_macro_input!(item as ItemFn);
+    entrypoint(input, "public", false, multi_format)
+}
+
+/// Like `#[public]`, but for read-only queries. The function runs with state
+/// marked read-only, so every write or delete it attempts fails with
+/// `StateError::ReadOnly` instead of taking effect. Reads are unaffected.
+/// Like `#[public]`, it takes a `multi_format` argument.
+#[proc_macro_attribute]
+pub fn view(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let multi_format = match multi_format(attr, "view") {
+        Ok(multi_format) => multi_format,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let input = parse_macro_input!(item as ItemFn);
+    entrypoint(input, "view", true, multi_format)
+}
+
+/// Parses the arguments of `#[attr]`, returning whether `multi_format` was
+/// passed, its only argument.
+fn multi_format(args: TokenStream, attr: &str) -> syn::Result<bool> {
+    if args.is_empty() {
+        return Ok(false);
+    }
 
+    let arg = syn::parse::<Ident>(args)?;
+    if arg == "multi_format" {
+        Ok(true)
+    } else {
+        Err(syn::Error::new(
+            arg.span(),
+            format!("Unknown argument `{arg}` of `#[{attr}]`, expected `multi_format`."),
+        ))
+    }
+}
+
+/// Memoizes the result of a function that reads state, so calling it again
+/// in the same invocation returns the value computed the first time instead
+/// of crossing to the host again. The result is recomputed after state is
+/// written, see `wasmlanche_sdk::memo`.
+///
+/// The result is keyed by the function alone, so the function can't take
+/// parameters: it must return the same value for every call in an invocation,
+/// as an accessor of the program's config does. The return type must be `Clone`.
+#[proc_macro_attribute]
+pub fn memoize(_: TokenStream, item: TokenStream) -> TokenStream {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = parse_macro_input!(item as ItemFn);
+
+    if !sig.inputs.is_empty() {
+        return syn::Error::new(
+            sig.inputs.span(),
+            "Functions with the `#[memoize]` attribute can't take parameters, their result is not keyed by them.",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let return_type = match &sig.output {
+        ReturnType::Type(_, ty) => quote! { #ty },
+        ReturnType::Default => {
+            return syn::Error::new(
+                sig.span(),
+                "Functions with the `#[memoize]` attribute must return a value.",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let output = quote! {
+        #(#attrs)*
+        #vis #sig {
+            wasmlanche_sdk::memo::thread_local! {
+                static MEMO: wasmlanche_sdk::memo::Memoized<#return_type> =
+                    const { wasmlanche_sdk::memo::Memoized::new() };
+            }
+
+            MEMO.with(|memo| memo.get_or_init(|| #block))
+        }
+    };
+
+    TokenStream::from(output)
+}
+
+/// Generates the wrapper the host calls for a function annotated with
+/// `#[attr]`, reporting errors against that attribute.
+fn entrypoint(input: ItemFn, attr: &str, read_only: bool, multi_format: bool) -> TokenStream {
     let vis_err = if !matches!(input.vis, Visibility::Public(_)) {
         let err = syn::Error::new(
             input.sig.span(),
-            "Functions with the `#[public]` attribute must have `pub` visibility.",
+            format!("Functions with the `#[{attr}]` attribute must have `pub` visibility."),
         );
 
         Some(err)
@@ -43,19 +153,19 @@ pub fn public(_: TokenStream, item: TokenStream) -> TokenStream {
                 Some(FnArg::Typed(PatType { ty, .. })) => {
                     syn::Error::new(
                         ty.span(),
-                        format!("The first paramter of a function with the `#[public]` attribute must be of type `{CONTEXT_TYPE}`"),
+                        format!("The first paramter of a function with the `#[{attr}]` attribute must be of type `{CONTEXT_TYPE}`"),
                     )
                 }
                 Some(_) => {
                     syn::Error::new(
                         arg.span(),
-                        format!("The first paramter of a function with the `#[public]` attribute must be of type `{CONTEXT_TYPE}`"),
+                        format!("The first paramter of a function with the `#[{attr}]` attribute must be of type `{CONTEXT_TYPE}`"),
                     )
                 }
                 None => {
                     syn::Error::new(
                         input.sig.paren_token.span.join(),
-                        format!("Functions with the `#[public]` attribute must have at least one parameter and the first parameter must be of type `{CONTEXT_TYPE}`"),
+                        format!("Functions with the `#[{attr}]` attribute must have at least one parameter and the first parameter must be of type `{CONTEXT_TYPE}`"),
                     )
                 }
             };
@@ -71,7 +181,7 @@ pub fn public(_: TokenStream, item: TokenStream) -> TokenStream {
         .map(|(i, fn_arg)| match fn_arg {
             FnArg::Receiver(_) => Err(syn::Error::new(
                 fn_arg.span(),
-                "Functions with the `#[public]` attribute cannot have a `self` parameter.",
+                format!("Functions with the `#[{attr}]` attribute cannot have a `self` parameter."),
             )),
             FnArg::Typed(PatType { pat, .. }) => match pat.as_ref() {
                 // TODO:
@@ -80,7 +190,7 @@ pub fn public(_: TokenStream, item: TokenStream) -> TokenStream {
                 // which means we might want wildcards in order to help produce stable APIs
                 Pat::Wild(_) => Err(syn::Error::new(
                     fn_arg.span(),
-                    "Functions with the `#[public]` attribute can only ignore the first parameter.",
+                    format!("Functions with the `#[{attr}]` attribute can only ignore the first parameter."),
                 )),
                 _ => Ok(Ident::new(&format!("param_{i}"), fn_arg.span())),
             },
@@ -115,28 +225,188 @@ pub fn public(_: TokenStream, item: TokenStream) -> TokenStream {
         Err(errors) => return errors.to_compile_error().into(),
     };
 
-    let converted_params = param_names.iter().map(|param_name| {
-        quote! {
-            unsafe {
-                wasmlanche_sdk::from_host_ptr(#param_name).expect("error serializing ptr")
-            }
-        }
+    let param_types = input_args.iter().skip(1).map(|fn_arg| {
+        let FnArg::Typed(PatType { ty, .. }) = fn_arg else {
+            unreachable!("receivers are rejected above");
+        };
+        ty
     });
 
-    let param_types = std::iter::repeat(quote! { i64 }).take(param_names.len());
+    // Extract the original function's return type. This must be a WASM supported type,
+    // unless it is a `Result`, which is returned to the host as a serialized `HostPtr`.
+    let result_type = match &input.sig.output {
+        ReturnType::Type(_, ty) if is_result(&input.sig.output) => Some(ty),
+        _ => None,
+    };
+
+    let (wasm_params, decode_params) = if multi_format {
+        // the tag is only needed again to encode a returned `Result`
+        let format = if result_type.is_some() {
+            quote! { entrypoint_format }
+        } else {
+            quote! { _ }
+        };
+        let decode_params = quote! {
+            let (#format, (#(#param_names,)*)) =
+                <(#(#param_types,)*) as wasmlanche_sdk::params::FormatArgs>::from_tagged_args(tagged_args)
+                    .expect("error serializing ptr");
+        };
+        (quote! { tagged_args: i64 }, decode_params)
+    } else {
+        // each parameter is decoded as its declared type, so a type that cannot
+        // be deserialized is reported against the parameter rather than the wrapper
+        let decode_params = param_names.iter().zip(param_types).map(|(param_name, ty)| {
+            quote_spanned! {ty.span()=>
+                let #param_name = <#ty as wasmlanche_sdk::params::EntrypointArg>::from_arg(#param_name)
+                    .expect("error serializing ptr");
+            }
+        });
+        let wasm_params = param_names
+            .iter()
+            .map(|param_name| quote! { #param_name: i64 });
+        (quote! { #(#wasm_params),* }, quote! { #(#decode_params)* })
+    };
 
-    // Extract the original function's return type. This must be a WASM supported type.
-    let return_type = &input.sig.output;
+    let (return_type, return_value) = if let Some(ty) = result_type {
+        let return_value = if multi_format {
+            quote_spanned! {ty.span()=>
+                <#ty as wasmlanche_sdk::params::EntrypointReturn>::to_tagged_return_ptr(
+                    &result,
+                    entrypoint_format,
+                )
+                .expect("error serializing return value")
+            }
+        } else {
+            quote_spanned! {ty.span()=>
+                <#ty as wasmlanche_sdk::params::EntrypointReturn>::to_return_ptr(&result)
+                    .expect("error serializing return value")
+            }
+        };
+        (quote! { -> wasmlanche_sdk::HostPtr }, return_value)
+    } else {
+        let return_type = &input.sig.output;
+        (quote! { #return_type }, quote! { result })
+    };
+    // the guard is dropped, ending the read-only scope, when the wrapper returns
+    let read_only_guard = read_only.then(|| {
+        quote! { let _read_only = wasmlanche_sdk::state::ReadOnlyGuard::enter(); }
+    });
     let context_type: Path = parse_str(CONTEXT_TYPE).unwrap();
     let output = quote! {
         // Need to include the original function in the output, so contract can call itself
         #input
         #[no_mangle]
-        pub extern "C" fn #new_name(param_0: i64, #(#param_names: #param_types), *) #return_type {
+        pub extern "C" fn #new_name(param_0: i64, #wasm_params) #return_type {
+            // values memoized by a previous call may be stale
+            wasmlanche_sdk::memo::invalidate();
             let param_0: #context_type = unsafe {
                 wasmlanche_sdk::from_host_ptr(param_0).expect("error serializing ptr")
             };
-            #name(param_0, #(#converted_params),*)
+            #read_only_guard
+            #decode_params
+            let result = #name(param_0, #(#param_names),*);
+            wasmlanche_sdk::assert_no_leaks();
+            #return_value
+        }
+    };
+
+    TokenStream::from(output)
+}
+
+/// Turns a struct describing part of a program's state into an accessor of
+/// that state. Each field is stored under its own key, built from the names
+/// of the struct and the field with `wasmlanche_sdk::state::KeyBuilder`, so
+/// that a change to one field only writes that field.
+///
+/// Every field of type `T` becomes a `wasmlanche_sdk::state::StateField<T>`,
+/// and the struct gets a `load(program)` constructor and a `flush` method.
+/// Fields are read the first time they are dereferenced and written back by
+/// `flush`, or when the struct is dropped, which ignores any error:
+///
+/// ```ignore
+/// #[state]
+/// pub struct Config {
+///     pub counter: u64,
+/// }
+///
+/// let mut config = Config::load(program);
+/// *config.counter += 1;
+/// config.flush()?;
+/// ```
+///
+/// Field types must implement `BorshSerialize`, `BorshDeserialize` and
+/// `Default`, which is what an absent field reads as.
+#[proc_macro_attribute]
+pub fn state(_: TokenStream, item: TokenStream) -> TokenStream {
+    let ItemStruct {
+        attrs,
+        vis,
+        struct_token,
+        ident: name,
+        generics,
+        fields,
+        ..
+    } = parse_macro_input!(item as ItemStruct);
+
+    let fields = match fields {
+        Fields::Named(fields) if generics.params.is_empty() => fields.named,
+        _ => {
+            return syn::Error::new(
+                name.span(),
+                "The `#[state]` attribute only supports structs with named fields and no generics.",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let field_defs = fields.iter().map(|field| {
+        let Field {
+            attrs,
+            vis,
+            ident,
+            ty,
+            ..
+        } = field;
+        quote! {
+            #(#attrs)*
+            #vis #ident: wasmlanche_sdk::state::StateField<#ty>
+        }
+    });
+    let field_inits = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let key = ident.as_ref().map(ToString::to_string);
+        quote! {
+            #ident: wasmlanche_sdk::state::StateField::new(
+                program,
+                wasmlanche_sdk::state::field_key(stringify!(#name), #key),
+            )
+        }
+    });
+    let field_idents = fields.iter().map(|field| &field.ident);
+
+    let output = quote! {
+        #(#attrs)*
+        #vis #struct_token #name {
+            #(#field_defs),*
+        }
+
+        impl #name {
+            /// Returns the fields of the struct in the storage of `program`,
+            /// each read when it is first dereferenced.
+            #vis fn load(program: wasmlanche_sdk::Program) -> Self {
+                Self {
+                    #(#field_inits),*
+                }
+            }
+
+            /// Writes back every field that was modified.
+            /// # Errors
+            /// Returns an error if a field cannot be written.
+            #vis fn flush(&mut self) -> ::core::result::Result<(), wasmlanche_sdk::state::Error> {
+                #(self.#field_idents.flush()?;)*
+                Ok(())
+            }
         }
     };
 
@@ -217,6 +487,35 @@ fn generate_to_vec(
         .collect()
 }
 
+/// Returns whether the function returns a `Result`, named by one of the
+/// paths of the standard `Result`. Aliases can't be resolved by a macro, so a
+/// bare `Result` is taken to be the standard one.
+fn is_result(output: &ReturnType) -> bool {
+    let ReturnType::Type(_, ty) = output else {
+        return false;
+    };
+    let Type::Path(type_path) = ty.as_ref() else {
+        return false;
+    };
+    if type_path.qself.is_some() {
+        return false;
+    }
+
+    let segments: Vec<_> = type_path
+        .path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect();
+    match segments.as_slice() {
+        [result] => type_path.path.leading_colon.is_none() && result == "Result",
+        [krate, module, result] => {
+            (krate == "core" || krate == "std") && module == "result" && result == "Result"
+        }
+        _ => false,
+    }
+}
+
 /// Returns whether the type_path represents a Program type.
 fn is_context(type_path: &std::boxed::Box<Type>) -> bool {
     if let Type::Path(type_path) = type_path.as_ref() {
@@ -0,0 +1,22 @@
+use sdk_macros::memoize;
+use std::cell::Cell;
+
+thread_local! {
+    static EVALUATIONS: Cell<u32> = const { Cell::new(0) };
+}
+
+#[memoize]
+fn config() -> (u32, String) {
+    EVALUATIONS.set(EVALUATIONS.get() + 1);
+    (EVALUATIONS.get(), "config".to_owned())
+}
+
+#[test]
+fn result_is_computed_once_until_invalidated() {
+    assert_eq!(config(), (1, "config".to_owned()));
+    assert_eq!(config(), (1, "config".to_owned()));
+
+    wasmlanche_sdk::memo::invalidate();
+    assert_eq!(config(), (2, "config".to_owned()));
+    assert_eq!(EVALUATIONS.get(), 2);
+}
@@ -0,0 +1,29 @@
+use wasmlanche_sdk::{public, Context, HostPtr};
+
+#[public]
+pub fn bare(_: Context) -> Result<u8, u8> {
+    Ok(1)
+}
+
+#[public]
+pub fn core_path(_: Context) -> core::result::Result<u8, u8> {
+    Ok(1)
+}
+
+#[public]
+pub fn std_path(_: Context) -> ::std::result::Result<u8, u8> {
+    Ok(1)
+}
+
+#[public]
+pub fn wasm_type(_: Context) -> i64 {
+    1
+}
+
+#[test]
+fn results_are_returned_as_host_pointers() {
+    let _: extern "C" fn(i64) -> HostPtr = bare_guest;
+    let _: extern "C" fn(i64) -> HostPtr = core_path_guest;
+    let _: extern "C" fn(i64) -> HostPtr = std_path_guest;
+    let _: extern "C" fn(i64) -> i64 = wasm_type_guest;
+}
@@ -0,0 +1,8 @@
+use sdk_macros::memoize;
+
+#[memoize]
+fn balance(account: u64) -> u64 {
+    account
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+use wasmlanche_sdk::{public, Context};
+
+struct Order {
+    amount: u64,
+}
+
+struct Receipt;
+
+#[public]
+pub fn place(_: Context, order: Order) -> Result<Receipt, String> {
+    let _ = order.amount;
+    Ok(Receipt)
+}
+
+fn main() {}
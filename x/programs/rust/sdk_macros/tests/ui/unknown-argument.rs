@@ -0,0 +1,9 @@
+use sdk_macros::public;
+
+#[allow(unused_imports)]
+use wasmlanche_sdk::Context;
+
+#[public(json)]
+pub fn test(_: Context) {}
+
+fn main() {}
@@ -0,0 +1,49 @@
+//! Compares decoding host blocks by copying each into a fresh vec with the
+//! pooled buffers `from_host_ptr` copies into when blocks can't be adopted.
+//!
+//! Run with `cargo bench --bench pooled_decode --features deterministic-alloc`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+use wasmlanche_sdk::{alloc, from_host_ptr, into_boxed_bytes};
+
+const VALUES: usize = 10_000;
+
+/// Copies the serialized `value` into a newly allocated block, the way the
+/// host returns one, and returns its pointer.
+fn host_block(value: u64) -> i64 {
+    let bytes = borsh::to_vec(&value).unwrap();
+    let ptr = alloc(bytes.len());
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+    ptr as i64
+}
+
+fn fresh_vec() -> Duration {
+    let blocks: Vec<_> = (0..VALUES as u64).map(host_block).collect();
+    let start = Instant::now();
+    for ptr in blocks {
+        let bytes = into_boxed_bytes(ptr).unwrap();
+        black_box(borsh::from_slice::<u64>(&bytes).unwrap());
+    }
+    start.elapsed()
+}
+
+fn pooled() -> Duration {
+    let blocks: Vec<_> = (0..VALUES as u64).map(host_block).collect();
+    let start = Instant::now();
+    for ptr in blocks {
+        black_box(from_host_ptr::<u64>(ptr).unwrap());
+    }
+    start.elapsed()
+}
+
+fn main() {
+    // warm up the allocator and the pool before measuring
+    fresh_vec();
+    pooled();
+
+    println!("{VALUES} values, fresh vec: {:?}", fresh_vec());
+    println!("{VALUES} values, pooled:    {:?}", pooled());
+}
@@ -0,0 +1,52 @@
+//! Compares reading host blocks into a fresh vec on every call with reading
+//! them into one reused buffer through `read_into`.
+//!
+//! Run with `cargo bench --bench read_into`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+use wasmlanche_sdk::{alloc, read_into};
+
+const RECORDS: usize = 10_000;
+const RECORD: &[u8] = &[7; 48];
+
+/// Copies a record into a newly allocated block, the way the host returns
+/// one, and returns its pointer.
+fn host_block() -> i64 {
+    let ptr = alloc(RECORD.len());
+    unsafe { std::ptr::copy_nonoverlapping(RECORD.as_ptr(), ptr, RECORD.len()) };
+    ptr as i64
+}
+
+fn fresh_vec() -> Duration {
+    let blocks: Vec<_> = (0..RECORDS).map(|_| host_block()).collect();
+    let start = Instant::now();
+    for ptr in blocks {
+        let mut record = Vec::new();
+        read_into(ptr, &mut record).unwrap();
+        black_box(record);
+    }
+    start.elapsed()
+}
+
+fn reused_buffer() -> Duration {
+    let blocks: Vec<_> = (0..RECORDS).map(|_| host_block()).collect();
+    let mut buffer = Vec::new();
+    let start = Instant::now();
+    for ptr in blocks {
+        read_into(ptr, &mut buffer).unwrap();
+        black_box(buffer.as_slice());
+    }
+    start.elapsed()
+}
+
+fn main() {
+    // warm up the allocator and caches before measuring
+    fresh_vec();
+    reused_buffer();
+
+    println!("{RECORDS} records, fresh vec:     {:?}", fresh_vec());
+    println!("{RECORDS} records, reused buffer: {:?}", reused_buffer());
+}
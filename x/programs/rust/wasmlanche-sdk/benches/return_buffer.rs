@@ -0,0 +1,51 @@
+//! Compares returning a small value through `into_return_ptr`, which
+//! serializes it into a per-thread scratch buffer reused by every call and
+//! copies it into a block of its exact length, with serializing it into a
+//! fresh block through a `HostWriter`, which grows the block and trims it on
+//! every call. Both run 10k times.
+//!
+//! Native addresses don't fit a packed pointer, so each block is freed where
+//! it would be handed over, as the host frees it with `dealloc` once read.
+//!
+//! Run with `cargo bench --bench return_buffer`.
+
+use borsh::BorshSerialize;
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+use wasmlanche_sdk::{into_return_ptr, HostWriter};
+
+const CALLS: usize = 10_000;
+
+/// The return value of a typical `#[public]` function.
+fn value(i: usize) -> Result<u64, u8> {
+    Ok(i as u64)
+}
+
+fn fresh_block() -> Duration {
+    let start = Instant::now();
+    for i in 0..CALLS {
+        let mut writer = HostWriter::new();
+        value(i).serialize(&mut writer).unwrap();
+        let _ = black_box(writer.finish());
+    }
+    start.elapsed()
+}
+
+fn reused_buffer() -> Duration {
+    let start = Instant::now();
+    for i in 0..CALLS {
+        let _ = black_box(into_return_ptr(&value(i)));
+    }
+    start.elapsed()
+}
+
+fn main() {
+    // warm up the allocator and caches before measuring
+    fresh_block();
+    reused_buffer();
+
+    println!("{CALLS} calls, fresh block:   {:?}", fresh_block());
+    println!("{CALLS} calls, reused buffer: {:?}", reused_buffer());
+}
@@ -0,0 +1,132 @@
+//! Arithmetic over unsigned integers too large for a machine word, for
+//! programs that verify signatures or proofs of their own.
+//!
+//! Integers are passed as big-endian bytes of any length, leading zeros
+//! included, and the work is done by the host, which is both faster than
+//! arithmetic in WASM and the same on every node.
+
+use crate::{host::decode_result, memory::read_into, metrics, state::Error as StateError};
+
+/// Returns `base` raised to the power `exp`, modulo `modulus`, as big-endian
+/// bytes of the same length as `modulus`. An empty `exp` is zero, so the
+/// result is one, unless `modulus` is one.
+/// # Errors
+/// Returns [`StateError::DivisionByZero`] if `modulus` is zero,
+/// [`StateError::HostError`] if the host fails to compute the result, or a
+/// [`StateError`] if the arguments cannot be passed to the host or the result
+/// it returns is not as long as `modulus`.
+pub fn mod_exp(base: &[u8], exp: &[u8], modulus: &[u8]) -> Result<Vec<u8>, StateError> {
+    if modulus.iter().all(|byte| *byte == 0) {
+        return Err(StateError::DivisionByZero);
+    }
+
+    let len = base.len() + exp.len() + modulus.len();
+    let ptr = metrics::record("bigint.mod_exp", len, || host::mod_exp(base, exp, modulus))?;
+    let ptr = decode_result(ptr)?;
+    let mut result = Vec::new();
+    read_into(ptr, &mut result)?;
+
+    if result.len() != modulus.len() {
+        return Err(StateError::InvalidByteLength {
+            expected: modulus.len(),
+            got: result.len(),
+        });
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+use crate::mock::bigint as host;
+
+#[cfg(not(test))]
+mod host {
+    use crate::{memory::to_host_ptr, state::Error};
+
+    #[link(wasm_import_module = "bigint")]
+    extern "C" {
+        #[link_name = "mod_exp"]
+        fn _mod_exp(base: i64, exp: i64, modulus: i64) -> i64;
+    }
+
+    /// Returns a pointer to the result, or a negative value if the host fails
+    /// to compute it.
+    pub(super) fn mod_exp(base: &[u8], exp: &[u8], modulus: &[u8]) -> Result<i64, Error> {
+        let base = to_host_ptr(base)?;
+        let exp = to_host_ptr(exp)?;
+        let modulus = to_host_ptr(modulus)?;
+
+        Ok(unsafe { _mod_exp(base, exp, modulus) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mod_exp;
+    use crate::{memory::tracked_blocks, mock, state::Error as StateError};
+
+    /// Decodes a big-endian hex string, padded with zeros to `len` bytes.
+    fn hex(digits: &str, len: usize) -> Vec<u8> {
+        let digits = format!("{digits:0>width$}", width = len * 2);
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn small_vectors() {
+        assert_eq!(mod_exp(&[4], &[13], &[0x01, 0xf1]).unwrap(), [0x01, 0xbd]);
+        // the toy RSA key n = 61 * 53, e = 17, d = 2753
+        let n = 3233_u16.to_be_bytes();
+        let ciphertext = mod_exp(&[65], &[17], &n).unwrap();
+        assert_eq!(ciphertext, 2790_u16.to_be_bytes());
+        assert_eq!(
+            mod_exp(&ciphertext, &2753_u16.to_be_bytes(), &n).unwrap(),
+            [0, 65]
+        );
+        assert_eq!(tracked_blocks(), 0);
+        assert_eq!(mock::with(|host| host.calls("bigint.mod_exp")), 3);
+    }
+
+    #[test]
+    fn large_vectors() {
+        // Fermat's little theorem for the prime 2^255 - 19
+        let p = hex(
+            "7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffed",
+            32,
+        );
+        let p_minus_one = hex(
+            "7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffec",
+            32,
+        );
+        assert_eq!(mod_exp(&[2], &p_minus_one, &p).unwrap(), hex("1", 32));
+
+        // 7^(2^200 + 12345) mod 2^256 - 189
+        let modulus = hex(
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff43",
+            32,
+        );
+        let exp = hex("100000000000000000000000000000000000000000000003039", 26);
+        assert_eq!(
+            mod_exp(&[7], &exp, &modulus).unwrap(),
+            hex(
+                "397befa71cad4c7bf27e57e1d2ef88cfac9cb64144177a695e729d4662f86e3",
+                32
+            )
+        );
+    }
+
+    #[test]
+    fn edge_cases() {
+        // leading zeros are ignored, but the result is as long as the modulus
+        assert_eq!(mod_exp(&[0, 0, 3], &[0, 2], &[0, 0, 7]).unwrap(), [0, 0, 2]);
+        assert_eq!(mod_exp(&[3], &[], &[7]).unwrap(), [1]);
+        assert_eq!(mod_exp(&[3], &[], &[1]).unwrap(), [0]);
+        assert!(matches!(
+            mod_exp(&[3], &[2], &[0, 0]),
+            Err(StateError::DivisionByZero)
+        ));
+        assert_eq!(mock::with(|host| host.calls("bigint.mod_exp")), 3);
+    }
+}
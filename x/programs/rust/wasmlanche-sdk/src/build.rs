@@ -47,12 +47,12 @@ pub fn build_wasm_on_test() {
         let target_dir = match target_dir.canonicalize() {
             Ok(target_dir) => target_dir,
             err @ Err(_) => {
-                println!("cargo:warning= not found -> {target_dir:?}");
+                println!("cargo:warning= not found -> {}", target_dir.display());
                 err.expect("failed to canonicalize wasm file path")
             }
         };
 
-        println!("cargo:warning=`.wasm` file at {target_dir:?}");
+        println!("cargo:warning=`.wasm` file at {}", target_dir.display());
 
         let target_dir = target_dir
             .to_str()
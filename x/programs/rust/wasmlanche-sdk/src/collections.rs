@@ -0,0 +1,362 @@
+//! Typed collections persisted in the host storage.
+
+use crate::{
+    program::Program,
+    state::{Error, Key, KeyBuilder, State, StateNamespace},
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    marker::PhantomData,
+};
+
+/// A map of `K` to `V` stored under its own name in the program's storage.
+/// Each entry is stored at the key built from the map's name followed by the
+/// entry's key, so maps with different names can never overwrite each other.
+///
+/// Reads and writes go through a [State], so writes are flushed to the host
+/// when the map is dropped.
+pub struct Map<K, V> {
+    /// Prepended to every key, empty unless the map is part of a namespace.
+    prefix: Key,
+    name: &'static str,
+    state: State<Key>,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> Map<K, V>
+where
+    K: BorshSerialize,
+    V: BorshSerialize + BorshDeserialize,
+{
+    #[must_use]
+    pub fn new(program: Program, name: &'static str) -> Self {
+        Self {
+            prefix: Key::default(),
+            name,
+            state: State::new(program),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the map named `name` within `namespace`, isolated from maps of
+    /// the same name in other namespaces.
+    #[must_use]
+    pub fn in_namespace<N>(namespace: &StateNamespace<N>, name: &'static str) -> Self {
+        Self {
+            prefix: namespace.prefix().clone(),
+            name,
+            state: State::new(namespace.program()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the value stored for `key`, or `None` if it is absent.
+    /// # Errors
+    /// Returns an [Error] if the key cannot be serialized or the host fails
+    /// to read the value.
+    pub fn get(&mut self, key: &K) -> Result<Option<V>, Error> {
+        let key = self.storage_key(key)?;
+        self.state.get(key)
+    }
+
+    /// Stores `value` for `key`, overwriting any previous value.
+    /// # Errors
+    /// Returns an [Error] if the key or value cannot be serialized.
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<(), Error> {
+        let key = self.storage_key(key)?;
+        self.state.store(key, value)
+    }
+
+    /// Deletes the value stored for `key`.
+    /// # Errors
+    /// Returns an [Error] if the key cannot be serialized or the host fails
+    /// to delete the value.
+    pub fn remove(&mut self, key: &K) -> Result<(), Error> {
+        let key = self.storage_key(key)?;
+        self.state.delete(key)
+    }
+
+    fn storage_key(&self, key: &K) -> Result<Key, Error> {
+        Ok(KeyBuilder::from(self.prefix.clone())
+            .with(&self.name)?
+            .with(key)?
+            .build())
+    }
+
+    /// Returns the bytes the storage key of every entry starts with.
+    #[cfg(feature = "unstable")]
+    fn entries_prefix(&self) -> Result<Key, Error> {
+        Ok(KeyBuilder::from(self.prefix.clone())
+            .with(&self.name)?
+            .build())
+    }
+}
+
+impl<K, V> Map<K, V>
+where
+    K: BorshSerialize + BorshDeserialize,
+    V: BorshSerialize + BorshDeserialize,
+{
+    /// Iterates over every entry of the map, including writes not yet sent
+    /// to the host, fetching them lazily like [`State::scan_raw`].
+    ///
+    /// Entries are visited in ascending order of their storage keys, which
+    /// the host guarantees for every scan, so every node iterating the same
+    /// map sees the same sequence regardless of the order the entries were
+    /// inserted in. Within a map, storage keys only differ by the [borsh]
+    /// encoding of the entry's key, prefixed with its length: entries are
+    /// ordered by the length of their encoded key, then lexicographically by
+    /// its bytes. For fixed-size keys, such as integers or addresses, that is
+    /// the lexicographic order of the encoded keys.
+    ///
+    /// Aggregating over the map in this order, for example to sum balances,
+    /// therefore yields the same result on every node.
+    /// # Errors
+    /// Returns an [Error] if the first page of entries cannot be fetched.
+    /// Errors fetching a later page, or decoding an entry, are yielded by the
+    /// iterator.
+    // fallible since the first page is fetched before the iterator is returned
+    #[cfg(feature = "unstable")]
+    #[allow(clippy::iter_not_returning_iterator)]
+    pub fn iter(&mut self) -> Result<impl Iterator<Item = Result<(K, V), Error>> + '_, Error> {
+        let prefix = self.entries_prefix()?;
+        let entries = self.state.scan_raw(&prefix)?;
+
+        Ok(entries.map(move |entry| {
+            let (key, value) = entry?;
+            let corrupt = |source| Error::corrupt_state(key.clone(), source);
+            // the length of the encoded key precedes it
+            let encoded = key
+                .get(prefix.len() + 4..)
+                .ok_or_else(|| corrupt(Error::InvalidBytes))?;
+            let entry_key =
+                borsh::from_slice(encoded).map_err(|err| corrupt(Error::from_borsh(&err)))?;
+            let value =
+                borsh::from_slice(&value).map_err(|err| corrupt(Error::from_borsh(&err)))?;
+            Ok((entry_key, value))
+        }))
+    }
+}
+
+/// An in-memory map whose iteration order and encoding only depend on its
+/// contents. Unlike a [`HashMap`](std::collections::HashMap), whose order
+/// changes with its random seed, entries are always visited and serialized in
+/// ascending key order, so every validator building the same map sees the same
+/// order and produces the same bytes.
+///
+/// Decoding only accepts keys in strictly ascending order, so each map has a
+/// single encoding: bytes with duplicate or unsorted keys are rejected rather
+/// than collapsed into the same map.
+#[derive(Clone, Debug, PartialEq, Eq, BorshSerialize)]
+pub struct DeterministicMap<K, V>(BTreeMap<K, V>);
+
+impl<K, V> BorshDeserialize for DeterministicMap<K, V>
+where
+    K: BorshDeserialize + Ord,
+    V: BorshDeserialize,
+{
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let entries = Vec::<(K, V)>::deserialize_reader(reader)?;
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "map keys are not in strictly ascending order",
+            ));
+        }
+        Ok(Self(entries.into_iter().collect()))
+    }
+}
+
+impl<K, V> Default for DeterministicMap<K, V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<K, V> DeterministicMap<K, V>
+where
+    K: Ord,
+{
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts `value` for `key` and returns the value it replaced, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.0.insert(key, value)
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.0.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.0.get_mut(key)
+    }
+
+    /// Removes the value for `key` and returns it, if any.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.0.remove(key)
+    }
+
+    #[must_use]
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.0.contains_key(key)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the entries in ascending key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.0.iter()
+    }
+
+    /// Returns the keys in ascending order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.0.keys()
+    }
+
+    /// Returns the values in ascending key order.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.0.values()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for DeterministicMap<K, V>
+where
+    K: Ord,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeterministicMap, Map};
+    use crate::{
+        program::Program,
+        state::{Key, StateNamespace},
+    };
+
+    fn program() -> Program {
+        Program::new([1; Program::LEN])
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut balances = Map::<u8, u64>::new(program(), "balances");
+        balances.insert(&1, &100).unwrap();
+        balances.insert(&2, &200).unwrap();
+        drop(balances);
+
+        let mut balances = Map::<u8, u64>::new(program(), "balances");
+        assert_eq!(balances.get(&1).unwrap(), Some(100));
+        assert_eq!(balances.get(&2).unwrap(), Some(200));
+        assert_eq!(balances.get(&3).unwrap(), None);
+
+        balances.remove(&1).unwrap();
+        assert_eq!(balances.get(&1).unwrap(), None);
+    }
+
+    #[test]
+    fn maps_with_different_names_are_isolated() {
+        let mut balances = Map::<u8, u64>::new(program(), "balances");
+        let mut allowances = Map::<u8, u64>::new(program(), "allowances");
+        balances.insert(&1, &100).unwrap();
+        allowances.insert(&1, &5).unwrap();
+        drop((balances, allowances));
+
+        let mut balances = Map::<u8, u64>::new(program(), "balances");
+        assert_eq!(balances.get(&1).unwrap(), Some(100));
+    }
+
+    #[test]
+    fn maps_in_different_namespaces_are_isolated() {
+        let auth = StateNamespace::<Key>::new(program(), "auth").unwrap();
+        let vault = StateNamespace::<Key>::new(program(), "vault").unwrap();
+        let mut admins = Map::<u8, bool>::in_namespace(&auth, "admins");
+        let mut vault_admins = Map::<u8, bool>::in_namespace(&vault, "admins");
+        admins.insert(&1, &true).unwrap();
+        vault_admins.insert(&1, &false).unwrap();
+        drop((admins, vault_admins));
+
+        let mut admins = Map::<u8, bool>::in_namespace(&auth, "admins");
+        assert_eq!(admins.get(&1).unwrap(), Some(true));
+        let mut unscoped = Map::<u8, bool>::new(program(), "admins");
+        assert_eq!(unscoped.get(&1).unwrap(), None);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn iteration_order_ignores_insertion_order() {
+        let keys: [u32; 6] = [7, 0x0100, 3, u32::MAX, 0, 42];
+        // each thread runs against its own mock host
+        let iterate = |keys: Vec<u32>| {
+            std::thread::spawn(move || {
+                let mut balances = Map::<u32, u64>::new(program(), "balances");
+                for key in keys {
+                    balances.insert(&key, &(u64::from(key) * 2)).unwrap();
+                }
+                let mut others = Map::<u32, u64>::new(program(), "others");
+                others.insert(&1, &1).unwrap();
+                drop(others);
+                balances
+                    .iter()
+                    .unwrap()
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap()
+            })
+            .join()
+            .unwrap()
+        };
+
+        let forward = iterate(keys.to_vec());
+        let backward = iterate(keys.iter().rev().copied().collect());
+        let mut shuffled = keys.to_vec();
+        shuffled.rotate_left(2);
+
+        assert_eq!(forward, backward);
+        assert_eq!(forward, iterate(shuffled));
+        // little-endian keys compare by their low byte first
+        let mut expected: Vec<_> = keys.iter().map(|key| (*key, u64::from(*key) * 2)).collect();
+        expected.sort_by_key(|(key, _)| key.to_le_bytes());
+        assert_eq!(forward, expected);
+    }
+
+    #[test]
+    fn deterministic_map_encoding_ignores_insertion_order() {
+        let entries = [(3_u8, 30_u64), (1, 10), (2, 20)];
+        let forward: DeterministicMap<_, _> = entries.into_iter().collect();
+        let backward: DeterministicMap<_, _> = entries.into_iter().rev().collect();
+
+        let bytes = borsh::to_vec(&forward).unwrap();
+        assert_eq!(bytes, borsh::to_vec(&backward).unwrap());
+        assert_eq!(forward.keys().copied().collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(
+            borsh::from_slice::<DeterministicMap<u8, u64>>(&bytes).unwrap(),
+            forward
+        );
+    }
+
+    #[test]
+    fn deterministic_map_rejects_unsorted_or_duplicate_keys() {
+        for entries in [vec![(2_u8, 20_u64), (1, 10)], vec![(1, 10), (1, 11)]] {
+            let bytes = borsh::to_vec(&entries).unwrap();
+            let err = borsh::from_slice::<DeterministicMap<u8, u64>>(&bytes).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+}
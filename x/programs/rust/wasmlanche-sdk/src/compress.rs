@@ -0,0 +1,155 @@
+//! A small LZ77 compressor for values kept in state. It is implemented here,
+//! rather than taken from a general purpose library, so that the output is
+//! fully specified by this file: every node must produce the same bytes for
+//! the same input, since the compressed bytes are what consensus stores.
+//!
+//! The compressed stream is a sequence of tokens. A tag byte below `0x80`
+//! starts a run of `tag + 1` literal bytes, which follow it. A tag byte of
+//! `0x80` or more is a match of `tag - 0x80 + MIN_MATCH` bytes copied from
+//! earlier output, at the distance given by the little-endian `u16` that
+//! follows it.
+
+use crate::state::Error as StateError;
+
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+const MAX_LITERALS: usize = 0x80;
+const MAX_DISTANCE: usize = u16::MAX as usize;
+const TABLE_BITS: u32 = 12;
+
+/// Compresses `input`. Matches are found greedily with a table of the last
+/// position of each hashed 4-byte window, which depends only on the input.
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2);
+    let mut table = vec![usize::MAX; 1 << TABLE_BITS];
+    let mut literals = 0;
+    let mut pos = 0;
+
+    while pos + MIN_MATCH <= input.len() {
+        let slot = &mut table[hash(&input[pos..pos + MIN_MATCH])];
+        let candidate = std::mem::replace(slot, pos);
+
+        let len = if candidate != usize::MAX && pos - candidate <= MAX_DISTANCE {
+            input[candidate..]
+                .iter()
+                .zip(&input[pos..])
+                .take(MAX_MATCH)
+                .take_while(|(a, b)| a == b)
+                .count()
+        } else {
+            0
+        };
+
+        if len < MIN_MATCH {
+            literals += 1;
+            pos += 1;
+            continue;
+        }
+
+        flush_literals(&mut output, &input[pos - literals..pos]);
+        literals = 0;
+        let distance = u16::try_from(pos - candidate).expect("distance is bounded");
+        let tag = u8::try_from(0x80 + len - MIN_MATCH).expect("length is bounded");
+        output.push(tag);
+        output.extend(distance.to_le_bytes());
+        pos += len;
+    }
+
+    flush_literals(&mut output, &input[pos - literals..]);
+    output
+}
+
+/// Reverses [`compress`].
+/// # Errors
+/// Returns [`StateError::InvalidEncoding`] if `input` is not a valid stream.
+pub(crate) fn decompress(mut input: &[u8]) -> Result<Vec<u8>, StateError> {
+    let mut output = Vec::with_capacity(input.len() * 2);
+
+    while let Some((&tag, rest)) = input.split_first() {
+        if tag < 0x80 {
+            let len = usize::from(tag) + 1;
+            let literals = rest.get(..len).ok_or(StateError::InvalidEncoding)?;
+            output.extend_from_slice(literals);
+            input = &rest[len..];
+        } else {
+            let (distance, rest) = rest
+                .split_first_chunk::<2>()
+                .ok_or(StateError::InvalidEncoding)?;
+            let distance = usize::from(u16::from_le_bytes(*distance));
+            if distance == 0 || distance > output.len() {
+                return Err(StateError::InvalidEncoding);
+            }
+
+            // byte by byte, since a match may overlap the bytes it produces
+            let start = output.len() - distance;
+            for i in 0..usize::from(tag - 0x80) + MIN_MATCH {
+                output.push(output[start + i]);
+            }
+            input = rest;
+        }
+    }
+
+    Ok(output)
+}
+
+fn flush_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for run in literals.chunks(MAX_LITERALS) {
+        output.push(u8::try_from(run.len() - 1).expect("runs are at most 128 bytes"));
+        output.extend_from_slice(run);
+    }
+}
+
+fn hash(window: &[u8]) -> usize {
+    let window = u32::from_le_bytes(window.try_into().expect("windows are 4 bytes"));
+    (window.wrapping_mul(0x9e37_79b1) >> (32 - TABLE_BITS)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress};
+    use crate::state::Error as StateError;
+
+    #[test]
+    fn round_trip() {
+        let inputs: [&[u8]; 5] = [
+            b"",
+            b"abc",
+            &[7; 1_000],
+            b"{\"fee\":30,\"owner\":\"alice\"},{\"fee\":30,\"owner\":\"bob\"}",
+            &(0..=255).collect::<Vec<u8>>(),
+        ];
+
+        for input in inputs {
+            assert_eq!(decompress(&compress(input)).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn repetitive_input_shrinks() {
+        let input = b"{\"balance\":100}".repeat(100);
+        assert!(compress(&input).len() < input.len() / 10);
+    }
+
+    #[test]
+    fn output_is_stable() {
+        // changing this output changes what is stored on chain
+        assert_eq!(
+            compress(b"abcabcabcabc"),
+            [0x02, b'a', b'b', b'c', 0x85, 0x03, 0x00]
+        );
+    }
+
+    #[test]
+    fn malformed_stream_is_rejected() {
+        for malformed in [
+            &[0x03, b'a'][..],
+            &[0x80, 0x01],
+            &[0x00, b'a', 0x80, 0x02, 0x00],
+        ] {
+            assert!(matches!(
+                decompress(malformed),
+                Err(StateError::InvalidEncoding)
+            ));
+        }
+    }
+}
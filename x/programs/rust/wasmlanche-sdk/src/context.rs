@@ -0,0 +1,412 @@
+use crate::program::Program;
+#[cfg(feature = "unstable")]
+use crate::{
+    host::{decode_optional, decode_result},
+    memory::from_host_ptr,
+    metrics,
+    state::{Error as StateError, Key},
+    types::Address,
+};
+
+/// The number of most recent blocks whose hashes the host keeps, including the
+/// block the current call is executed in.
+pub const BLOCK_HASH_WINDOW: u64 = 256;
+
+/// The deepest [call depth](Context::call_depth) the host runs a program at.
+/// A cross-program call made at this depth fails without running the callee.
+pub const MAX_CALL_DEPTH: u32 = 64;
+
+/// The context of the current invocation, passed by the host as the first
+/// parameter of every `#[public]` function. Its methods read the invocation
+/// from host functions that need the `unstable` feature.
+#[derive(Clone, Copy, borsh::BorshSerialize, borsh::BorshDeserialize)]
+pub struct Context {
+    pub program: Program,
+}
+
+#[cfg(feature = "unstable")]
+impl Context {
+    /// Returns the amount of native tokens attached to the current call.
+    ///
+    /// Functions that do not accept payments should assert that the value is
+    /// zero, otherwise the tokens sent with the call are kept by the program.
+    #[must_use]
+    pub fn value(&self) -> u64 {
+        metrics::record("context.value", 0, host::value)
+    }
+
+    /// Returns the address of the account or program that made the current
+    /// call.
+    /// # Panics
+    /// Panics if the host does not return a valid address.
+    #[must_use]
+    pub fn caller(&self) -> Address {
+        decode_result(metrics::record("context.caller", 0, host::caller))
+            .and_then(from_host_ptr)
+            .expect("the host returns the caller address")
+    }
+
+    /// Returns `true` if the current call was made by the program itself, as
+    /// opposed to an external account or another program.
+    ///
+    /// A public function that is only meant to be reached through the program
+    /// calling itself, such as a callback, should return early or panic when
+    /// this is `false`, before it reads its arguments or touches state.
+    #[must_use]
+    pub fn caller_is_self(&self) -> bool {
+        self.caller().as_bytes() == self.program.id()
+    }
+
+    /// Traps unless the current call was made by the owner of the program.
+    ///
+    /// The owner is the [`Address`] stored in the program's state under `key`.
+    /// The SDK never writes it: a program configures its owner by storing the
+    /// address under a key of its choosing, typically from its init function,
+    /// and passes that same key here. Call this first thing in the guarded
+    /// function, before it reads its arguments or touches state.
+    /// # Panics
+    /// Panics if no owner is stored under `key`, if the stored value is not an
+    /// [`Address`], or if the caller is not the owner.
+    pub fn require_owner(&self, key: &[u8]) {
+        let owner = self
+            .program
+            .state::<Key>()
+            .get::<Address>(Key::new(key.to_vec()))
+            .expect("the owner is a valid address")
+            .expect("the program has an owner");
+
+        assert!(
+            owner.ct_eq(&self.caller()),
+            "only the owner of the program may call this function"
+        );
+    }
+
+    /// Returns the number of cross-program calls the current call is nested
+    /// in: zero when the program was called by a transaction, one when it was
+    /// called by a program called by a transaction, and so on.
+    ///
+    /// The host stops a chain of calls at [`MAX_CALL_DEPTH`], so a program
+    /// that calls back into its callers only needs a lower bound of its own
+    /// to fail early and with a clearer error than the host's.
+    #[must_use]
+    pub fn call_depth(&self) -> u32 {
+        metrics::record("context.call_depth", 0, host::call_depth)
+    }
+
+    /// Returns the id of the chain the program is deployed on.
+    ///
+    /// The id is constant for the lifetime of the network, and differs
+    /// between networks, so a program that verifies signed messages should
+    /// include it in the signed payload: a signature over a payload bound to
+    /// one chain can't be replayed on another.
+    /// # Panics
+    /// Panics if the host does not return a valid chain id.
+    #[must_use]
+    pub fn chain_id(&self) -> [u8; 32] {
+        decode_result(metrics::record("context.chain_id", 0, host::chain_id))
+            .and_then(from_host_ptr)
+            .expect("the host returns the chain id")
+    }
+
+    /// Returns the number of bytes the current transaction may still write to
+    /// storage. Writes are only sent to the host when a [State] is flushed, so
+    /// values stored in a [State] that has not been dropped yet are not
+    /// accounted for.
+    ///
+    /// A program that batches writes can check this before each one and stop,
+    /// or fail with an error of its own, instead of trapping when the host
+    /// runs out of quota mid-write.
+    ///
+    /// [State]: crate::state::State
+    #[must_use]
+    pub fn remaining_storage_quota(&self) -> u64 {
+        metrics::record(
+            "context.remaining_storage_quota",
+            0,
+            host::remaining_storage_quota,
+        )
+    }
+
+    /// Returns the native token balance of the program, including the
+    /// [value](Context::value) attached to the current call, which is credited
+    /// before the call starts.
+    #[must_use]
+    pub fn self_balance(&self) -> u64 {
+        metrics::record("context.balance", Program::LEN, || {
+            host::balance(&self.program)
+        })
+    }
+
+    /// Returns the hash of the block at `height`, or `None` if the block is not
+    /// one of the last [`BLOCK_HASH_WINDOW`] blocks.
+    ///
+    /// Block hashes are a weak source of randomness on their own: whoever
+    /// builds a block has some say over its hash. Combine them with a
+    /// commit-reveal scheme rather than relying on them alone.
+    /// # Errors
+    /// Returns a [`StateError`] if the hash returned by the host is malformed.
+    pub fn block_hash(&self, height: u64) -> Result<Option<[u8; 32]>, StateError> {
+        // the host has nothing to return for a block outside of the window
+        let hash = metrics::record("context.block_hash", 8, || host::block_hash(height));
+        decode_optional(hash)?.map(from_host_ptr).transpose()
+    }
+
+    /// Returns the timestamp of the block the current call is executed in, in
+    /// seconds since the Unix epoch.
+    #[must_use]
+    pub fn timestamp(&self) -> u64 {
+        timestamp()
+    }
+}
+
+/// Returns the timestamp of the current block, for callers that don't have a
+/// [Context] at hand.
+#[cfg(feature = "unstable")]
+pub(crate) fn timestamp() -> u64 {
+    metrics::record("context.timestamp", 0, host::timestamp)
+}
+
+#[cfg(all(test, feature = "unstable"))]
+use crate::mock::context as host;
+
+#[cfg(all(not(test), feature = "unstable"))]
+mod host {
+    use crate::{memory::to_host_ptr, program::Program};
+
+    #[link(wasm_import_module = "context")]
+    extern "C" {
+        #[link_name = "value"]
+        fn _value() -> i64;
+
+        #[link_name = "timestamp"]
+        fn _timestamp() -> i64;
+
+        #[link_name = "balance"]
+        fn _balance(program: i64) -> i64;
+
+        #[link_name = "block_hash"]
+        fn _block_hash(height: i64) -> i64;
+
+        #[link_name = "caller"]
+        fn _caller() -> i64;
+
+        #[link_name = "chain_id"]
+        fn _chain_id() -> i64;
+
+        #[link_name = "remaining_storage_quota"]
+        fn _remaining_storage_quota() -> i64;
+
+        #[link_name = "call_depth"]
+        fn _call_depth() -> i64;
+    }
+
+    /// Returns the number of calls the current one is nested in.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // bounded by `MAX_CALL_DEPTH`
+    pub(super) fn call_depth() -> u32 {
+        unsafe { _call_depth() as u32 }
+    }
+
+    /// Returns the native amount attached to the call.
+    #[allow(clippy::cast_sign_loss)] // the host returns the `u64` bit pattern
+    pub(super) fn value() -> u64 {
+        unsafe { _value() as u64 }
+    }
+
+    /// Returns the native balance of `program`.
+    #[allow(clippy::cast_sign_loss)] // the host returns the `u64` bit pattern
+    pub(super) fn balance(program: &Program) -> u64 {
+        // ids are a fixed 32 bytes in program memory, so they always fit
+        let program = to_host_ptr(program.id()).expect("program id fits a host pointer");
+        unsafe { _balance(program) as u64 }
+    }
+
+    /// Returns the number of bytes the transaction may still write.
+    #[allow(clippy::cast_sign_loss)] // the host returns the `u64` bit pattern
+    pub(super) fn remaining_storage_quota() -> u64 {
+        unsafe { _remaining_storage_quota() as u64 }
+    }
+
+    /// Returns a pointer to the address of the caller.
+    pub(super) fn caller() -> i64 {
+        unsafe { _caller() }
+    }
+
+    /// Returns a pointer to the id of the chain.
+    pub(super) fn chain_id() -> i64 {
+        unsafe { _chain_id() }
+    }
+
+    /// Returns a pointer to the hash of the block at `height`, or -1.
+    #[allow(clippy::cast_possible_wrap)] // the host reads back the `u64` bit pattern
+    pub(super) fn block_hash(height: u64) -> i64 {
+        unsafe { _block_hash(height as i64) }
+    }
+
+    /// Returns the timestamp of the current block.
+    #[allow(clippy::cast_sign_loss)] // the host returns the `u64` bit pattern
+    pub(super) fn timestamp() -> u64 {
+        unsafe { _timestamp() as u64 }
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod tests {
+    use super::{Context, BLOCK_HASH_WINDOW, MAX_CALL_DEPTH};
+    use crate::{mock, program::Program, state::Key, types::Address};
+
+    fn context() -> Context {
+        Context {
+            program: Program::new([1; Program::LEN]),
+        }
+    }
+
+    #[test]
+    fn value_defaults_to_zero() {
+        assert_eq!(context().value(), 0);
+    }
+
+    #[test]
+    fn value_is_read_from_host() {
+        mock::with(|host| host.set_value(1_000));
+
+        assert_eq!(context().value(), 1_000);
+        assert_eq!(mock::with(|host| host.calls("context.value")), 1);
+    }
+
+    #[test]
+    fn caller_is_self_only_for_self_calls() {
+        let context = context();
+
+        mock::with(|host| host.set_caller(Address::new(*context.program.id())));
+        assert!(context.caller_is_self());
+
+        mock::with(|host| host.set_caller(Address::new([2; Address::LEN])));
+        assert!(!context.caller_is_self());
+        assert_eq!(context.caller(), Address::new([2; Address::LEN]));
+    }
+
+    #[test]
+    fn owner_calls_proceed() {
+        let context = context();
+        let owner = Address::new([3; Address::LEN]);
+        context
+            .program
+            .state::<Key>()
+            .store(Key::new(b"owner".to_vec()), &owner)
+            .unwrap();
+
+        mock::with(|host| host.set_caller(owner));
+        context.require_owner(b"owner");
+    }
+
+    #[test]
+    #[should_panic = "only the owner of the program may call this function"]
+    fn non_owner_calls_revert() {
+        let context = context();
+        let owner = Address::new([3; Address::LEN]);
+        context
+            .program
+            .state::<Key>()
+            .store(Key::new(b"owner".to_vec()), &owner)
+            .unwrap();
+
+        mock::with(|host| host.set_caller(Address::new([4; Address::LEN])));
+        context.require_owner(b"owner");
+    }
+
+    #[test]
+    fn call_depth_increments_across_nested_calls() {
+        // calls itself `remaining` more times and returns the depth of each call
+        mock::with(|host| {
+            host.deploy(
+                Address::new([1; Address::LEN]),
+                "recurse",
+                |program, args| {
+                    let remaining: u32 = borsh::from_slice(args).unwrap();
+                    let mut depths = vec![Context { program }.call_depth()];
+                    if remaining > 0 {
+                        let inner = program
+                            .call_metered::<_, Vec<u32>>("recurse", &(remaining - 1), 1_000)
+                            .unwrap();
+                        depths.extend(inner.value);
+                    }
+                    borsh::to_vec(&depths).unwrap()
+                },
+            );
+        });
+        let context = context();
+        assert_eq!(context.call_depth(), 0);
+
+        let depths = context
+            .program
+            .call_metered::<_, Vec<u32>>("recurse", &2_u32, 1_000)
+            .unwrap();
+        assert_eq!(depths.value, [1, 2, 3]);
+        assert_eq!(context.call_depth(), 0);
+
+        mock::with(|host| host.set_call_depth(MAX_CALL_DEPTH));
+        assert!(matches!(
+            context
+                .program
+                .call_metered::<_, Vec<u32>>("recurse", &0_u32, 1_000),
+            Err(crate::state::Error::HostError(-1))
+        ));
+    }
+
+    #[test]
+    fn chain_id_is_read_from_host() {
+        mock::with(|host| host.set_chain_id([7; 32]));
+
+        assert_eq!(context().chain_id(), [7; 32]);
+        assert_eq!(mock::with(|host| host.calls("context.chain_id")), 1);
+    }
+
+    #[test]
+    fn self_balance_is_read_from_host() {
+        mock::with(|host| host.set_balance(*context().program.id(), 5_000));
+
+        assert_eq!(context().self_balance(), 5_000);
+        assert_eq!(mock::with(|host| host.calls("context.balance")), 1);
+    }
+
+    #[test]
+    fn block_hash_is_only_available_within_window() {
+        mock::with(|host| {
+            for height in 0..300_u16 {
+                let mut hash = [0; 32];
+                hash[..2].copy_from_slice(&height.to_be_bytes());
+                host.push_block(hash);
+            }
+        });
+        let oldest = 300 - BLOCK_HASH_WINDOW;
+
+        let hash = context().block_hash(299).unwrap().unwrap();
+        assert_eq!(hash[..2], 299_u16.to_be_bytes());
+        assert!(context().block_hash(oldest).unwrap().is_some());
+        assert_eq!(context().block_hash(oldest - 1).unwrap(), None);
+        assert_eq!(context().block_hash(300).unwrap(), None);
+    }
+
+    #[test]
+    fn storage_quota_decreases_after_a_write() {
+        mock::with(|host| host.set_storage_quota(1_000));
+        let context = context();
+        assert_eq!(context.remaining_storage_quota(), 1_000);
+
+        let mut state = context.program.state::<Key>();
+        state.store(Key::new(vec![0; 4]), &[0_u8; 16]).unwrap();
+        assert_eq!(context.remaining_storage_quota(), 1_000);
+        drop(state);
+
+        // the key, then the value with its `u32` length prefix
+        assert_eq!(context.remaining_storage_quota(), 1_000 - 4 - (4 + 16));
+    }
+
+    #[test]
+    fn timestamp_is_read_from_host() {
+        mock::with(|host| host.set_timestamp(1_700_000_000));
+
+        assert_eq!(context().timestamp(), 1_700_000_000);
+        assert_eq!(mock::with(|host| host.calls("context.timestamp")), 1);
+    }
+}
@@ -0,0 +1,171 @@
+//! Hex and base64 encodings, for programs that emit or parse human readable
+//! identifiers. Enabled with the `encoding` feature.
+
+/// Lowercase hexadecimal encoding.
+pub mod hex {
+    use crate::state::Error as StateError;
+
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    /// Encodes `bytes` as lowercase hex.
+    #[must_use]
+    pub fn encode(bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xf])
+            .map(|nibble| char::from(DIGITS[usize::from(nibble)]))
+            .collect()
+    }
+
+    /// Decodes a hex string. Both upper and lowercase digits are accepted.
+    /// # Errors
+    /// Returns [`StateError::InvalidEncoding`] if `s` has an odd length or
+    /// contains a non-hex character.
+    pub fn decode(s: &str) -> Result<Vec<u8>, StateError> {
+        if !s.len().is_multiple_of(2) {
+            return Err(StateError::InvalidEncoding);
+        }
+
+        s.as_bytes()
+            .chunks_exact(2)
+            .map(|pair| Ok(nibble(pair[0])? << 4 | nibble(pair[1])?))
+            .collect()
+    }
+
+    fn nibble(digit: u8) -> Result<u8, StateError> {
+        match digit {
+            b'0'..=b'9' => Ok(digit - b'0'),
+            b'a'..=b'f' => Ok(digit - b'a' + 10),
+            b'A'..=b'F' => Ok(digit - b'A' + 10),
+            _ => Err(StateError::InvalidEncoding),
+        }
+    }
+}
+
+/// Padded base64 encoding with the standard alphabet from RFC 4648.
+pub mod base64 {
+    use crate::state::Error as StateError;
+
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    const PAD: u8 = b'=';
+
+    /// Encodes `bytes` as padded base64.
+    #[must_use]
+    pub fn encode(bytes: &[u8]) -> String {
+        let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+        for chunk in bytes.chunks(3) {
+            let block = chunk.iter().enumerate().fold(0_u32, |block, (i, byte)| {
+                block | u32::from(*byte) << (16 - 8 * i)
+            });
+
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    let sextet = (block >> (18 - 6 * i)) & 0x3f;
+                    encoded.push(char::from(ALPHABET[sextet as usize]));
+                } else {
+                    encoded.push(char::from(PAD));
+                }
+            }
+        }
+
+        encoded
+    }
+
+    /// Decodes padded base64.
+    /// # Errors
+    /// Returns [`StateError::InvalidEncoding`] if `s` is not a multiple of 4
+    /// characters long, contains a character outside the alphabet, or is
+    /// padded anywhere but at its end.
+    pub fn decode(s: &str) -> Result<Vec<u8>, StateError> {
+        let s = s.as_bytes();
+        if !s.len().is_multiple_of(4) {
+            return Err(StateError::InvalidEncoding);
+        }
+
+        let mut decoded = Vec::with_capacity(s.len() / 4 * 3);
+
+        for (i, chunk) in s.chunks_exact(4).enumerate() {
+            let is_last = (i + 1) * 4 == s.len();
+            let padding = chunk.iter().rev().take_while(|c| **c == PAD).count();
+            if padding > 2 || (padding > 0 && !is_last) {
+                return Err(StateError::InvalidEncoding);
+            }
+
+            let mut block = 0_u32;
+            for (j, c) in chunk[..4 - padding].iter().enumerate() {
+                block |= sextet(*c)? << (18 - 6 * j);
+            }
+
+            #[allow(clippy::cast_possible_truncation)] // masked to a single byte
+            decoded.extend((0..3 - padding).map(|j| (block >> (16 - 8 * j)) as u8));
+        }
+
+        Ok(decoded)
+    }
+
+    fn sextet(c: u8) -> Result<u32, StateError> {
+        ALPHABET
+            .iter()
+            .position(|a| *a == c)
+            .and_then(|position| u32::try_from(position).ok())
+            .ok_or(StateError::InvalidEncoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64, hex};
+    use crate::state::Error as StateError;
+
+    #[test]
+    fn hex_vectors() {
+        assert_eq!(hex::encode(&[]), "");
+        assert_eq!(hex::encode(&[0x00, 0x0f, 0xa5, 0xff]), "000fa5ff");
+        assert_eq!(hex::decode("000fa5ff").unwrap(), [0x00, 0x0f, 0xa5, 0xff]);
+        assert_eq!(hex::decode("DEADbeef").unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn hex_rejects_malformed_input() {
+        assert!(matches!(
+            hex::decode("abc"),
+            Err(StateError::InvalidEncoding)
+        ));
+        assert!(matches!(
+            hex::decode("zz"),
+            Err(StateError::InvalidEncoding)
+        ));
+    }
+
+    #[test]
+    fn base64_vectors() {
+        // https://datatracker.ietf.org/doc/html/rfc4648#section-10
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+
+        for (plain, encoded) in vectors {
+            assert_eq!(base64::encode(plain.as_bytes()), encoded);
+            assert_eq!(base64::decode(encoded).unwrap(), plain.as_bytes());
+        }
+
+        assert_eq!(base64::encode(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[test]
+    fn base64_rejects_malformed_input() {
+        for malformed in ["Zg=", "Zg==Zg==", "Z===", "Zm9*", "=Zm9"] {
+            assert!(
+                matches!(base64::decode(malformed), Err(StateError::InvalidEncoding)),
+                "{malformed} should not decode"
+            );
+        }
+    }
+}
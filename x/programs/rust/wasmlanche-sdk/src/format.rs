@@ -0,0 +1,134 @@
+//! The encodings used to move values between a program and the host.
+
+use crate::state::Error as StateError;
+use borsh::{BorshDeserialize, BorshSerialize};
+
+/// An encoding for values of type `T`: a format that can both
+/// [encode](WireEncode) and [decode](WireDecode) them. [`BorshFormat`] is used
+/// unless a different format is chosen, for example to store values with a
+/// compact fixed layout.
+///
+/// Operations only ask for the half they use, so a value that is only
+/// written needs nothing more than an encoding, such as [`BorshSerialize`]
+/// with [`BorshFormat`]. Implement both halves and this trait follows.
+///
+/// A format must be deterministic: encoding the same value must produce the
+/// same bytes on every node.
+pub trait WireFormat<T>: WireEncode<T> + WireDecode<T> {}
+
+impl<F, T> WireFormat<T> for F where F: WireEncode<T> + WireDecode<T> {}
+
+/// The encoding half of a [`WireFormat`].
+pub trait WireEncode<T> {
+    /// Encodes `value` into bytes.
+    /// # Errors
+    /// Returns a [`StateError`] if the value cannot be encoded.
+    fn encode(value: &T) -> Result<Vec<u8>, StateError>;
+}
+
+/// The decoding half of a [`WireFormat`].
+pub trait WireDecode<T> {
+    /// Decodes a value from `bytes`.
+    /// # Errors
+    /// Returns a [`StateError`] if the bytes are not a valid encoding of `T`.
+    fn decode(bytes: &[u8]) -> Result<T, StateError>;
+}
+
+/// The default [borsh] encoding.
+pub struct BorshFormat;
+
+impl<T> WireEncode<T> for BorshFormat
+where
+    T: BorshSerialize,
+{
+    fn encode(value: &T) -> Result<Vec<u8>, StateError> {
+        borsh::to_vec(value).map_err(|_| StateError::Serialization)
+    }
+}
+
+impl<T> WireDecode<T> for BorshFormat
+where
+    T: BorshDeserialize,
+{
+    fn decode(bytes: &[u8]) -> Result<T, StateError> {
+        borsh::from_slice(bytes).map_err(|err| StateError::from_borsh(&err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BorshFormat, WireDecode, WireEncode};
+    use crate::{
+        memory::{alloc, from_host_ptr_with},
+        program::Program,
+        state::{Error as StateError, Key, State},
+    };
+
+    /// Encodes a `u32` as 4 big-endian bytes.
+    struct BigEndian;
+
+    impl WireEncode<u32> for BigEndian {
+        fn encode(value: &u32) -> Result<Vec<u8>, StateError> {
+            Ok(value.to_be_bytes().to_vec())
+        }
+    }
+
+    impl WireDecode<u32> for BigEndian {
+        fn decode(bytes: &[u8]) -> Result<u32, StateError> {
+            let bytes = bytes
+                .try_into()
+                .map_err(|_| StateError::InvalidByteLength {
+                    expected: 4,
+                    got: bytes.len(),
+                })?;
+            Ok(u32::from_be_bytes(bytes))
+        }
+    }
+
+    fn state() -> State<Key, BigEndian> {
+        State::new(Program::new([1; Program::LEN]))
+    }
+
+    #[test]
+    fn custom_format_round_trips_through_state() {
+        let key = Key::new(vec![0]);
+
+        let mut writer = state();
+        writer.store(key.clone(), &0x0102_0304_u32).unwrap();
+        drop(writer);
+
+        assert_eq!(state().get::<u32>(key).unwrap(), Some(0x0102_0304));
+    }
+
+    #[test]
+    fn custom_format_decodes_host_ptr() {
+        let bytes = BigEndian::encode(&7).unwrap();
+        let ptr = alloc(bytes.len());
+        unsafe { std::ptr::copy(bytes.as_ptr(), ptr, bytes.len()) }
+
+        assert_eq!(from_host_ptr_with::<BigEndian, u32>(ptr as i64).unwrap(), 7);
+    }
+
+    #[test]
+    fn borsh_format_matches_borsh() {
+        let encoded = <BorshFormat as WireEncode<u32>>::encode(&7).unwrap();
+        assert_eq!(encoded, borsh::to_vec(&7_u32).unwrap());
+        assert_eq!(
+            <BorshFormat as WireDecode<u32>>::decode(&encoded).unwrap(),
+            7
+        );
+    }
+
+    #[test]
+    fn write_only_values_can_be_stored() {
+        #[derive(borsh::BorshSerialize)]
+        struct Receipt(u64);
+
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        state.store(Key::new(vec![0]), &Receipt(7)).unwrap();
+        drop(state);
+
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        assert_eq!(state.get::<u64>(Key::new(vec![0])).unwrap(), Some(7));
+    }
+}
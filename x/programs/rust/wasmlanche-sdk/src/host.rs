@@ -0,0 +1,134 @@
+//! Decoding of what host imports return, and calls to host functions chosen
+//! at runtime, for programs such as routers that dispatch on a name they only
+//! learn from their input.
+
+#[cfg(feature = "unstable")]
+use crate::{memory::read_into, metrics};
+use crate::{memory::HostPtr, state::Error as StateError};
+
+/// The code returned by host imports that may have nothing to return, such as
+/// `state.get` for an absent key, when there is nothing.
+pub const NOTHING: i64 = -1;
+
+/// Interprets the return value of a host import that returns a [`HostPtr`] on
+/// success and a negative error code on failure.
+///
+/// Imports that may have nothing to return are decoded with
+/// [`decode_optional`] instead.
+/// # Errors
+/// Returns [`StateError::HostError`] with the code if `ret` is negative.
+pub fn decode_result(ret: i64) -> Result<HostPtr, StateError> {
+    if ret < 0 {
+        Err(StateError::HostError(ret))
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Like [`decode_result`], for imports that return [`NOTHING`] when there is
+/// nothing to return, such as `state.get` for an absent key.
+/// # Errors
+/// Returns [`StateError::HostError`] with the code if `ret` is negative and
+/// not [`NOTHING`].
+pub fn decode_optional(ret: i64) -> Result<Option<HostPtr>, StateError> {
+    match ret {
+        NOTHING => Ok(None),
+        ret => decode_result(ret).map(Some),
+    }
+}
+
+/// Calls the host function registered as `name` with the raw `input` and
+/// returns its raw response. Neither side is serialized: encoding the input
+/// and decoding the response is up to the caller, as the function requires.
+/// # Errors
+/// Returns [`StateError::HostError`] if the host has no function called `name`
+/// or the function fails, or a [`StateError`] if the arguments cannot be
+/// passed to the host.
+#[cfg(feature = "unstable")]
+pub fn call_named(name: &str, input: &[u8]) -> Result<Vec<u8>, StateError> {
+    let ptr = metrics::record("host.call_named", name.len() + input.len(), || {
+        imports::call_named(name, input)
+    })?;
+    let ptr = decode_result(ptr)?;
+
+    let mut response = Vec::new();
+    read_into(ptr, &mut response)?;
+    Ok(response)
+}
+
+#[cfg(all(test, feature = "unstable"))]
+use crate::mock::host as imports;
+
+#[cfg(all(not(test), feature = "unstable"))]
+mod imports {
+    use crate::{memory::to_host_ptr, state::Error};
+
+    #[link(wasm_import_module = "host")]
+    extern "C" {
+        #[link_name = "call_named"]
+        fn _call_named(name: i64, input: i64) -> i64;
+    }
+
+    /// Returns a pointer to the response of the function `name`, or a negative
+    /// value if there is no such function or it fails.
+    pub(super) fn call_named(name: &str, input: &[u8]) -> Result<i64, Error> {
+        let name = to_host_ptr(name.as_bytes())?;
+        let input = to_host_ptr(input)?;
+
+        Ok(unsafe { _call_named(name, input) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_optional, decode_result, NOTHING};
+    use crate::state::Error as StateError;
+    #[cfg(feature = "unstable")]
+    use crate::{host::call_named, memory::tracked_blocks, mock};
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn named_calls_are_routed_to_handlers() {
+        mock::with(|host| {
+            host.register("reverse", |input| input.iter().rev().copied().collect());
+            host.register("len", |input| vec![u8::try_from(input.len()).unwrap()]);
+        });
+
+        assert_eq!(call_named("reverse", b"abc").unwrap(), b"cba");
+        assert_eq!(call_named("len", b"abc").unwrap(), [3]);
+        assert_eq!(tracked_blocks(), 0);
+        assert_eq!(mock::with(|host| host.calls("host.call_named")), 2);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn unknown_function_is_a_host_error() {
+        assert!(matches!(
+            call_named("missing", b"input"),
+            Err(StateError::HostError(-1))
+        ));
+    }
+
+    #[test]
+    fn negative_returns_are_error_codes() {
+        assert_eq!(decode_result(0x10_0000_2000).unwrap(), 0x10_0000_2000);
+        assert_eq!(decode_result(0).unwrap(), 0);
+
+        let err = decode_result(-3).unwrap_err();
+        assert!(matches!(err, StateError::HostError(-3)));
+        assert_eq!(err.to_string(), "host function failed with code -3");
+    }
+
+    #[test]
+    fn nothing_is_not_an_error_code() {
+        assert_eq!(decode_optional(NOTHING).unwrap(), None);
+        assert_eq!(
+            decode_optional(0x10_0000_2000).unwrap(),
+            Some(0x10_0000_2000)
+        );
+        assert!(matches!(
+            decode_optional(-3),
+            Err(StateError::HostError(-3))
+        ));
+    }
+}
@@ -0,0 +1,462 @@
+//! JSON that serializes to the same bytes on every node, for events read by
+//! off-chain indexers and results returned in the JSON
+//! [`Format`](crate::params::Format).
+//!
+//! Object keys are written in ascending byte order, whatever order the fields
+//! were declared or inserted in, with no whitespace. Integers are written in
+//! plain decimal. Floating-point numbers are rejected: programs should not
+//! use them, and their textual form is easy to get subtly different.
+
+use crate::state::Error as StateError;
+use serde::ser::{self, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+/// Serializes `value` to deterministic JSON.
+/// # Errors
+/// Returns [`StateError::Serialization`] if `value` cannot be represented as
+/// JSON or contains a floating-point number.
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>, StateError>
+where
+    T: Serialize + ?Sized,
+{
+    let value = value
+        .serialize(ValueSerializer)
+        .map_err(|_| StateError::Serialization)?;
+    let mut out = Vec::new();
+    write(&mut out, &value)?;
+
+    Ok(out)
+}
+
+/// A serialized value. Unlike `serde_json::Value`, numbers keep their digits,
+/// so 128-bit integers are written in full, and objects are kept sorted by
+/// key whatever features the build enables for `serde_json`.
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+fn write(out: &mut Vec<u8>, value: &Json) -> Result<(), StateError> {
+    match value {
+        Json::Null => out.extend_from_slice(b"null"),
+        Json::Bool(value) => out.extend_from_slice(if *value { b"true" } else { b"false" }),
+        Json::Number(digits) => out.extend_from_slice(digits.as_bytes()),
+        Json::String(string) => write_string(out, string)?,
+        Json::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write(out, item)?;
+            }
+            out.push(b']');
+        }
+        Json::Object(map) => {
+            out.push(b'{');
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_string(out, key)?;
+                out.push(b':');
+                write(out, value)?;
+            }
+            out.push(b'}');
+        }
+    }
+
+    Ok(())
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str) -> Result<(), StateError> {
+    serde_json::to_writer(out, string).map_err(|_| StateError::Serialization)
+}
+
+/// A value that has no deterministic JSON form.
+#[derive(Debug)]
+struct Unsupported;
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the value cannot be represented as deterministic JSON")
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+impl ser::Error for Unsupported {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        Unsupported
+    }
+}
+
+/// Serializes values into [`Json`], the way `serde_json` lays them out:
+/// enums are externally tagged and `None` and units are `null`.
+struct ValueSerializer;
+
+macro_rules! serialize_integers {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, value: $ty) -> Result<Json, Unsupported> {
+                Ok(Json::Number(value.to_string()))
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Json;
+    type Error = Unsupported;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer<MapSerializer>;
+
+    serialize_integers!(
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128
+    );
+
+    fn serialize_bool(self, value: bool) -> Result<Json, Unsupported> {
+        Ok(Json::Bool(value))
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<Json, Unsupported> {
+        Err(Unsupported)
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<Json, Unsupported> {
+        Err(Unsupported)
+    }
+
+    fn serialize_char(self, value: char) -> Result<Json, Unsupported> {
+        Ok(Json::String(value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Json, Unsupported> {
+        Ok(Json::String(value.to_owned()))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Json, Unsupported> {
+        Ok(Json::Array(
+            value
+                .iter()
+                .map(|byte| Json::Number(byte.to_string()))
+                .collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<Json, Unsupported> {
+        Ok(Json::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Json, Unsupported> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Json, Unsupported> {
+        Ok(Json::Null)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Json, Unsupported> {
+        Ok(Json::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<Json, Unsupported> {
+        Ok(Json::String(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<Json, Unsupported> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Json, Unsupported> {
+        Ok(tagged(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, Unsupported> {
+        Ok(SeqSerializer(Vec::with_capacity(len.unwrap_or_default())))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, Unsupported> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, Unsupported> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<SeqSerializer>, Unsupported> {
+        Ok(VariantSerializer(variant, self.serialize_seq(Some(len))?))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<MapSerializer, Unsupported> {
+        Ok(MapSerializer::default())
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<MapSerializer, Unsupported> {
+        Ok(MapSerializer::default())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<VariantSerializer<MapSerializer>, Unsupported> {
+        Ok(VariantSerializer(variant, MapSerializer::default()))
+    }
+}
+
+/// An enum variant carrying `value`, as an object whose only key is the
+/// variant's name.
+fn tagged(variant: &str, value: Json) -> Json {
+    Json::Object([(variant.to_owned(), value)].into_iter().collect())
+}
+
+struct SeqSerializer(Vec<Json>);
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Json;
+    type Error = Unsupported;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Unsupported> {
+        self.0.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Json, Unsupported> {
+        Ok(Json::Array(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Json;
+    type Error = Unsupported;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Unsupported> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Json, Unsupported> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Json;
+    type Error = Unsupported;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Unsupported> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Json, Unsupported> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+#[derive(Default)]
+struct MapSerializer {
+    entries: BTreeMap<String, Json>,
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Json;
+    type Error = Unsupported;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Unsupported> {
+        // keys are strings in JSON, so integer keys are written as their digits
+        match key.serialize(ValueSerializer)? {
+            Json::String(key) | Json::Number(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(Unsupported),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Unsupported> {
+        let key = self.key.take().ok_or(Unsupported)?;
+        self.entries.insert(key, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Json, Unsupported> {
+        Ok(Json::Object(self.entries))
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Json;
+    type Error = Unsupported;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Unsupported> {
+        self.entries
+            .insert(key.to_owned(), value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Json, Unsupported> {
+        Ok(Json::Object(self.entries))
+    }
+}
+
+/// Serializes the fields of an enum variant, then tags them with its name.
+struct VariantSerializer<S>(&'static str, S);
+
+impl ser::SerializeTupleVariant for VariantSerializer<SeqSerializer> {
+    type Ok = Json;
+    type Error = Unsupported;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Unsupported> {
+        ser::SerializeSeq::serialize_element(&mut self.1, value)
+    }
+
+    fn end(self) -> Result<Json, Unsupported> {
+        Ok(tagged(self.0, ser::SerializeSeq::end(self.1)?))
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<MapSerializer> {
+    type Ok = Json;
+    type Error = Unsupported;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Unsupported> {
+        ser::SerializeStruct::serialize_field(&mut self.1, key, value)
+    }
+
+    fn end(self) -> Result<Json, Unsupported> {
+        Ok(tagged(self.0, ser::SerializeStruct::end(self.1)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_vec;
+    use crate::state::Error as StateError;
+    use serde::Serialize;
+    use std::collections::HashMap;
+
+    #[derive(Serialize)]
+    struct Transfer {
+        to: &'static str,
+        amount: u64,
+        memo: Option<&'static str>,
+        tags: HashMap<&'static str, i32>,
+    }
+
+    fn transfer() -> Transfer {
+        Transfer {
+            to: "bob",
+            amount: 100,
+            memo: None,
+            tags: (0..16).map(|i| (["z", "a", "m", "b"][i % 4], -1)).collect(),
+        }
+    }
+
+    #[test]
+    fn keys_are_sorted_without_whitespace() {
+        assert_eq!(
+            to_vec(&transfer()).unwrap(),
+            br#"{"amount":100,"memo":null,"tags":{"a":-1,"b":-1,"m":-1,"z":-1},"to":"bob"}"#
+        );
+    }
+
+    #[test]
+    fn serializations_are_identical() {
+        // each map is seeded differently, so iteration orders differ
+        assert_eq!(to_vec(&transfer()).unwrap(), to_vec(&transfer()).unwrap());
+    }
+
+    #[test]
+    fn wide_integers_are_written_in_full() {
+        assert_eq!(
+            to_vec(&(u128::MAX, i128::MIN)).unwrap(),
+            b"[340282366920938463463374607431768211455,-170141183460469231731687303715884105728]"
+        );
+    }
+
+    #[test]
+    fn enums_are_externally_tagged() {
+        #[derive(Serialize)]
+        enum Action {
+            Pause,
+            Mint(u8),
+            Move(u8, u8),
+            Burn { amount: u8 },
+        }
+
+        let actions = [
+            Action::Pause,
+            Action::Mint(1),
+            Action::Move(2, 3),
+            Action::Burn { amount: 4 },
+        ];
+        assert_eq!(
+            to_vec(&actions).unwrap(),
+            br#"["Pause",{"Mint":1},{"Move":[2,3]},{"Burn":{"amount":4}}]"#
+        );
+        assert_eq!(to_vec(&Ok::<u8, ()>(1)).unwrap(), br#"{"Ok":1}"#);
+    }
+
+    #[test]
+    fn floats_are_rejected() {
+        assert!(matches!(to_vec(&1.5_f64), Err(StateError::Serialization)));
+        assert_eq!(
+            to_vec("quote \" and \u{1}").unwrap(),
+            br#""quote \" and \u0001""#
+        );
+    }
+}
@@ -1,22 +1,100 @@
 #![deny(clippy::pedantic)]
 
+extern crate alloc;
+
+#[cfg(feature = "unstable")]
+pub mod bigint;
+pub mod collections;
+pub mod format;
+pub mod host;
+#[cfg(any(feature = "json-events", feature = "multi-format"))]
+pub mod json;
+pub mod log;
+pub mod math;
+pub mod memo;
+pub mod metrics;
 pub mod params;
+pub mod prelude;
+#[cfg(feature = "unstable")]
+pub mod proof;
+pub mod random;
+pub mod sandbox;
+pub mod sort;
 pub mod state;
+pub mod time;
 pub mod types;
 
+#[cfg(feature = "compression")]
+mod compress;
+mod context;
 mod memory;
+#[cfg(test)]
+mod mock;
 mod program;
 
 pub use self::{
-    memory::{from_host_ptr, HostPtr},
+    context::{Context, BLOCK_HASH_WINDOW, MAX_CALL_DEPTH},
+    memory::{
+        assert_aligned, assert_no_leaks, copy_bytes, dealloc_all_except, from_host_ptr,
+        from_host_ptr_counted, from_host_ptr_enum, from_host_ptr_or_default, from_host_ptr_partial,
+        from_host_ptr_with, into_boxed_bytes, max_return_size, read_host_return, read_into,
+        serialized_size, set_max_return_size, shrink_store, to_host_ptr, to_host_ptr_from_iter,
+        try_alloc, try_from_host_ptr, Decoder, HostPtr, HostWriter, Pointer, Variants,
+        MAX_RETURN_SIZE,
+    },
     params::{serialize_param, Params},
-    program::Program,
+    program::{
+        max_call_input, max_call_output, set_max_call_input, set_max_call_output, Program,
+        MAX_CALL_INPUT, MAX_CALL_OUTPUT,
+    },
 };
 
+#[cfg(feature = "unstable")]
+pub use self::program::{CallResult, CallSpec};
+
+#[cfg(feature = "metrics")]
+pub use self::memory::{stats, MemStats};
+
+#[cfg(feature = "alloc-observer")]
+pub use self::memory::{set_alloc_observer, set_dealloc_observer, Observer};
+
+// the exports the host calls and the return path of the generated wrappers,
+// which programs have no reason to call themselves
+#[doc(hidden)]
+pub use self::memory::{alloc, dealloc, into_return_ptr};
+
 #[cfg(feature = "build")]
 pub mod build;
 
-pub use sdk_macros::{public, state_keys};
+#[cfg(feature = "encoding")]
+pub mod encoding;
+
+pub use sdk_macros::{memoize, public, state, state_keys, view};
+
+/// The [borsh] the SDK encodes values with. Deriving through it, rather than
+/// a `borsh` dependency of the program's own, keeps the program on the same
+/// version as the SDK, so both agree on the layout of every value. The
+/// derives name the crate by path, which `#[borsh(crate = ..)]` points here:
+///
+/// ```
+/// use wasmlanche_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+///
+/// #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+/// #[borsh(crate = "wasmlanche_sdk::borsh")]
+/// struct Position {
+///     x: u32,
+///     y: u32,
+/// }
+///
+/// let bytes = borsh::to_vec(&Position { x: 1, y: 2 }).unwrap();
+/// assert_eq!(bytes, [1, 0, 0, 0, 2, 0, 0, 0]);
+/// assert_eq!(borsh::from_slice::<Position>(&bytes).unwrap(), Position { x: 1, y: 2 });
+/// ```
+pub use borsh;
+
+// lets the unit tests use the macros, which name the crate by its path
+#[cfg(test)]
+extern crate self as wasmlanche_sdk;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -25,8 +103,3 @@ pub enum Error {
     #[error("Param error: {0}")]
     Param(#[from] std::io::Error),
 }
-
-#[derive(Clone, Copy, borsh::BorshSerialize, borsh::BorshDeserialize)]
-pub struct Context {
-    pub program: program::Program,
-}
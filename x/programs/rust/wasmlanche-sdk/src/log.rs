@@ -0,0 +1,469 @@
+//! Events emitted by a program for off-chain consumers, and log messages for
+//! developers. Emitting either needs the `unstable` feature, since the host
+//! functions that record them are not provided by every runtime yet.
+
+#[cfg(feature = "unstable")]
+use crate::{
+    metrics,
+    state::{Direction, Error as StateError},
+};
+use borsh::BorshSerialize;
+use std::cell::Cell;
+
+/// The severity of a log message. Levels are ordered from [`LogLevel::Off`],
+/// which lets no message through, to [`LogLevel::Debug`], which lets every
+/// message through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    /// Returns the level whose discriminant is `level`, from `0` for
+    /// [`LogLevel::Off`] to `4` for [`LogLevel::Debug`].
+    #[must_use]
+    pub fn from_u8(level: u8) -> Option<Self> {
+        [Self::Off, Self::Error, Self::Warn, Self::Info, Self::Debug]
+            .get(usize::from(level))
+            .copied()
+    }
+}
+
+/// The default limit on the encoded size of a single event, in bytes.
+pub const MAX_EVENT_SIZE: usize = 16 * 1024;
+
+thread_local! {
+    static LEVEL: Cell<LogLevel> = const { Cell::new(LogLevel::Info) };
+    static EVENT_SIZE_LIMIT: Cell<usize> = const { Cell::new(MAX_EVENT_SIZE) };
+}
+
+/// Sets the most verbose level of the messages sent to the host, [`LogLevel::Info`]
+/// by default. Messages of a more verbose level are dropped before they
+/// reach the host, so filtered logging costs next to nothing. The host sets
+/// the level from outside the program with [`set_log_level`].
+pub fn set_level(level: LogLevel) {
+    LEVEL.set(level);
+}
+
+/// Lets the host set the [level](set_level) of the instance, typically right
+/// after instantiating it, to silence debug messages in production without
+/// recompiling the program. `level` is the discriminant of a [`LogLevel`].
+///
+/// Returns `0` once the level is set, or `-1` if `level` is not a
+/// [`LogLevel`], in which case the level is left unchanged.
+#[no_mangle]
+pub extern "C" fn set_log_level(level: i32) -> i32 {
+    match u8::try_from(level).ok().and_then(LogLevel::from_u8) {
+        Some(level) => {
+            set_level(level);
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Returns the level set by [`set_level`].
+#[must_use]
+pub fn level() -> LogLevel {
+    LEVEL.get()
+}
+
+/// Returns `true` if messages at `level` are sent to the host.
+#[must_use]
+pub fn enabled(level: LogLevel) -> bool {
+    level <= LEVEL.get()
+}
+
+// `msg!` expands to this, so programs built without `std` can use it
+#[cfg(feature = "unstable")]
+#[doc(hidden)]
+pub use alloc::format;
+
+/// Formats its arguments like [`format!`] and logs the result at the
+/// [`LogLevel::Debug`] level. When debug messages are filtered out, the
+/// arguments are neither evaluated nor formatted, so the call costs a single
+/// check of the level.
+///
+/// The macro drops the host's error, like [`println!`], since a failed debug
+/// message should not fail the call. Use [`debug`] to handle it.
+///
+/// ```no_run
+/// # let amount = 5;
+/// wasmlanche_sdk::msg!("transferring {amount} tokens");
+/// ```
+#[cfg(feature = "unstable")]
+#[macro_export]
+macro_rules! msg {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::LogLevel::Debug) {
+            let _ = $crate::log::debug(&$crate::log::format!($($arg)*));
+        }
+    };
+}
+
+/// Logs `message` at the [`LogLevel::Error`] level.
+/// # Errors
+/// Returns a [`StateError`] if the host fails to record the message.
+#[cfg(feature = "unstable")]
+pub fn error(message: &str) -> Result<(), StateError> {
+    log(LogLevel::Error, message)
+}
+
+/// Logs `message` at the [`LogLevel::Warn`] level.
+/// # Errors
+/// Returns a [`StateError`] if the host fails to record the message.
+#[cfg(feature = "unstable")]
+pub fn warn(message: &str) -> Result<(), StateError> {
+    log(LogLevel::Warn, message)
+}
+
+/// Logs `message` at the [`LogLevel::Info`] level.
+/// # Errors
+/// Returns a [`StateError`] if the host fails to record the message.
+#[cfg(feature = "unstable")]
+pub fn info(message: &str) -> Result<(), StateError> {
+    log(LogLevel::Info, message)
+}
+
+/// Logs `message` at the [`LogLevel::Debug`] level.
+/// # Errors
+/// Returns a [`StateError`] if the host fails to record the message.
+#[cfg(feature = "unstable")]
+pub fn debug(message: &str) -> Result<(), StateError> {
+    log(LogLevel::Debug, message)
+}
+
+#[cfg(feature = "unstable")]
+fn log(level: LogLevel, message: &str) -> Result<(), StateError> {
+    if !enabled(level) {
+        return Ok(());
+    }
+
+    metrics::record("log.message", message.len(), || {
+        host::message(level as u8, message.as_bytes())
+    })
+}
+
+/// Sets the limit on the encoded size of a single event, for hosts that accept
+/// events larger than [`MAX_EVENT_SIZE`]. The host enforces its own limit
+/// regardless; this one only turns an oversized event into a
+/// [`StateError::LengthTooLarge`], which a program can handle by splitting or
+/// truncating the event, instead of a trap.
+pub fn set_max_event_size(limit: usize) {
+    EVENT_SIZE_LIMIT.set(limit);
+}
+
+/// Returns the limit set by [`set_max_event_size`], [`MAX_EVENT_SIZE`] by
+/// default.
+#[must_use]
+pub fn max_event_size() -> usize {
+    EVENT_SIZE_LIMIT.get()
+}
+
+#[cfg(feature = "unstable")]
+fn check_event_size(bytes: &[u8]) -> Result<(), StateError> {
+    let max = max_event_size();
+    if bytes.len() > max {
+        return Err(StateError::LengthTooLarge {
+            len: bytes.len(),
+            max,
+            direction: Direction::Input,
+        });
+    }
+
+    Ok(())
+}
+
+/// A [borsh] serializable value that can be emitted to the host.
+pub trait Event: BorshSerialize {}
+
+/// A value that can be emitted to the host as deterministic [JSON](crate::json)
+/// with [`emit_json`], for events read by off-chain indexers.
+#[cfg(feature = "json-events")]
+pub trait JsonEvent: serde::Serialize {}
+
+/// Emits a single event.
+/// # Errors
+/// Returns [`StateError::LengthTooLarge`], before calling the host, if the
+/// encoded event is longer than [`max_event_size`], or a [`StateError`] if
+/// the event cannot be serialized or the host fails to record it.
+#[cfg(feature = "unstable")]
+pub fn emit<E>(event: &E) -> Result<(), StateError>
+where
+    E: Event,
+{
+    let bytes = encode(event)?;
+    emit_bytes(&bytes)
+}
+
+/// Emits a single event as deterministic [JSON](crate::json), so every node
+/// records the same bytes for it.
+/// # Errors
+/// Returns [`StateError::LengthTooLarge`], before calling the host, if the
+/// encoded event is longer than [`max_event_size`], or a [`StateError`] if
+/// the event cannot be represented as JSON or the host fails to record it.
+#[cfg(feature = "unstable")]
+#[cfg(feature = "json-events")]
+pub fn emit_json<E>(event: &E) -> Result<(), StateError>
+where
+    E: JsonEvent,
+{
+    let bytes = crate::json::to_vec(event)?;
+    emit_bytes(&bytes)
+}
+
+#[cfg(feature = "unstable")]
+fn emit_bytes(bytes: &[u8]) -> Result<(), StateError> {
+    check_event_size(bytes)?;
+    metrics::record("log.emit", bytes.len(), || host::emit(bytes))
+}
+
+/// Emits all `events` with a single host call. The events are packed into one
+/// buffer, each prefixed with its big-endian `u32` length, and the host records
+/// them in the order they appear in `events`.
+///
+/// The batch is all or nothing: if the host traps while recording it, the whole
+/// invocation is reverted along with every event it emitted.
+/// # Errors
+/// Returns [`StateError::LengthTooLarge`], before calling the host, if an
+/// encoded event is longer than [`max_event_size`], or a [`StateError`] if an
+/// event cannot be serialized or the host fails to record the batch.
+#[cfg(feature = "unstable")]
+pub fn emit_batch<E>(events: &[E]) -> Result<(), StateError>
+where
+    E: Event,
+{
+    let mut batch = Vec::new();
+
+    for event in events {
+        let bytes = encode(event)?;
+        check_event_size(&bytes)?;
+        let len = u32::try_from(bytes.len()).map_err(|_| StateError::IntegerConversion)?;
+        batch.extend(len.to_be_bytes());
+        batch.extend(bytes);
+    }
+
+    metrics::record("log.emit_batch", batch.len(), || host::emit_batch(&batch))
+}
+
+#[cfg(feature = "unstable")]
+fn encode<E>(event: &E) -> Result<Vec<u8>, StateError>
+where
+    E: Event,
+{
+    borsh::to_vec(event).map_err(|_| StateError::Serialization)
+}
+
+#[cfg(all(test, feature = "unstable"))]
+use crate::mock::log as host;
+
+#[cfg(all(not(test), feature = "unstable"))]
+mod host {
+    use crate::{memory::to_host_ptr, state::Error};
+
+    #[link(wasm_import_module = "log")]
+    extern "C" {
+        #[link_name = "emit"]
+        fn _emit(event: i64) -> i64;
+
+        #[link_name = "emit_batch"]
+        fn _emit_batch(events: i64) -> i64;
+
+        #[link_name = "message"]
+        fn _message(level: i64, message: i64) -> i64;
+    }
+
+    /// Records the message at `level` on the host.
+    pub(super) fn message(level: u8, message: &[u8]) -> Result<(), Error> {
+        let message = to_host_ptr(message)?;
+
+        match unsafe { _message(level.into(), message) } {
+            0 => Ok(()),
+            _ => Err(Error::Emit),
+        }
+    }
+
+    /// Records the serialized event on the host.
+    pub(super) fn emit(event: &[u8]) -> Result<(), Error> {
+        let event = to_host_ptr(event)?;
+
+        match unsafe { _emit(event) } {
+            0 => Ok(()),
+            _ => Err(Error::Emit),
+        }
+    }
+
+    /// Records the length-prefixed events on the host, in order.
+    pub(super) fn emit_batch(events: &[u8]) -> Result<(), Error> {
+        let events = to_host_ptr(events)?;
+
+        match unsafe { _emit_batch(events) } {
+            0 => Ok(()),
+            _ => Err(Error::Emit),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod tests {
+    use super::{
+        debug, emit, emit_batch, encode, info, level, set_level, set_log_level, set_max_event_size,
+        warn, Event, LogLevel, MAX_EVENT_SIZE,
+    };
+    use crate::{mock, state::Error as StateError};
+    use borsh::BorshSerialize;
+
+    #[derive(BorshSerialize)]
+    struct Swap {
+        id: u32,
+    }
+
+    impl Event for Swap {}
+
+    #[test]
+    fn batch_preserves_order() {
+        let swaps: Vec<_> = (0..100).map(|id| Swap { id }).collect();
+        emit_batch(&swaps).unwrap();
+
+        let events = mock::with(|host| {
+            assert_eq!(host.calls("log.emit_batch"), 1);
+            host.events().to_vec()
+        });
+        let expected: Vec<_> = swaps.iter().map(|swap| encode(swap).unwrap()).collect();
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn single_event() {
+        emit(&Swap { id: 7 }).unwrap();
+
+        mock::with(|host| {
+            assert_eq!(host.calls("log.emit"), 1);
+            assert_eq!(host.events(), [7_u32.to_le_bytes()]);
+        });
+    }
+
+    #[cfg(feature = "json-events")]
+    #[test]
+    fn json_event() {
+        #[derive(serde::Serialize)]
+        struct Deposit {
+            to: &'static str,
+            amount: u128,
+        }
+
+        impl super::JsonEvent for Deposit {}
+
+        let deposit = Deposit {
+            to: "bob",
+            amount: u128::MAX,
+        };
+        super::emit_json(&deposit).unwrap();
+
+        mock::with(|host| {
+            assert_eq!(host.calls("log.emit"), 1);
+            assert_eq!(
+                host.events(),
+                [format!(r#"{{"amount":{},"to":"bob"}}"#, u128::MAX).into_bytes()]
+            );
+        });
+    }
+
+    #[derive(BorshSerialize)]
+    struct Blob {
+        bytes: Vec<u8>,
+    }
+
+    impl Event for Blob {}
+
+    #[test]
+    fn oversized_events_are_rejected_before_the_host() {
+        let blob = Blob {
+            bytes: vec![0; MAX_EVENT_SIZE],
+        };
+        let len = encode(&blob).unwrap().len();
+
+        assert!(matches!(
+            emit(&blob),
+            Err(StateError::LengthTooLarge { len: got, max: MAX_EVENT_SIZE, .. }) if got == len
+        ));
+        assert!(matches!(
+            emit_batch(&[Blob { bytes: vec![1] }, blob]),
+            Err(StateError::LengthTooLarge { .. })
+        ));
+        mock::with(|host| {
+            assert_eq!(host.calls("log.emit") + host.calls("log.emit_batch"), 0);
+        });
+
+        set_max_event_size(len);
+        emit(&Blob {
+            bytes: vec![0; MAX_EVENT_SIZE],
+        })
+        .unwrap();
+        assert_eq!(mock::with(|host| host.calls("log.emit")), 1);
+    }
+
+    #[test]
+    fn messages_below_the_level_are_dropped() {
+        set_level(LogLevel::Warn);
+        debug("cache miss").unwrap();
+        info("transfer").unwrap();
+        assert_eq!(mock::with(|host| host.calls("log.message")), 0);
+
+        warn("low balance").unwrap();
+        set_level(LogLevel::Debug);
+        debug("cache miss").unwrap();
+        set_level(LogLevel::Off);
+        super::error("unreachable").unwrap();
+
+        mock::with(|host| {
+            assert_eq!(host.calls("log.message"), 2);
+            assert_eq!(
+                host.messages(),
+                [
+                    (LogLevel::Warn as u8, "low balance".to_owned()),
+                    (LogLevel::Debug as u8, "cache miss".to_owned()),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn host_sets_the_level() {
+        assert_eq!(set_log_level(LogLevel::Error as i32), 0);
+        assert_eq!(level(), LogLevel::Error);
+        warn("low balance").unwrap();
+        assert_eq!(mock::with(|host| host.calls("log.message")), 0);
+
+        assert_eq!(set_log_level(5), -1);
+        assert_eq!(set_log_level(-1), -1);
+        assert_eq!(level(), LogLevel::Error);
+    }
+
+    #[test]
+    fn msg_formats_only_when_enabled() {
+        let formatted = std::cell::Cell::new(0);
+        let amount = || {
+            formatted.set(formatted.get() + 1);
+            5
+        };
+
+        set_level(LogLevel::Info);
+        crate::msg!("transferring {} tokens", amount());
+        assert_eq!(formatted.get(), 0);
+
+        set_level(LogLevel::Debug);
+        crate::msg!("transferring {} tokens", amount());
+        assert_eq!(formatted.get(), 1);
+        mock::with(|host| {
+            assert_eq!(
+                host.messages(),
+                [(LogLevel::Debug as u8, "transferring 5 tokens".to_owned())]
+            );
+        });
+    }
+}
@@ -0,0 +1,57 @@
+//! Integer arithmetic for fees and ratios. Floating point is not deterministic
+//! across targets, so amounts must never go through `f64`.
+
+use crate::state::Error as StateError;
+
+/// Returns `value * numerator / denominator`, rounded down. The product is
+/// computed in a `u128`, so it may exceed [`u64::MAX`] as long as the result
+/// does not, for example when taking a percentage of a large amount as
+/// `mul_div(amount, 30, 100)`.
+///
+/// The result never exceeds the exact fraction, so the remainder of a split
+/// computed this way is never negative.
+/// # Errors
+/// Returns [`StateError::DivisionByZero`] if `denominator` is zero, or
+/// [`StateError::IntegerConversion`] if the result does not fit a `u64`.
+pub fn mul_div(value: u64, numerator: u64, denominator: u64) -> Result<u64, StateError> {
+    if denominator == 0 {
+        return Err(StateError::DivisionByZero);
+    }
+
+    let result = u128::from(value) * u128::from(numerator) / u128::from(denominator);
+    u64::try_from(result).map_err(|_| StateError::IntegerConversion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mul_div;
+    use crate::state::Error as StateError;
+
+    #[test]
+    fn percentage_rounds_down() {
+        assert_eq!(mul_div(1_000, 30, 100).unwrap(), 300);
+        assert_eq!(mul_div(999, 1, 100).unwrap(), 9);
+        assert_eq!(mul_div(0, 7, 3).unwrap(), 0);
+        assert_eq!(mul_div(2, 1, 3).unwrap(), 0);
+    }
+
+    #[test]
+    fn intermediate_product_may_exceed_u64() {
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX).unwrap(), u64::MAX);
+        assert_eq!(mul_div(u64::MAX, 3, 4).unwrap(), u64::MAX / 4 * 3 + 2);
+        assert_eq!(mul_div(1 << 40, 1 << 40, 1 << 50).unwrap(), 1 << 30);
+    }
+
+    #[test]
+    fn zero_denominator_is_rejected() {
+        assert!(matches!(mul_div(1, 1, 0), Err(StateError::DivisionByZero)));
+    }
+
+    #[test]
+    fn overflowing_result_is_rejected() {
+        assert!(matches!(
+            mul_div(u64::MAX, 2, 1),
+            Err(StateError::IntegerConversion)
+        ));
+    }
+}
@@ -0,0 +1,115 @@
+//! Memoization of values read from state, so a helper called several times in
+//! one invocation only crosses to the host once.
+//!
+//! Memoized values are dropped at the start of every `#[public]` call and
+//! whenever state is written through a [State](crate::state::State): any
+//! write, delete or flush invalidates every memoized value, since any of them
+//! may depend on what was written.
+
+use std::cell::{Cell, RefCell};
+
+// `#[memoize]` expands to this, so programs built without `std` can use it
+#[doc(hidden)]
+pub use std::thread_local;
+
+thread_local! {
+    static EPOCH: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Invalidates every memoized value.
+pub fn invalidate() {
+    EPOCH.set(EPOCH.get().wrapping_add(1));
+}
+
+/// A value computed at most once between invalidations. It is meant to be
+/// kept in a `thread_local!`, which is what `#[memoize]` expands to.
+pub struct Memoized<T> {
+    value: RefCell<Option<(u64, T)>>,
+}
+
+impl<T> Default for Memoized<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Memoized<T> {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            value: RefCell::new(None),
+        }
+    }
+}
+
+impl<T> Memoized<T>
+where
+    T: Clone,
+{
+    /// Returns the memoized value, computing it with `f` if there is none or
+    /// it was invalidated. `f` may itself use other memoized values, but not
+    /// this one.
+    /// # Panics
+    /// Panics if `f` calls `get_or_init` on this cell.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> T {
+        let epoch = EPOCH.get();
+        if let Some((computed_at, value)) = &*self.value.borrow() {
+            if *computed_at == epoch {
+                return value.clone();
+            }
+        }
+
+        let value = f();
+        // `f` may have written state, in which case the value is already stale
+        if EPOCH.get() == epoch {
+            *self.value.borrow_mut() = Some((epoch, value.clone()));
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{invalidate, Memoized};
+    use crate::{mock, program::Program, state::Key};
+
+    thread_local! {
+        static FEE: Memoized<Option<u64>> = const { Memoized::new() };
+    }
+
+    fn program() -> Program {
+        Program::new([1; Program::LEN])
+    }
+
+    fn key() -> Key {
+        Key::new(b"fee".to_vec())
+    }
+
+    /// A config accessor, as `#[memoize]` expands it.
+    fn fee() -> Option<u64> {
+        FEE.with(|memo| memo.get_or_init(|| program().state().get(key()).unwrap()))
+    }
+
+    #[test]
+    fn memoized_read_crosses_once() {
+        program().state().store(key(), &30_u64).unwrap();
+        let gets = || mock::with(|host| host.calls("state.get"));
+
+        assert_eq!(fee(), Some(30));
+        assert_eq!(fee(), Some(30));
+        assert_eq!(fee(), Some(30));
+        assert_eq!(gets(), 1);
+
+        invalidate();
+        assert_eq!(fee(), Some(30));
+        assert_eq!(gets(), 2);
+    }
+
+    #[test]
+    fn write_invalidates_memoized_values() {
+        assert_eq!(fee(), None);
+
+        program().state().store(key(), &50_u64).unwrap();
+        assert_eq!(fee(), Some(50));
+    }
+}
@@ -4,14 +4,33 @@
 //! the program. These methods are unsafe as should be used
 //! with caution.
 
-use crate::state::Error as StateError;
-use borsh::{from_slice, BorshDeserialize};
+use crate::{
+    format::WireDecode,
+    host::decode_result,
+    state::{Direction, Error as StateError},
+};
+use borsh::{from_slice, BorshDeserialize, BorshSerialize};
 use std::{alloc::Layout, cell::RefCell, collections::HashMap};
 
 /// Represents a pointer to a block of memory allocated by the global allocator.
 #[derive(Clone, Copy)]
 pub struct Pointer(*mut u8);
 
+impl Pointer {
+    /// Returns the address of a packed [`HostPtr`], such as one built by
+    /// [`to_host_ptr`], whose high 32 bits hold the length of the block.
+    ///
+    /// Unlike `From<i64>`, which reinterprets the whole value as an address
+    /// and is only correct for raw pointers, this keeps just the low 32 bits.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // keeps the low 32 bits
+    pub fn from_packed(host_ptr: HostPtr) -> Self {
+        let ptr = host_ptr as u32 as usize as *mut u8;
+        Pointer(ptr)
+    }
+}
+
+/// Reinterprets a raw pointer. See [`Pointer::from_packed`] for packed ones.
 impl From<i64> for Pointer {
     fn from(v: i64) -> Self {
         let ptr: *mut u8 = v as *mut u8;
@@ -39,18 +58,201 @@ pub type HostPtr = i64;
 thread_local! {
     /// Map of pointer to the length of its content on the heap
     static GLOBAL_STORE: RefCell<HashMap<*const u8, usize>> = RefCell::new(HashMap::new());
+
+    /// Scratch space [`into_return_ptr`] serializes into, cleared and reused by
+    /// every call, so a return value is written without growing a block.
+    static RETURN_SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+
+    static RETURN_SIZE_LIMIT: std::cell::Cell<usize> = const { std::cell::Cell::new(MAX_RETURN_SIZE) };
+
+    /// Blocks handed over to the host, by pointer, with their length. They are
+    /// still allocated, and counted as live, until the host frees them with
+    /// [`dealloc`] once it has read them.
+    static HANDED_OVER: RefCell<HashMap<*const u8, usize>> = RefCell::new(HashMap::new());
+
+    /// Blocks freed since they were last handed out, to tell a double free
+    /// apart from a pointer that was never allocated. Cleared by
+    /// [`assert_no_leaks`] at the end of every call, so it only ever holds the
+    /// blocks freed during one call.
+    #[cfg(feature = "strict-alloc")]
+    static FREED: RefCell<std::collections::HashSet<*const u8>> = RefCell::new(std::collections::HashSet::new());
+}
+
+/// The default limit on the length of the bytes behind a [`HostPtr`], in
+/// bytes, matching the largest value the host accepts.
+pub const MAX_RETURN_SIZE: usize = 1024 * 1024;
+
+/// Sets the limit on the length of the bytes behind a [`HostPtr`], for hosts
+/// configured to accept more than [`MAX_RETURN_SIZE`].
+pub fn set_max_return_size(limit: usize) {
+    RETURN_SIZE_LIMIT.set(limit);
+}
+
+/// Returns the limit set by [`set_max_return_size`], [`MAX_RETURN_SIZE`] by
+/// default.
+#[must_use]
+pub fn max_return_size() -> usize {
+    RETURN_SIZE_LIMIT.get()
+}
+
+fn check_return_size(len: usize) -> Result<(), StateError> {
+    let max = max_return_size();
+    if len > max {
+        return Err(StateError::LengthTooLarge {
+            len,
+            max,
+            direction: Direction::Output,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "metrics")]
+thread_local! {
+    static STATS: std::cell::Cell<MemStats> = const { std::cell::Cell::new(MemStats::new()) };
+}
+
+/// A snapshot of the blocks tracked in the store, returned by [`stats`].
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemStats {
+    /// The number of blocks currently tracked.
+    pub live_blocks: usize,
+    /// The total length of the blocks currently tracked.
+    pub live_bytes: usize,
+    /// The highest value `live_bytes` has reached.
+    pub peak_bytes: usize,
+    /// The number of blocks allocated with [`alloc`] or [`try_alloc`].
+    pub total_allocs: u64,
+    /// The number of blocks reclaimed.
+    pub total_frees: u64,
+}
+
+#[cfg(feature = "metrics")]
+impl MemStats {
+    const fn new() -> Self {
+        Self {
+            live_blocks: 0,
+            live_bytes: 0,
+            peak_bytes: 0,
+            total_allocs: 0,
+            total_frees: 0,
+        }
+    }
+}
+
+/// Returns the allocation statistics of the current thread.
+#[cfg(feature = "metrics")]
+#[must_use]
+pub fn stats() -> MemStats {
+    STATS.get()
+}
+
+#[cfg(feature = "metrics")]
+fn update_stats(f: impl FnOnce(&mut MemStats)) {
+    STATS.with(|cell| {
+        let mut stats = cell.get();
+        f(&mut stats);
+        stats.peak_bytes = stats.peak_bytes.max(stats.live_bytes);
+        cell.set(stats);
+    });
+}
+
+#[cfg(feature = "metrics")]
+fn track_alloc(len: usize) {
+    update_stats(|stats| {
+        stats.live_blocks += 1;
+        stats.live_bytes += len;
+        stats.total_allocs += 1;
+    });
+}
+
+#[cfg(feature = "metrics")]
+fn track_free(len: usize) {
+    update_stats(|stats| {
+        stats.live_blocks = stats.live_blocks.saturating_sub(1);
+        stats.live_bytes = stats.live_bytes.saturating_sub(len);
+        stats.total_frees += 1;
+    });
+}
+
+#[cfg(feature = "metrics")]
+fn track_resize(old_len: usize, len: usize) {
+    update_stats(|stats| stats.live_bytes = stats.live_bytes - old_len + len);
+}
+
+#[cfg(not(feature = "metrics"))]
+fn track_alloc(_: usize) {}
+
+#[cfg(not(feature = "metrics"))]
+fn track_free(_: usize) {}
+
+#[cfg(not(feature = "metrics"))]
+fn track_resize(_: usize, _: usize) {}
+
+/// A function called with the pointer and length of a block, see
+/// [`set_alloc_observer`].
+#[cfg(feature = "alloc-observer")]
+pub type Observer = fn(ptr: *mut u8, len: usize);
+
+#[cfg(feature = "alloc-observer")]
+thread_local! {
+    static ALLOC_OBSERVER: std::cell::Cell<Option<Observer>> = const { std::cell::Cell::new(None) };
+    static DEALLOC_OBSERVER: std::cell::Cell<Option<Observer>> = const { std::cell::Cell::new(None) };
+}
+
+/// Calls `f` with every block tracked from now on, on the current thread,
+/// once it has been allocated and entered in the store, replacing the
+/// previous observer. A block moved by a resize is reported as freed at its
+/// old address and allocated at its new one.
+///
+/// Observers let profiling tools trace allocations or find leaks without
+/// changing the SDK. They must not allocate from the SDK themselves.
+#[cfg(feature = "alloc-observer")]
+pub fn set_alloc_observer(f: Observer) {
+    ALLOC_OBSERVER.set(Some(f));
+}
+
+/// Like [`set_alloc_observer`], but calls `f` with every block removed from
+/// the store, before it is reclaimed.
+#[cfg(feature = "alloc-observer")]
+pub fn set_dealloc_observer(f: Observer) {
+    DEALLOC_OBSERVER.set(Some(f));
 }
 
+#[cfg(feature = "alloc-observer")]
+fn observe_alloc(ptr: *mut u8, len: usize) {
+    if let Some(f) = ALLOC_OBSERVER.get() {
+        f(ptr, len);
+    }
+}
+
+#[cfg(feature = "alloc-observer")]
+fn observe_free(ptr: *mut u8, len: usize) {
+    if let Some(f) = DEALLOC_OBSERVER.get() {
+        f(ptr, len);
+    }
+}
+
+#[cfg(not(feature = "alloc-observer"))]
+fn observe_alloc(_: *mut u8, _: usize) {}
+
+#[cfg(not(feature = "alloc-observer"))]
+fn observe_free(_: *mut u8, _: usize) {}
+
 /// Converts a pointer to a i64 with the first 4 bytes of the pointer
 /// representing the length of the memory block.
 /// # Errors
-/// Returns an [`StateError`] if the pointer or length of `args` exceeds
-/// the maximum size of a u32.
-#[allow(clippy::cast_possible_truncation)]
+/// Returns an [`StateError`] if the pointer or length of `args` exceeds the
+/// maximum size of a u32.
 pub fn to_host_ptr(arg: &[u8]) -> Result<HostPtr, StateError> {
-    let ptr = arg.as_ptr() as usize;
-    let len = arg.len();
+    pack(arg.as_ptr() as usize, arg.len())
+}
 
+/// Packs an address and a length into a [`HostPtr`].
+#[allow(clippy::cast_possible_truncation)]
+fn pack(ptr: usize, len: usize) -> Result<HostPtr, StateError> {
     // Make sure the pointer and length fit into u32
     if ptr > u32::MAX as usize || len > u32::MAX as usize {
         return Err(StateError::IntegerConversion);
@@ -60,6 +262,172 @@ pub fn to_host_ptr(arg: &[u8]) -> Result<HostPtr, StateError> {
     Ok(host_ptr)
 }
 
+/// Splits a [`HostPtr`] packed by [`pack`] into its address and length.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // keeps each `u32` half
+fn unpack(host_ptr: HostPtr) -> (usize, usize) {
+    let ptr = host_ptr as u32 as usize;
+    let len = (host_ptr >> 32) as u32 as usize;
+    (ptr, len)
+}
+
+/// Takes back the bytes behind a packed [`HostPtr`] returned by a host
+/// import: the low 32 bits are the address and the high 32 bits the length,
+/// the layout [`to_host_ptr`] packs. The host writes its return into a block
+/// it allocated with [`alloc`], so the packed pointer must match a tracked
+/// block, whose bytes are returned and which is freed, like
+/// [`from_host_ptr`] does. Checking the store is what makes the read safe: a
+/// pointer the program did not hand out is rejected instead of read.
+///
+/// Zero, the packed null pointer, has no bytes and yields an empty vec. A
+/// negative value is the host's signal for a failure, since no valid packed
+/// pointer is `2^31` bytes long.
+/// # Errors
+/// Returns [`StateError::HostError`] with `ret` if it is negative,
+/// [`StateError::NullPointer`] if it packs the null address with a non-zero
+/// length, [`StateError::InvalidPointer`] if the address is not tracked and
+/// [`StateError::InvalidByteLength`] if the block at the address has another
+/// length, in which case the block is left tracked.
+pub fn read_host_return(ret: HostPtr) -> Result<Vec<u8>, StateError> {
+    let (ptr, len) = match unpack(decode_result(ret)?) {
+        (_, 0) => return Ok(Vec::new()),
+        (0, _) => return Err(StateError::NullPointer),
+        // the address came from the low 32 bits
+        (ptr, len) => (
+            HostPtr::try_from(ptr).map_err(|_| StateError::IntegerConversion)?,
+            len,
+        ),
+    };
+    match tracked_len(ptr) {
+        Some(tracked) if tracked == len => {
+            into_bytes(ptr).ok_or(StateError::InvalidPointer { ptr })
+        }
+        Some(tracked) => Err(StateError::InvalidByteLength {
+            expected: tracked,
+            got: len,
+        }),
+        None => Err(StateError::InvalidPointer { ptr: ret }),
+    }
+}
+
+/// Copies exactly `len_hint` bytes from `iter` into a new block and returns
+/// its packed [`HostPtr`], so bytes produced lazily don't have to be
+/// collected into a vec first. Like [`HostWriter::finish`], the block is
+/// handed over to the host, so it is no longer tracked, until the host frees
+/// it with [`dealloc`]. An empty iterator
+/// yields the packed null pointer, `0`, without allocating.
+/// # Errors
+/// Returns [`StateError::InvalidByteLength`] if `iter` does not yield exactly
+/// `len_hint` bytes, with the number it yielded, counting at most one past
+/// `len_hint`. Returns [`StateError::LengthTooLarge`], before allocating, if
+/// `len_hint` is larger than [`max_return_size`]. Returns a [`StateError`] if
+/// the block cannot be allocated or packed into a [`HostPtr`], in which case
+/// it is freed.
+pub fn to_host_ptr_from_iter<I>(iter: I, len_hint: usize) -> Result<HostPtr, StateError>
+where
+    I: Iterator<Item = u8>,
+{
+    check_return_size(len_hint)?;
+    match fill_block(iter, len_hint)? {
+        Some(ptr) => hand_over(ptr, len_hint),
+        None => Ok(0),
+    }
+}
+
+/// Fills a new block of `len` bytes from `iter` and returns it, leaving it
+/// tracked, or `None` if `len` is zero. The block is freed on error.
+fn fill_block<I>(mut iter: I, len: usize) -> Result<Option<*mut u8>, StateError>
+where
+    I: Iterator<Item = u8>,
+{
+    if len == 0 {
+        return match iter.next() {
+            Some(_) => Err(StateError::InvalidByteLength {
+                expected: 0,
+                got: 1,
+            }),
+            None => Ok(None),
+        };
+    }
+
+    let ptr = try_alloc(len)?;
+    let block = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+    let mut written = 0;
+    for (slot, byte) in block.iter_mut().zip(iter.by_ref()) {
+        *slot = byte;
+        written += 1;
+    }
+
+    if written < len || iter.next().is_some() {
+        drop(into_bytes(ptr as HostPtr));
+        let got = if written < len { written } else { len + 1 };
+        return Err(StateError::InvalidByteLength { expected: len, got });
+    }
+
+    Ok(Some(ptr))
+}
+
+/// Serializes `value` and returns the packed [`HostPtr`] to the bytes. Unlike
+/// [`to_host_ptr`], the bytes outlive the caller, so they can be handed to the
+/// host as the return value of a `#[public]` function.
+///
+/// The value is serialized into a per-thread scratch buffer, which every call
+/// clears and reuses, and then copied into a block of exactly its length, the
+/// only allocation a steady-state call makes. The block, not the buffer, is
+/// handed over to the host, so every returned pointer stays valid, whatever
+/// the program returns next, until the host frees it with [`dealloc`].
+/// # Errors
+/// Returns [`StateError::LengthTooLarge`], before allocating, if the
+/// serialized value is longer than [`max_return_size`], or an [`StateError`]
+/// if the value cannot be serialized or the bytes cannot be packed into a
+/// [`HostPtr`].
+pub fn into_return_ptr<T>(value: &T) -> Result<HostPtr, StateError>
+where
+    T: BorshSerialize,
+{
+    check_return_size(serialized_size(value)?)?;
+
+    RETURN_SCRATCH.with_borrow_mut(|scratch| {
+        scratch.clear();
+        value
+            .serialize(scratch)
+            .map_err(|_| StateError::Serialization)?;
+        let ret = copy_to_host(scratch);
+        // a rare large value shouldn't pin its memory for the instance's life
+        if scratch.capacity() > RETAINED_SCRATCH {
+            *scratch = Vec::new();
+        }
+        ret
+    })
+}
+
+/// The largest capacity the scratch buffer of [`into_return_ptr`] keeps
+/// between calls.
+const RETAINED_SCRATCH: usize = 4 * 1024;
+
+/// Copies `bytes` into a new block and hands it over to the host. Nothing is
+/// allocated for no bytes, which yield the packed null pointer, `0`.
+fn copy_to_host(bytes: &[u8]) -> Result<HostPtr, StateError> {
+    if bytes.is_empty() {
+        return Ok(0);
+    }
+
+    let ptr = try_alloc(bytes.len())?;
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+    hand_over(ptr, bytes.len())
+}
+
+/// Returns the length of the [borsh] encoding of `value` without writing it
+/// out, so a program can check that a return value fits the host's limit
+/// before serializing it.
+/// # Errors
+/// Returns [`StateError::Serialization`] if the value cannot be serialized.
+pub fn serialized_size<V>(value: &V) -> Result<usize, StateError>
+where
+    V: BorshSerialize,
+{
+    borsh::object_length(value).map_err(|_| StateError::Serialization)
+}
+
 /// Converts a raw pointer to a deserialized value.
 /// Expects the first 4 bytes of the pointer to represent the `length` of the serialized value,
 /// with the subsequent `length` bytes comprising the serialized data.
@@ -67,91 +435,1870 @@ pub fn to_host_ptr(arg: &[u8]) -> Result<HostPtr, StateError> {
 /// Panics if the bytes cannot be deserialized.
 /// # Safety
 /// This function is unsafe because it dereferences raw pointers.
+///
+/// The block is owned by the program from the moment this is called: it is
+/// freed whether or not it decodes, so `ptr` is dangling once this returns,
+/// even with an error. The host must not read the block again after handing
+/// it over. Use [`try_from_host_ptr`] to keep the block when it can't be
+/// decoded.
 /// # Errors
-/// Returns an [`StateError`] if the bytes cannot be deserialized.
+/// Returns an [`StateError`] if the bytes cannot be deserialized, or
+/// [`StateError::NullPointer`] if `ptr` is null, which the host passes when
+/// there is no data.
 pub fn from_host_ptr<V>(ptr: i64) -> Result<V, StateError>
 where
     V: BorshDeserialize,
 {
-    match into_bytes(ptr) {
-        Some(bytes) => from_slice::<V>(&bytes).map_err(|_| StateError::Deserialization),
-        None => Err(StateError::InvalidPointer),
+    match read_bytes(ptr) {
+        Some(bytes) => from_slice::<V>(&bytes).map_err(|err| StateError::from_borsh(&err)),
+        None => Err(missing(ptr)),
+    }
+}
+
+/// An enum borsh tags with its variant index, in declaration order from
+/// zero, as a derived [`BorshDeserialize`] does unless the enum opts into
+/// `#[borsh(use_discriminant = true)]`.
+pub trait Variants: BorshDeserialize {
+    /// The number of variants, one past the highest index.
+    const COUNT: u8;
+}
+
+/// Like [`from_host_ptr`], but reads the leading variant index of the enum
+/// `V` before decoding it, so that an index past its last variant, such as a
+/// malformed selector, is told apart from other malformed input.
+/// # Errors
+/// Returns [`StateError::UnknownVariant`] if the index is not below
+/// [`Variants::COUNT`], another [`StateError`] if the pointer is not tracked
+/// or the bytes cannot be deserialized.
+pub fn from_host_ptr_enum<V>(ptr: HostPtr) -> Result<V, StateError>
+where
+    V: Variants,
+{
+    let bytes = read_bytes(ptr).ok_or_else(|| missing(ptr))?;
+    match bytes.first() {
+        Some(&index) if index >= V::COUNT => Err(StateError::UnknownVariant { index }),
+        _ => from_slice::<V>(&bytes).map_err(|err| StateError::from_borsh(&err)),
+    }
+}
+
+/// Like [`from_host_ptr`], but only frees the block once it has decoded.
+/// When the bytes can't be decoded, the block is left tracked and untouched,
+/// so `ptr` stays valid: it can be read again, for example with
+/// [`read_into`] to report the raw bytes, and must eventually be freed, with
+/// [`from_host_ptr`] or [`dealloc`].
+/// # Errors
+/// Returns an [`StateError`] if the bytes cannot be deserialized,
+/// [`StateError::NullPointer`] if `ptr` is null and
+/// [`StateError::InvalidPointer`] if it is not tracked.
+pub fn try_from_host_ptr<V>(ptr: HostPtr) -> Result<V, StateError>
+where
+    V: BorshDeserialize,
+{
+    let block = Block::borrow(ptr).ok_or_else(|| missing(ptr))?;
+    match from_slice::<V>(&block) {
+        // dropping the guard frees the block
+        Ok(value) => Ok(value),
+        Err(err) => {
+            std::mem::forget(block);
+            Err(StateError::from_borsh(&err))
+        }
+    }
+}
+
+/// Like [`from_host_ptr`], but decodes the bytes with the
+/// [`WireFormat`](crate::format::WireFormat) `F`.
+/// # Errors
+/// Returns an [`StateError`] if the pointer is not tracked or the bytes
+/// cannot be decoded.
+pub fn from_host_ptr_with<F, V>(ptr: HostPtr) -> Result<V, StateError>
+where
+    F: WireDecode<V>,
+{
+    match read_bytes(ptr) {
+        Some(bytes) => F::decode(&bytes),
+        None => Err(missing(ptr)),
+    }
+}
+
+/// Like [`from_host_ptr`], but only decodes a `V` from the start of the block
+/// and returns it together with the bytes that follow it, for buffers that
+/// carry a header followed by a payload.
+/// # Errors
+/// Returns an [`StateError`] if the pointer is not tracked or a `V` cannot be
+/// deserialized from the start of the block.
+pub fn from_host_ptr_partial<V>(ptr: HostPtr) -> Result<(V, Vec<u8>), StateError>
+where
+    V: BorshDeserialize,
+{
+    let bytes = into_bytes(ptr).ok_or_else(|| missing(ptr))?;
+    let mut rest = bytes.as_slice();
+    let value = V::deserialize(&mut rest).map_err(|err| StateError::from_borsh(&err))?;
+
+    Ok((value, rest.to_vec()))
+}
+
+/// Decodes a `V` starting `offset` bytes into the block at `ptr` and returns
+/// it together with the number of bytes it took up. A buffer of back-to-back
+/// records is walked by passing the same `ptr` each time, offset by the
+/// lengths of the records before it.
+///
+/// The block is decoded in place and stays tracked while bytes remain after
+/// the `V`. It is freed once a decode reaches its end, or fails.
+/// # Errors
+/// Returns an [`StateError`] if the pointer is not tracked or a `V` cannot be
+/// deserialized at `offset`.
+pub fn from_host_ptr_counted<V>(ptr: HostPtr, offset: usize) -> Result<(V, usize), StateError>
+where
+    V: BorshDeserialize,
+{
+    let block = Block::borrow(ptr).ok_or_else(|| missing(ptr))?;
+    let mut cursor = block.get(offset..).unwrap_or_default();
+    let start = cursor.len();
+    let value = V::deserialize(&mut cursor).map_err(|err| StateError::from_borsh(&err))?;
+    let consumed = start - cursor.len();
+
+    if !cursor.is_empty() {
+        // the records after this one are still to be decoded
+        std::mem::forget(block);
+    }
+    Ok((value, consumed))
+}
+
+/// Like [`from_host_ptr`], but returns `V::default()` when there is nothing to
+/// decode: `ptr` is null (the host's "no value" signal), or it points to an
+/// empty block. A negative `ptr` is a host error code, like the ones
+/// [`read_host_return`] reports, not an absent value.
+/// # Errors
+/// Returns [`StateError::HostError`] with `ptr` if it is negative, or an
+/// [`StateError`] if a non-empty block cannot be deserialized or if `ptr` is
+/// not tracked.
+pub fn from_host_ptr_or_default<V>(ptr: HostPtr) -> Result<V, StateError>
+where
+    V: BorshDeserialize + Default,
+{
+    if decode_result(ptr)? == 0 {
+        return Ok(V::default());
+    }
+
+    match read_bytes(ptr) {
+        Some(bytes) if bytes.is_empty() => Ok(V::default()),
+        Some(bytes) => from_slice::<V>(&bytes).map_err(|err| StateError::from_borsh(&err)),
+        None => Err(missing(ptr)),
+    }
+}
+
+/// Replaces the contents of `buf` with the raw bytes of the block at `ptr` and
+/// frees the block. Unlike reading the block into a new vec, the capacity of
+/// `buf` is reused, so decoding many blocks through one buffer only allocates
+/// when a block is larger than any before it.
+/// # Errors
+/// Returns [`StateError::NullPointer`] if `ptr` is null and
+/// [`StateError::InvalidPointer`] if it is not tracked, in which case `buf` is
+/// left untouched.
+pub fn read_into(ptr: HostPtr, buf: &mut Vec<u8>) -> Result<(), StateError> {
+    let block = Block::borrow(ptr).ok_or_else(|| missing(ptr))?;
+    buf.clear();
+    buf.extend_from_slice(&block);
+
+    Ok(())
+}
+
+/// Checks that `ptr` satisfies the alignment of `T`. Call it before reading a
+/// `T` directly out of program memory, for example with
+/// [`read`](std::ptr::read), since reading through a misaligned pointer is
+/// undefined behaviour.
+/// # Errors
+/// Returns [`StateError::Misaligned`] if `ptr` is not aligned for `T`.
+pub fn assert_aligned<T>(ptr: *const u8) -> Result<(), StateError> {
+    if ptr.cast::<T>().is_aligned() {
+        Ok(())
+    } else {
+        Err(StateError::Misaligned)
+    }
+}
+
+/// The error for a `ptr` with no tracked block: [`StateError::NullPointer`]
+/// for the null pointer, which the host passes when there is no data, and
+/// [`StateError::InvalidPointer`] for any other pointer.
+pub(crate) fn missing(ptr: HostPtr) -> StateError {
+    if ptr == 0 {
+        StateError::NullPointer
+    } else {
+        StateError::InvalidPointer { ptr }
     }
 }
 
 /// Reconstructs the vec from the pointer with the length given by the store
 /// `host_ptr` is encoded using Big Endian as an i64.
+///
+/// [`alloc`] never hands out empty blocks, but a zero-length entry is still
+/// accepted and yields an empty vec without touching the pointer, which may
+/// be dangling.
+///
+/// With the `strict-alloc` feature, reading back a block that was already
+/// freed panics instead of returning `None`.
 #[must_use]
 fn into_bytes(ptr: HostPtr) -> Option<Vec<u8>> {
-    GLOBAL_STORE
-        .with_borrow_mut(|s| s.remove(&(ptr as *const u8)))
-        .map(|len| unsafe { std::vec::Vec::from_raw_parts(ptr as *mut u8, len, len) })
+    untrack(ptr).map(|len| match len {
+        0 => Vec::new(),
+        len => reclaim(ptr as *mut u8, len),
+    })
+}
+
+/// Packs the tracked block at `ptr` into a [`HostPtr`] and hands it over to
+/// the host: the block leaves the store without being freed, so it is not
+/// reported as a leak and `from_host_ptr` no longer reclaims it, but it is
+/// still live until the host frees it with [`dealloc`]. The block is freed
+/// instead if it cannot be packed.
+fn hand_over(ptr: *mut u8, len: usize) -> Result<HostPtr, StateError> {
+    match pack(ptr as usize, len) {
+        Ok(host_ptr) => {
+            give_to_host(ptr);
+            Ok(host_ptr)
+        }
+        Err(err) => {
+            drop(into_bytes(ptr as HostPtr));
+            Err(err)
+        }
+    }
+}
+
+/// Moves the tracked block at `ptr` out of the store into the blocks handed
+/// over to the host, which [`dealloc`] frees.
+fn give_to_host(ptr: *mut u8) {
+    if let Some(len) = detach(ptr as HostPtr) {
+        HANDED_OVER.with_borrow_mut(|blocks| blocks.insert(ptr.cast_const(), len));
+    }
+}
+
+/// Removes the block at `ptr` from the store and returns its length, without
+/// reporting it as freed, for a block that stays allocated.
+fn detach(ptr: HostPtr) -> Option<usize> {
+    // null is never tracked, and means there is no data rather than a lookup
+    // miss, so it doesn't reach the store
+    if ptr == 0 {
+        return None;
+    }
+
+    GLOBAL_STORE.with_borrow_mut(|s| s.remove(&(ptr as *const u8)))
+}
+
+/// Removes the block at `ptr` from the store and returns its length, leaving
+/// the block itself to the caller to reclaim.
+fn untrack(ptr: HostPtr) -> Option<usize> {
+    let removed = detach(ptr);
+    #[cfg(feature = "strict-alloc")]
+    FREED.with_borrow_mut(|freed| match removed {
+        Some(_) => drop(freed.insert(ptr as *const u8)),
+        None => assert!(
+            !freed.contains(&(ptr as *const u8)),
+            "double free of the block at {ptr:#x}"
+        ),
+    });
+
+    removed.inspect(|len| {
+        track_free(*len);
+        observe_free(ptr as *mut u8, *len);
+    })
+}
+
+/// Takes back the tracked block at `ptr` for a decode that only needs its
+/// bytes while it runs, freeing it like [`into_bytes`].
+///
+/// The block is adopted as is, so there is nothing to pool.
+#[cfg(not(feature = "deterministic-alloc"))]
+fn read_bytes(ptr: HostPtr) -> Option<Vec<u8>> {
+    into_bytes(ptr)
+}
+
+/// The arena can't hand its blocks over, so the bytes are copied into a
+/// buffer drawn from the [pool](POOL_SIZE), which takes it back once the
+/// decode is done.
+#[cfg(feature = "deterministic-alloc")]
+fn read_bytes(ptr: HostPtr) -> Option<pool::Pooled> {
+    untrack(ptr).map(|len| {
+        let mut buf = pool::take();
+        if len > 0 {
+            buf.extend_from_slice(unsafe { std::slice::from_raw_parts(ptr as *const u8, len) });
+        }
+        buf
+    })
+}
+
+/// The most buffers the decode pool of a thread keeps. With the
+/// `deterministic-alloc` feature every decode copies its block out of the
+/// arena, drawing a buffer from the pool and returning it when done, so
+/// decoding in a loop stops allocating once the pool is warm. Buffers
+/// returned while the pool is full are dropped.
+///
+/// Without the feature decodes adopt the block itself, so there is nothing
+/// to copy into and no pool.
+#[cfg(feature = "deterministic-alloc")]
+pub(crate) const POOL_SIZE: usize = 8;
+
+/// The largest capacity, in bytes, of a buffer the decode pool takes back.
+/// Larger buffers are dropped instead, so that decoding one large value
+/// doesn't keep its memory alive for the rest of the call.
+#[cfg(feature = "deterministic-alloc")]
+pub(crate) const POOL_BUFFER_CAPACITY: usize = 4 * 1024;
+
+#[cfg(feature = "deterministic-alloc")]
+mod pool {
+    use super::{POOL_BUFFER_CAPACITY, POOL_SIZE};
+    use std::cell::RefCell;
+
+    thread_local! {
+        pub(super) static POOL: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// A buffer drawn from the pool, returned to it when dropped.
+    pub(super) struct Pooled(Vec<u8>);
+
+    impl std::ops::Deref for Pooled {
+        type Target = Vec<u8>;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl std::ops::DerefMut for Pooled {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            &mut self.0
+        }
+    }
+
+    impl Drop for Pooled {
+        fn drop(&mut self) {
+            let mut buf = std::mem::take(&mut self.0);
+            if buf.capacity() > POOL_BUFFER_CAPACITY {
+                return;
+            }
+
+            buf.clear();
+            POOL.with_borrow_mut(|pool| {
+                if pool.len() < POOL_SIZE {
+                    pool.push(buf);
+                }
+            });
+        }
+    }
+
+    /// Returns an empty buffer from the pool, or a new one if it is empty.
+    pub(super) fn take() -> Pooled {
+        Pooled(POOL.with_borrow_mut(Vec::pop).unwrap_or_default())
+    }
+}
+
+/// Takes back the tracked block at `ptr` as an exactly sized, immutable
+/// buffer, or returns `None` if `ptr` is null or not tracked. Prefer it over
+/// decoding into a [`Vec`] for payloads that are only read.
+///
+/// Every tracked block has a capacity equal to its length, so the conversion
+/// adopts the block as is, without reallocating or copying.
+#[must_use]
+pub fn into_boxed_bytes(ptr: HostPtr) -> Option<Box<[u8]>> {
+    into_bytes(ptr).map(|bytes| {
+        debug_assert_eq!(bytes.capacity(), bytes.len());
+        bytes.into_boxed_slice()
+    })
+}
+
+/// Copies the bytes of the tracked block at `ptr` into a new vec, or returns
+/// `None` if `ptr` is null or not tracked.
+///
+/// Unlike [`into_boxed_bytes`] and the `from_host_ptr` family, the block is
+/// neither freed nor untracked, so `ptr` stays valid. Use it for blocks whose
+/// memory is still owned by the host, which frees them itself.
+#[must_use]
+pub fn copy_bytes(ptr: HostPtr) -> Option<Vec<u8>> {
+    tracked_len(ptr).map(|len| match len {
+        0 => Vec::new(),
+        len => unsafe { std::slice::from_raw_parts(ptr as *const u8, len) }.to_vec(),
+    })
+}
+
+/// A tracked block borrowed in place. Its entry stays in the store until the
+/// guard is dropped, at which point the block is freed.
+pub(crate) struct Block {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl Block {
+    /// Returns a guard over the tracked block at `ptr`, or `None` if `ptr`
+    /// is not tracked.
+    pub(crate) fn borrow(ptr: HostPtr) -> Option<Self> {
+        let ptr = ptr as *const u8;
+        GLOBAL_STORE
+            .with_borrow(|s| s.get(&ptr).copied())
+            .map(|len| Self { ptr, len })
+    }
+}
+
+impl std::ops::Deref for Block {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        drop(into_bytes(self.ptr as HostPtr));
+    }
+}
+
+/// Returns the length of the tracked block at `ptr`, or `None` if `ptr` is
+/// not tracked, leaving the block in place.
+pub(crate) fn tracked_len(ptr: HostPtr) -> Option<usize> {
+    GLOBAL_STORE.with_borrow(|s| s.get(&(ptr as *const u8)).copied())
+}
+
+/// Returns the number of blocks currently tracked.
+#[cfg(test)]
+pub(crate) fn tracked_blocks() -> usize {
+    GLOBAL_STORE.with_borrow(HashMap::len)
+}
+
+/// Takes back ownership of a block handed out by [`alloc`].
+#[cfg(not(feature = "deterministic-alloc"))]
+#[allow(clippy::same_length_and_capacity)] // `alloc` reserves exactly `len` bytes
+fn reclaim(ptr: *mut u8, len: usize) -> Vec<u8> {
+    unsafe { std::vec::Vec::from_raw_parts(ptr, len, len) }
+}
+
+/// The arena never frees, so the bytes are copied out instead of adopted.
+#[cfg(feature = "deterministic-alloc")]
+fn reclaim(ptr: *mut u8, len: usize) -> Vec<u8> {
+    unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec()
+}
+
+/// Panics with the leaked `(ptr, len)` pairs if any block allocated with
+/// [`alloc`] is still tracked. The `#[public]` wrapper calls this once the
+/// function returns, at which point every block should have been reclaimed.
+///
+/// The check only runs with the `strict-alloc` feature and is a no-op otherwise.
+/// It also forgets the blocks freed so far, which are only remembered to
+/// catch double frees within a call.
+/// # Panics
+/// Panics if the store is not empty and `strict-alloc` is enabled.
+pub fn assert_no_leaks() {
+    #[cfg(feature = "strict-alloc")]
+    FREED.with_borrow_mut(std::collections::HashSet::clear);
+    #[cfg(feature = "strict-alloc")]
+    GLOBAL_STORE.with_borrow(|s| {
+        if !s.is_empty() {
+            let mut leaks: Vec<_> = s.iter().map(|(ptr, len)| (*ptr, *len)).collect();
+            leaks.sort_unstable();
+            panic!("leaked {} block(s) (ptr, len): {leaks:?}", leaks.len());
+        }
+    });
+}
+
+/// Frees every tracked block except `keep`, whose entry stays in the store so
+/// the host can still read it. Meant as a sweep at the end of a call, once the
+/// only block still needed is the one returned to the host. Pass a null pointer
+/// to free every block.
+pub fn dealloc_all_except(keep: *mut u8) {
+    let blocks: Vec<_> = GLOBAL_STORE.with_borrow(|s| {
+        s.keys()
+            .copied()
+            .filter(|ptr| *ptr != keep.cast_const())
+            .collect()
+    });
+
+    for ptr in blocks {
+        drop(into_bytes(ptr as HostPtr));
+    }
+}
+
+/// Releases the spare capacity of the allocation store. The store keeps the
+/// capacity it grew to after a burst of allocations, so calling this after an
+/// allocation-heavy phase lets the memory be reused.
+///
+/// This is only a hint; the store may keep more capacity than it needs.
+pub fn shrink_store() {
+    GLOBAL_STORE.with_borrow_mut(HashMap::shrink_to_fit);
 }
 
 /* memory functions ------------------------------------------- */
 /// Allocate memory into the instance of Program and return the offset to the
 /// start of the block.
 /// # Panics
-/// Panics if the pointer exceeds the maximum size of an isize or that the allocated memory is null.
+/// Panics if `len` is zero, exceeds the maximum size of an isize or if the
+/// memory cannot be allocated. Use [`try_alloc`] to handle these cases. The
+/// panic can't unwind into the host, so it aborts, which traps the instance.
 #[no_mangle]
 pub extern "C" fn alloc(len: usize) -> *mut u8 {
+    alloc_block(len)
+}
+
+/// The body of [`alloc`], which unwinds when it panics, so the panics can be
+/// tested.
+fn alloc_block(len: usize) -> *mut u8 {
     assert!(len > 0, "cannot allocate 0 sized data");
-    // can only fail if `len > isize::MAX` for u8
-    let layout = Layout::array::<u8>(len).expect("capacity overflow");
+    assert!(isize::try_from(len).is_ok(), "capacity overflow");
+
+    try_alloc(len).expect("allocation failed")
+}
+
+/// Frees the tracked block at `ptr`, for the host to release a block it
+/// allocated with [`alloc`] but no longer passes to the program.
+///
+/// It is also how the host frees a block the program handed over to it, such
+/// as the return value of a `#[public]` function: the SDK never frees those
+/// itself, so the host calls this once it has read the bytes, or the block
+/// stays allocated for the rest of the instance's life.
+///
+/// An untracked `ptr` is ignored, unless the `strict-alloc` feature is
+/// enabled, in which case freeing a pointer that [`alloc`] did not hand out,
+/// or freeing a block twice, panics.
+/// # Panics
+/// Panics if `ptr` is not tracked and `strict-alloc` is enabled, which aborts
+/// like a panic in [`alloc`].
+#[no_mangle]
+pub extern "C" fn dealloc(ptr: *mut u8) {
+    free_block(ptr);
+}
+
+/// The body of [`dealloc`], which unwinds when it panics, like
+/// [`alloc_block`].
+fn free_block(ptr: *mut u8) {
+    let freed = take_handed_over(ptr).or_else(|| into_bytes(ptr as HostPtr));
+    #[cfg(feature = "strict-alloc")]
+    assert!(
+        freed.is_some(),
+        "freed a pointer not allocated by `alloc`: {ptr:?}"
+    );
+    drop(freed);
+}
+
+/// Takes back the block at `ptr` if it was handed over to the host, reporting
+/// it as freed like [`untrack`].
+fn take_handed_over(ptr: *mut u8) -> Option<Vec<u8>> {
+    let len = HANDED_OVER.with_borrow_mut(|blocks| blocks.remove(&ptr.cast_const()))?;
+    #[cfg(feature = "strict-alloc")]
+    FREED.with_borrow_mut(|freed| freed.insert(ptr.cast_const()));
+    track_free(len);
+    observe_free(ptr, len);
+
+    Some(reclaim(ptr, len))
+}
+
+/// Like [`alloc`], but returns an error instead of panicking or aborting.
+/// # Errors
+/// Returns [`StateError::AllocationFailed`] if `len` is zero, exceeds the
+/// maximum size of an isize or if the allocator returns a null pointer.
+pub fn try_alloc(len: usize) -> Result<*mut u8, StateError> {
+    if len == 0 {
+        return Err(StateError::AllocationFailed);
+    }
+    // fails if `len > isize::MAX` for u8
+    let layout = Layout::from_size_align(len, 1).map_err(|_| StateError::AllocationFailed)?;
     // take a mutable pointer to the layout
+    #[cfg(not(feature = "deterministic-alloc"))]
     let ptr = unsafe { std::alloc::alloc(layout) };
+    #[cfg(feature = "deterministic-alloc")]
+    let ptr = arena::ARENA.with_borrow_mut(|arena| arena.alloc(layout.size()));
     if ptr.is_null() {
-        std::alloc::handle_alloc_error(layout);
+        return Err(StateError::AllocationFailed);
     }
     // keep track of the pointer and the length of the allocated data
     GLOBAL_STORE.with_borrow_mut(|s| s.insert(ptr, len));
+    #[cfg(feature = "strict-alloc")]
+    FREED.with_borrow_mut(|freed| freed.remove(&ptr.cast_const()));
+    track_alloc(len);
+    observe_alloc(ptr, len);
     // return the pointer so the runtime
     // can write data at this offset
-    ptr
+    Ok(ptr)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{alloc, into_bytes};
-    use crate::memory::GLOBAL_STORE;
-
-    #[test]
-    fn data_allocation() {
-        let len = 1024;
-        let ptr = alloc(len);
-        let vec = vec![1; len];
-        unsafe { std::ptr::copy(vec.as_ptr(), ptr, vec.len()) }
-        let val = into_bytes(ptr as i64).unwrap();
-        assert_eq!(val, vec);
-        assert!(GLOBAL_STORE.with_borrow(|s| s.get(&(ptr.cast_const())).is_none()));
+/// Moves the tracked block at `ptr` to a block of `len` bytes, keeping its
+/// first `len` bytes, and returns its new address. The block is left as it was
+/// if the allocation fails.
+fn resize(ptr: *mut u8, len: usize) -> Result<*mut u8, StateError> {
+    let old_len = GLOBAL_STORE
+        .with_borrow(|s| s.get(&ptr.cast_const()).copied())
+        .ok_or(StateError::InvalidPointer {
+            ptr: ptr as HostPtr,
+        })?;
+    let layout = Layout::from_size_align(old_len, 1).map_err(|_| StateError::AllocationFailed)?;
+    if len == 0 || isize::try_from(len).is_err() {
+        return Err(StateError::AllocationFailed);
     }
 
-    #[test]
-    #[should_panic = "cannot allocate 0 sized data"]
-    fn zero_allocation_panics() {
-        alloc(0);
+    #[cfg(not(feature = "deterministic-alloc"))]
+    let new_ptr = unsafe { std::alloc::realloc(ptr, layout, len) };
+    // the arena can't grow a block in place, so the bytes are copied over
+    #[cfg(feature = "deterministic-alloc")]
+    let new_ptr = arena::ARENA.with_borrow_mut(|arena| arena.alloc(len));
+    if new_ptr.is_null() {
+        return Err(StateError::AllocationFailed);
+    }
+    #[cfg(feature = "deterministic-alloc")]
+    unsafe {
+        std::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(len));
     }
 
-    #[test]
-    #[should_panic = "capacity overflow"]
+    GLOBAL_STORE.with_borrow_mut(|s| {
+        s.remove(&ptr.cast_const());
+        s.insert(new_ptr, len);
+    });
+    #[cfg(feature = "strict-alloc")]
+    FREED.with_borrow_mut(|freed| {
+        freed.insert(ptr.cast_const());
+        freed.remove(&new_ptr.cast_const());
+    });
+    track_resize(old_len, len);
+    observe_free(ptr, old_len);
+    observe_alloc(new_ptr, len);
+
+    Ok(new_ptr)
+}
+
+/// A [`Write`](std::io::Write) target that serializes straight into a tracked
+/// block, so a value can be handed to the host without first being written to
+/// a [`Vec`] and then copied.
+///
+/// The block is allocated on the first write and grows as needed, up to
+/// [`max_return_size`] bytes, past which writes fail.
+/// [`HostWriter::finish`] trims it to the bytes written and returns its packed
+/// [`HostPtr`]. The block is handed over to the host then, so it is no longer
+/// tracked: the program must not free it, and it is not reported by
+/// [`assert_no_leaks`]. Dropping an unfinished writer frees the block.
+pub struct HostWriter {
+    ptr: *mut u8,
+    len: usize,
+    capacity: usize,
+}
+
+impl Default for HostWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostWriter {
+    /// The capacity of the block allocated by the first write.
+    const MIN_CAPACITY: usize = 64;
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+            capacity: 0,
+        }
+    }
+
+    /// Hands the block over to the host and returns the packed [`HostPtr`] to
+    /// the bytes written. Nothing is allocated if nothing was written, in
+    /// which case the packed null pointer, `0`, is returned.
+    /// # Errors
+    /// Returns an [`StateError`] if the block cannot be trimmed or packed into
+    /// a [`HostPtr`], in which case it is freed.
+    pub fn finish(self) -> Result<HostPtr, StateError> {
+        let (ptr, len) = self.into_block()?;
+        if ptr.is_null() {
+            return Ok(0);
+        }
+
+        hand_over(ptr, len)
+    }
+
+    /// Trims the block to the bytes written and returns it, leaving it tracked.
+    fn into_block(mut self) -> Result<(*mut u8, usize), StateError> {
+        if self.len > 0 && self.len < self.capacity {
+            self.ptr = resize(self.ptr, self.len)?;
+            self.capacity = self.len;
+        }
+        let block = (self.ptr, self.len);
+        // ownership passes to the store
+        self.ptr = std::ptr::null_mut();
+
+        Ok(block)
+    }
+
+    fn reserve(&mut self, additional: usize) -> Result<(), StateError> {
+        let required = self
+            .len
+            .checked_add(additional)
+            .ok_or(StateError::AllocationFailed)?;
+        if required <= self.capacity {
+            return Ok(());
+        }
+        // the bytes could never be handed to the host
+        check_return_size(required)?;
+
+        let capacity = required.max(self.capacity * 2).max(Self::MIN_CAPACITY);
+        self.ptr = if self.ptr.is_null() {
+            try_alloc(capacity)?
+        } else {
+            resize(self.ptr, capacity)?
+        };
+        self.capacity = capacity;
+
+        Ok(())
+    }
+}
+
+impl std::io::Write for HostWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.reserve(buf.len())
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::OutOfMemory))?;
+        unsafe { std::ptr::copy_nonoverlapping(buf.as_ptr(), self.ptr.add(self.len), buf.len()) };
+        self.len += buf.len();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for HostWriter {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            drop(into_bytes(self.ptr as HostPtr));
+        }
+    }
+}
+
+/// A cursor over a buffer of fixed-size fields packed back to back, as
+/// returned by host imports that return several values at once. Integers are
+/// little-endian. Every read advances the cursor past the field it returns.
+///
+/// A read past the end of the buffer fails with
+/// [`StateError::InvalidPointer`] carrying the offset the read started at, and
+/// leaves the cursor where it was.
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    #[must_use]
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    /// Returns the next `len` bytes.
+    /// # Errors
+    /// Returns [`StateError::InvalidPointer`] if fewer than `len` bytes remain.
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let field = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.offset..end))
+            .ok_or(StateError::InvalidPointer {
+                ptr: i64::try_from(self.offset).unwrap_or(i64::MAX),
+            })?;
+        self.offset += len;
+
+        Ok(field)
+    }
+
+    /// Returns the next `u32`.
+    /// # Errors
+    /// Returns [`StateError::InvalidPointer`] if fewer than 4 bytes remain.
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    /// Returns the next `u64`.
+    /// # Errors
+    /// Returns [`StateError::InvalidPointer`] if fewer than 8 bytes remain.
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    /// Returns the next [`Address`](crate::types::Address).
+    /// # Errors
+    /// Returns [`StateError::InvalidPointer`] if fewer than
+    /// [`Address::LEN`](crate::types::Address::LEN) bytes remain.
+    pub fn read_address(&mut self) -> Result<crate::types::Address, StateError> {
+        self.read_array().map(crate::types::Address::new)
+    }
+
+    /// Returns the bytes that have not been read yet.
+    #[must_use]
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let bytes = self.read_bytes(N)?;
+        Ok(bytes.try_into().expect("read_bytes returns N bytes"))
+    }
+}
+
+/// A bump allocator over a fixed, per-thread byte arena. Handing out blocks
+/// in order from a known base makes pointer offsets reproducible, which lets
+/// tests assert on exact packing and [`GLOBAL_STORE`] contents.
+///
+/// Blocks are never returned to the arena; call [`Arena::reset`] between
+/// independent allocation sequences.
+#[cfg(any(test, feature = "deterministic-alloc"))]
+pub(crate) mod arena {
+    use std::cell::RefCell;
+
+    /// The number of bytes available to a single thread's arena.
+    pub(crate) const ARENA_SIZE: usize = 1 << 20;
+
+    thread_local! {
+        pub(crate) static ARENA: RefCell<Arena> = RefCell::new(Arena::new());
+    }
+
+    pub(crate) struct Arena {
+        buf: Box<[u8]>,
+        offset: usize,
+    }
+
+    impl Arena {
+        pub(crate) fn new() -> Self {
+            Self {
+                buf: vec![0; ARENA_SIZE].into_boxed_slice(),
+                offset: 0,
+            }
+        }
+
+        /// Returns the next `len` bytes of the arena, or null if it is exhausted.
+        pub(crate) fn alloc(&mut self, len: usize) -> *mut u8 {
+            match self.offset.checked_add(len) {
+                Some(end) if end <= self.buf.len() => {
+                    let ptr = self.buf[self.offset..].as_mut_ptr();
+                    self.offset = end;
+                    ptr
+                }
+                _ => std::ptr::null_mut(),
+            }
+        }
+
+        /// Returns the offset of `ptr` from the start of the arena.
+        #[cfg(test)]
+        pub(crate) fn offset_of(&self, ptr: *const u8) -> usize {
+            ptr as usize - self.buf.as_ptr() as usize
+        }
+
+        /// Rewinds the arena so the next allocation starts at offset 0 again.
+        #[cfg(test)]
+        pub(crate) fn reset(&mut self) {
+            self.offset = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        alloc, alloc_block,
+        arena::{Arena, ARENA_SIZE},
+        assert_aligned, copy_bytes, dealloc, dealloc_all_except, fill_block, from_host_ptr,
+        from_host_ptr_counted, from_host_ptr_enum, from_host_ptr_or_default, from_host_ptr_partial,
+        into_boxed_bytes, into_bytes, into_return_ptr, pack, read_host_return, read_into,
+        serialized_size, set_max_return_size, to_host_ptr, to_host_ptr_from_iter, tracked_blocks,
+        try_alloc, try_from_host_ptr, unpack, Decoder, HostWriter, Pointer, Variants,
+        MAX_RETURN_SIZE,
+    };
+    use crate::{
+        memory::GLOBAL_STORE,
+        random::Rng,
+        state::{Direction, Error as StateError},
+    };
+    use arbitrary::Unstructured;
+    use borsh::{BorshDeserialize, BorshSerialize};
+    use std::collections::HashMap;
+
+    /// Allocates a block like [`alloc`] and zeroes it, for tests that don't
+    /// care about its contents but still read it, which is undefined
+    /// behavior while the block is uninitialized.
+    fn alloc_zeroed(len: usize) -> *mut u8 {
+        let ptr = alloc(len);
+        unsafe { std::ptr::write_bytes(ptr, 0, len) };
+        ptr
+    }
+
+    /// Runs `check` on `cases` inputs generated from a fixed seed, so that a
+    /// failing case reproduces on every run.
+    fn fuzz(cases: usize, mut check: impl FnMut(&mut Unstructured) -> arbitrary::Result<()>) {
+        let mut rng = Rng::from_seed([7; 32]);
+        for _ in 0..cases {
+            let raw: Vec<u8> = (0..512)
+                .flat_map(|_| rng.next_u64().to_le_bytes())
+                .collect();
+            check(&mut Unstructured::new(&raw)).unwrap();
+        }
+    }
+
+    #[test]
+    fn fuzz_block_round_trip() {
+        fuzz(500, |input| {
+            let bytes: Vec<u8> = input.arbitrary()?;
+            if bytes.is_empty() {
+                return Ok(());
+            }
+
+            let ptr = alloc(bytes.len());
+            unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+            assert_eq!(into_bytes(ptr as i64).unwrap(), bytes);
+            assert_eq!(tracked_blocks(), 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn fuzz_pack_around_u32_boundary() {
+        let max = u32::MAX as usize;
+        fuzz(2_000, |input| {
+            let ptr = max - 8 + input.int_in_range(0..=16)?;
+            let len = max - 8 + input.int_in_range(0..=16)?;
+
+            match pack(ptr, len) {
+                Ok(packed) => {
+                    assert!(ptr <= max && len <= max);
+                    let packed = packed.cast_unsigned();
+                    assert_eq!(packed & u64::from(u32::MAX), u64::try_from(ptr).unwrap());
+                    assert_eq!(packed >> 32, u64::try_from(len).unwrap());
+                }
+                Err(err) => {
+                    assert!(ptr > max || len > max);
+                    assert!(matches!(err, StateError::IntegerConversion));
+                }
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn data_allocation() {
+        let len = 1024;
+        let ptr = alloc(len);
+        let vec = vec![1; len];
+        unsafe { std::ptr::copy(vec.as_ptr(), ptr, vec.len()) }
+        let val = into_bytes(ptr as i64).unwrap();
+        assert_eq!(val, vec);
+        assert!(GLOBAL_STORE.with_borrow(|s| s.get(&(ptr.cast_const())).is_none()));
+    }
+
+    #[test]
+    fn arena_offsets_are_reproducible() {
+        let sequence = [1, 32, 7, 1024, 3];
+
+        let offsets = |arena: &mut Arena| {
+            sequence
+                .iter()
+                .map(|len| {
+                    let ptr = arena.alloc(*len);
+                    arena.offset_of(ptr)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut first = Arena::new();
+        let mut second = Arena::new();
+        assert_eq!(offsets(&mut first), offsets(&mut second));
+        assert_eq!(offsets(&mut Arena::new()), [0, 1, 33, 40, 1064]);
+
+        first.reset();
+        assert_eq!(offsets(&mut first), offsets(&mut Arena::new()));
+    }
+
+    #[test]
+    fn arena_exhaustion_returns_null() {
+        let mut arena = Arena::new();
+        assert!(!arena.alloc(ARENA_SIZE).is_null());
+        assert!(arena.alloc(1).is_null());
+    }
+
+    #[cfg(feature = "deterministic-alloc")]
+    #[test]
+    fn alloc_uses_arena() {
+        use crate::memory::arena::ARENA;
+
+        let run = || {
+            ARENA.with_borrow_mut(Arena::reset);
+            [16, 8, 4]
+                .map(|len| alloc(len))
+                .map(|ptr| ARENA.with_borrow(|arena| arena.offset_of(ptr)))
+        };
+
+        let offsets = run();
+        assert_eq!(offsets, [0, 16, 24]);
+        GLOBAL_STORE.with_borrow_mut(HashMap::clear);
+        assert_eq!(run(), offsets);
+        GLOBAL_STORE.with_borrow_mut(HashMap::clear);
+    }
+
+    #[cfg(feature = "deterministic-alloc")]
+    #[test]
+    fn decodes_reuse_pooled_buffers() {
+        use super::{
+            pool::{self, POOL},
+            POOL_BUFFER_CAPACITY, POOL_SIZE,
+        };
+
+        let block = |value: &u64| {
+            let bytes = borsh::to_vec(value).unwrap();
+            let ptr = alloc(bytes.len());
+            unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+            ptr as i64
+        };
+
+        assert_eq!(from_host_ptr::<u64>(block(&1)).unwrap(), 1);
+        let buffer = POOL.with_borrow(|pool| pool[0].as_ptr());
+        assert_eq!(from_host_ptr::<u64>(block(&2)).unwrap(), 2);
+        // the second decode copied into the buffer the first one returned
+        assert_eq!(
+            POOL.with_borrow(|pool| (pool.len(), pool[0].as_ptr())),
+            (1, buffer)
+        );
+
+        let held: Vec<_> = (0..=POOL_SIZE).map(|_| pool::take()).collect();
+        drop(held);
+        assert_eq!(POOL.with_borrow(Vec::len), POOL_SIZE);
+
+        let mut large = pool::take();
+        large.reserve(POOL_BUFFER_CAPACITY + 1);
+        drop(large);
+        assert_eq!(POOL.with_borrow(Vec::len), POOL_SIZE - 1);
+    }
+
+    #[test]
+    fn failed_decode_keeps_the_block_only_when_asked() {
+        let bytes = [0xff_u8; 3];
+        let block = || {
+            let ptr = alloc(bytes.len());
+            unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+            ptr as i64
+        };
+
+        // ownership passes on the call, so even a failed decode frees
+        assert!(from_host_ptr::<u64>(block()).is_err());
+        assert_eq!(tracked_blocks(), 0);
+
+        let ptr = block();
+        assert!(try_from_host_ptr::<u64>(ptr).is_err());
+        let mut read = Vec::new();
+        read_into(ptr, &mut read).unwrap();
+        assert_eq!(read, bytes);
+        assert_eq!(tracked_blocks(), 0);
+
+        let ptr = block();
+        assert!(try_from_host_ptr::<u64>(ptr).is_err());
+        assert_eq!(try_from_host_ptr::<[u8; 3]>(ptr).unwrap(), bytes);
+        assert_eq!(tracked_blocks(), 0);
+    }
+
+    #[test]
+    fn or_default_for_null_pointer() {
+        assert_eq!(from_host_ptr_or_default::<u64>(0).unwrap(), 0);
+    }
+
+    #[test]
+    fn or_default_reports_host_errors() {
+        assert!(matches!(
+            from_host_ptr_or_default::<u64>(-1),
+            Err(StateError::HostError(-1))
+        ));
+        assert!(matches!(
+            from_host_ptr_or_default::<u64>(i64::MIN),
+            Err(StateError::HostError(i64::MIN))
+        ));
+    }
+
+    #[test]
+    fn or_default_for_empty_block() {
+        let ptr = std::ptr::NonNull::<u8>::dangling().as_ptr();
+        GLOBAL_STORE.with_borrow_mut(|s| s.insert(ptr, 0));
+
+        assert_eq!(
+            from_host_ptr_or_default::<Vec<u8>>(ptr as i64).unwrap(),
+            Vec::<u8>::new()
+        );
+        assert!(GLOBAL_STORE.with_borrow(HashMap::is_empty));
+    }
+
+    #[test]
+    fn zero_length_block_is_empty() {
+        let ptr = std::ptr::NonNull::<u8>::dangling().as_ptr();
+        GLOBAL_STORE.with_borrow_mut(|s| s.insert(ptr, 0));
+        assert_eq!(into_bytes(ptr as i64), Some(Vec::new()));
+        assert!(GLOBAL_STORE.with_borrow(HashMap::is_empty));
+
+        // values with an empty encoding decode from it, others fail cleanly
+        GLOBAL_STORE.with_borrow_mut(|s| s.insert(ptr, 0));
+        assert!(from_host_ptr::<()>(ptr as i64).is_ok());
+        GLOBAL_STORE.with_borrow_mut(|s| s.insert(ptr, 0));
+        assert!(matches!(
+            from_host_ptr::<u8>(ptr as i64),
+            Err(StateError::Deserialization(_))
+        ));
+    }
+
+    #[derive(Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+    struct Transfer {
+        to: [u8; 32],
+        amount: u64,
+        memo: String,
+    }
+
+    #[test]
+    fn finished_host_writer_hands_over_its_block() {
+        let transfer = Transfer {
+            to: [3; 32],
+            amount: 42,
+            // long enough for the block to grow past its first allocation
+            memo: "x".repeat(100),
+        };
+
+        let mut writer = HostWriter::new();
+        transfer.serialize(&mut writer).unwrap();
+        // native addresses don't fit a packed pointer, in which case the block
+        // is freed; the round trip through wasm memory runs in
+        // `tests/public_function.rs`
+        match writer.finish() {
+            Ok(ptr) => {
+                let (addr, len) = unpack(ptr);
+                let bytes = unsafe { std::slice::from_raw_parts(addr as *const u8, len) }.to_vec();
+                dealloc(addr as *mut u8);
+                assert_eq!(Transfer::try_from_slice(&bytes).unwrap(), transfer);
+            }
+            Err(err) => assert!(matches!(err, StateError::IntegerConversion)),
+        }
+        assert_eq!(tracked_blocks(), 0);
+
+        assert_eq!(HostWriter::new().finish().unwrap(), 0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn stats_track_allocations() {
+        use super::{stats, MemStats};
+
+        let first = alloc_zeroed(10);
+        let second = alloc_zeroed(20);
+        drop(into_bytes(first as i64));
+        let third = alloc_zeroed(5);
+
+        assert_eq!(
+            stats(),
+            MemStats {
+                live_blocks: 2,
+                live_bytes: 25,
+                peak_bytes: 30,
+                total_allocs: 3,
+                total_frees: 1,
+            }
+        );
+
+        drop(into_bytes(second as i64));
+        drop(into_bytes(third as i64));
+        assert_eq!(stats().live_blocks, 0);
+        assert_eq!(stats().live_bytes, 0);
+        assert_eq!(stats().total_frees, 3);
+    }
+
+    #[cfg(feature = "alloc-observer")]
+    #[test]
+    fn observers_see_every_block() {
+        use super::{set_alloc_observer, set_dealloc_observer};
+        use std::cell::RefCell;
+
+        thread_local! {
+            static SEEN: RefCell<Vec<(bool, usize, usize)>> = const { RefCell::new(Vec::new()) };
+        }
+        set_alloc_observer(|ptr, len| SEEN.with_borrow_mut(|s| s.push((true, ptr as usize, len))));
+        set_dealloc_observer(|ptr, len| {
+            SEEN.with_borrow_mut(|s| s.push((false, ptr as usize, len)));
+        });
+
+        let first = alloc_zeroed(10);
+        let second = alloc_zeroed(20);
+        drop(into_bytes(first as i64));
+        drop(into_bytes(second as i64));
+
+        assert_eq!(
+            SEEN.take(),
+            [
+                (true, first as usize, 10),
+                (true, second as usize, 20),
+                (false, first as usize, 10),
+                (false, second as usize, 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn serialized_size_matches_encoding() {
+        fn check<V: BorshSerialize>(value: &V) {
+            assert_eq!(
+                serialized_size(value).unwrap(),
+                borsh::to_vec(value).unwrap().len()
+            );
+        }
+
+        check(&7_u64);
+        check(&String::from("hypersdk"));
+        check(&vec![1_u32, 2, 3]);
+        check(&Some([0_u8; 32]));
+        check(&Transfer {
+            to: [3; 32],
+            amount: 42,
+            memo: "memo".into(),
+        });
+    }
+
+    #[test]
+    fn dropped_host_writer_frees_its_block() {
+        let mut writer = HostWriter::new();
+        7_u64.serialize(&mut writer).unwrap();
+        drop(writer);
+
+        assert!(GLOBAL_STORE.with_borrow(HashMap::is_empty));
+    }
+
+    #[test]
+    fn or_default_decodes_value() {
+        let bytes = borsh::to_vec(&42_u64).unwrap();
+        let ptr = alloc(bytes.len());
+        unsafe { std::ptr::copy(bytes.as_ptr(), ptr, bytes.len()) }
+
+        assert_eq!(from_host_ptr_or_default::<u64>(ptr as i64).unwrap(), 42);
+    }
+
+    #[test]
+    fn partial_returns_remaining_bytes() {
+        let mut bytes = borsh::to_vec(&(7_u8, 42_u32)).unwrap();
+        bytes.extend(b"payload");
+        let ptr = alloc(bytes.len());
+        unsafe { std::ptr::copy(bytes.as_ptr(), ptr, bytes.len()) }
+
+        let (header, payload) = from_host_ptr_partial::<(u8, u32)>(ptr as i64).unwrap();
+        assert_eq!(header, (7, 42));
+        assert_eq!(payload, b"payload");
+    }
+
+    #[test]
+    fn counted_lengths_walk_a_stream_of_records() {
+        let mut stream = borsh::to_vec(&(7_u8, 42_u32)).unwrap();
+        stream.extend(borsh::to_vec("transfer").unwrap());
+        let ptr = alloc(stream.len());
+        unsafe { std::ptr::copy(stream.as_ptr(), ptr, stream.len()) }
+
+        let (header, header_len) = from_host_ptr_counted::<(u8, u32)>(ptr as i64, 0).unwrap();
+        assert_eq!((header, header_len), ((7, 42), 5));
+        assert_eq!(tracked_blocks(), 1);
+
+        let (name, name_len) = from_host_ptr_counted::<String>(ptr as i64, header_len).unwrap();
+        assert_eq!(name, "transfer");
+        assert_eq!(header_len + name_len, stream.len());
+        assert_eq!(tracked_blocks(), 0);
+    }
+
+    #[test]
+    fn counted_decode_frees_the_block_on_failure() {
+        let bytes = borsh::to_vec(&7_u32).unwrap();
+        let ptr = alloc(bytes.len());
+        unsafe { std::ptr::copy(bytes.as_ptr(), ptr, bytes.len()) }
+
+        assert!(from_host_ptr_counted::<u64>(ptr as i64, 0).is_err());
+        assert_eq!(tracked_blocks(), 0);
+    }
+
+    #[test]
+    fn host_returns_unpack_as_they_are_packed() {
+        assert_eq!(unpack(pack(0x1234, 16).unwrap()), (0x1234, 16));
+        assert_eq!(
+            unpack(pack(u32::MAX as usize, 1).unwrap()),
+            (u32::MAX as usize, 1)
+        );
+
+        // the round trip through a block the host allocated runs in
+        // `tests/public_function.rs`, since native addresses don't fit a
+        // packed pointer
+        assert!(read_host_return(0).unwrap().is_empty());
+        assert!(matches!(
+            read_host_return(pack(0, 4).unwrap()),
+            Err(StateError::NullPointer)
+        ));
+        assert!(matches!(
+            read_host_return(-1),
+            Err(StateError::HostError(-1))
+        ));
+
+        // untracked memory, here a static buffer, is never read
+        let bytes = [1, 2, 3];
+        if let Ok(ptr) = to_host_ptr(&bytes) {
+            assert!(matches!(
+                read_host_return(ptr),
+                Err(StateError::InvalidPointer { ptr: rejected }) if rejected == ptr
+            ));
+        }
+        let ptr = pack(0x1234, 4).unwrap();
+        assert!(matches!(
+            read_host_return(ptr),
+            Err(StateError::InvalidPointer { ptr: rejected }) if rejected == ptr
+        ));
+    }
+
+    #[test]
+    fn oversized_return_is_rejected_before_allocating() {
+        let oversized = vec![0; MAX_RETURN_SIZE + 1];
+        // the length prefix counts towards the limit
+        assert!(matches!(
+            into_return_ptr(&oversized),
+            Err(StateError::LengthTooLarge {
+                len,
+                max: MAX_RETURN_SIZE,
+                direction: Direction::Output,
+            }) if len == oversized.len() + 4
+        ));
+        assert!(matches!(
+            to_host_ptr_from_iter(oversized.iter().copied(), oversized.len()),
+            Err(StateError::LengthTooLarge { .. })
+        ));
+        assert!(std::io::Write::write_all(&mut HostWriter::new(), &oversized).is_err());
+        assert_eq!(tracked_blocks(), 0);
+
+        // arguments passed to the host are not returns
+        assert!(!matches!(
+            to_host_ptr(&oversized),
+            Err(StateError::LengthTooLarge { .. })
+        ));
+
+        set_max_return_size(8);
+        assert!(matches!(
+            into_return_ptr(&[0_u8; 9]),
+            Err(StateError::LengthTooLarge { len: 9, max: 8, .. })
+        ));
+        assert_eq!(into_return_ptr(&()).unwrap(), 0);
+    }
+
+    #[test]
+    fn returns_reuse_the_scratch_buffer() {
+        let scratch = || super::RETURN_SCRATCH.with_borrow(|s| (s.as_ptr(), s.capacity()));
+
+        // natively the copied block doesn't fit a packed pointer and is freed,
+        // but the buffer it was copied from is kept for the next return
+        let _ = into_return_ptr(&[7_u8; 100]);
+        let first = scratch();
+        let _ = into_return_ptr(&[8_u8; 50]);
+        assert_eq!(scratch(), first);
+        assert_eq!(tracked_blocks(), 0);
+
+        let _ = into_return_ptr(&vec![0_u8; 2 * super::RETAINED_SCRATCH]);
+        assert_eq!(scratch().1, 0);
+    }
+
+    #[test]
+    fn block_is_filled_from_an_iterator() {
+        let records = [(1_u32, *b"ab"), (2, *b"cd")];
+        let len = records.len() * 6;
+        let bytes = records
+            .iter()
+            .flat_map(|(id, tag)| id.to_le_bytes().into_iter().chain(*tag));
+
+        let ptr = fill_block(bytes, len).unwrap().unwrap();
+        let decoded: [(u32, [u8; 2]); 2] = from_host_ptr(ptr as i64).unwrap();
+        assert_eq!(decoded, records);
+        assert_eq!(tracked_blocks(), 0);
+    }
+
+    #[test]
+    fn iterator_block_is_handed_over() {
+        let records = [(1_u32, *b"ab"), (2, *b"cd")];
+        let bytes = records
+            .iter()
+            .flat_map(|(id, tag)| id.to_le_bytes().into_iter().chain(*tag));
+
+        // native addresses don't fit a packed pointer, in which case the block
+        // is freed; the round trip through wasm memory runs in
+        // `tests/public_function.rs`
+        match to_host_ptr_from_iter(bytes, records.len() * 6) {
+            Ok(ptr) => {
+                let (addr, len) = unpack(ptr);
+                let bytes = unsafe { std::slice::from_raw_parts(addr as *const u8, len) }.to_vec();
+                dealloc(addr as *mut u8);
+                assert_eq!(
+                    <[(u32, [u8; 2]); 2]>::try_from_slice(&bytes).unwrap(),
+                    records
+                );
+            }
+            Err(err) => assert!(matches!(err, StateError::IntegerConversion)),
+        }
+        assert_eq!(tracked_blocks(), 0);
+
+        assert_eq!(to_host_ptr_from_iter(std::iter::empty(), 0).unwrap(), 0);
+        assert!(to_host_ptr_from_iter(0..3, 4).is_err());
+        assert_eq!(tracked_blocks(), 0);
+    }
+
+    #[test]
+    fn block_length_must_match_the_iterator() {
+        assert!(matches!(
+            fill_block(0..3, 4),
+            Err(StateError::InvalidByteLength {
+                expected: 4,
+                got: 3
+            })
+        ));
+        assert!(matches!(
+            fill_block(0..10, 4),
+            Err(StateError::InvalidByteLength {
+                expected: 4,
+                got: 5
+            })
+        ));
+        assert!(matches!(
+            fill_block(0..1, 0),
+            Err(StateError::InvalidByteLength {
+                expected: 0,
+                got: 1
+            })
+        ));
+        assert!(fill_block(0..0, 0).unwrap().is_none());
+        assert_eq!(tracked_blocks(), 0);
+    }
+
+    #[test]
+    fn read_into_reuses_the_buffer() {
+        let mut buf = Vec::with_capacity(64);
+        let capacity = buf.capacity();
+
+        for record in [&b"first record"[..], b"second"] {
+            let ptr = alloc(record.len());
+            unsafe { std::ptr::copy(record.as_ptr(), ptr, record.len()) }
+            read_into(ptr as i64, &mut buf).unwrap();
+            assert_eq!(buf, record);
+        }
+
+        assert_eq!(buf.capacity(), capacity);
+        assert_eq!(tracked_blocks(), 0);
+        assert!(matches!(
+            read_into(1, &mut buf),
+            Err(StateError::InvalidPointer { ptr: 1 })
+        ));
+        assert_eq!(buf, b"second");
+    }
+
+    #[test]
+    fn boxed_bytes_keep_the_block() {
+        let ptr = alloc(4);
+        unsafe { std::ptr::copy([1_u8, 2, 3, 4].as_ptr(), ptr, 4) };
+
+        let boxed = into_boxed_bytes(ptr as i64).unwrap();
+        assert_eq!(*boxed, [1, 2, 3, 4]);
+        assert_eq!(tracked_blocks(), 0);
+        assert!(into_boxed_bytes(0).is_none());
+    }
+
+    #[test]
+    fn copied_bytes_leave_the_block() {
+        let ptr = alloc(4);
+        unsafe { std::ptr::copy([1_u8, 2, 3, 4].as_ptr(), ptr, 4) };
+
+        let mut copy = copy_bytes(ptr as i64).unwrap();
+        copy[0] = 9;
+        assert_eq!(copy, [9, 2, 3, 4]);
+        assert_eq!(tracked_blocks(), 1);
+        assert_eq!(*into_boxed_bytes(ptr as i64).unwrap(), [1, 2, 3, 4]);
+        assert!(copy_bytes(ptr as i64).is_none());
+        assert!(copy_bytes(0).is_none());
+    }
+
+    #[test]
+    fn decoder_reads_packed_fields() {
+        let address = crate::types::Address::new([9; 32]);
+        let buffer = [
+            &7_u64.to_le_bytes()[..],
+            &3_u32.to_le_bytes(),
+            address.as_bytes(),
+            b"tail",
+        ]
+        .concat();
+        let mut decoder = Decoder::new(&buffer);
+
+        assert_eq!(decoder.read_u64().unwrap(), 7);
+        assert_eq!(decoder.read_u32().unwrap(), 3);
+        assert_eq!(decoder.read_address().unwrap(), address);
+        assert_eq!(decoder.read_bytes(2).unwrap(), b"ta");
+        assert!(matches!(
+            decoder.read_u32(),
+            Err(StateError::InvalidPointer { ptr: 46 })
+        ));
+        // a failed read doesn't move the cursor
+        assert_eq!(decoder.remaining(), b"il");
+        assert!(decoder.read_bytes(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn null_pointer_is_not_a_lookup_miss() {
+        assert!(matches!(
+            from_host_ptr::<u32>(0),
+            Err(StateError::NullPointer)
+        ));
+        assert!(matches!(
+            from_host_ptr::<u32>(1),
+            Err(StateError::InvalidPointer { ptr: 1 })
+        ));
+        assert_eq!(from_host_ptr_or_default::<u32>(0).unwrap(), 0);
+        assert!(into_bytes(0).is_none());
+    }
+
+    #[test]
+    fn unknown_enum_variant_is_reported() {
+        #[derive(Debug, BorshDeserialize)]
+        enum Selector {
+            Transfer,
+            Approve,
+        }
+
+        impl Variants for Selector {
+            const COUNT: u8 = 2;
+        }
+
+        let ptr = alloc(1);
+        unsafe { *ptr = 1 };
+        assert!(matches!(
+            from_host_ptr_enum::<Selector>(ptr as i64),
+            Ok(Selector::Approve)
+        ));
+
+        let ptr = alloc(1);
+        unsafe { *ptr = 5 };
+        assert!(matches!(
+            from_host_ptr_enum::<Selector>(ptr as i64),
+            Err(StateError::UnknownVariant { index: 5 })
+        ));
+
+        // other malformed input, here a trailing byte, is still reported as such
+        let ptr = alloc(2);
+        unsafe { std::ptr::copy([0_u8, 0].as_ptr(), ptr, 2) };
+        assert!(matches!(
+            from_host_ptr_enum::<Selector>(ptr as i64),
+            Err(StateError::Deserialization(_))
+        ));
+    }
+
+    #[test]
+    fn partial_rejects_truncated_header() {
+        let ptr = alloc_zeroed(3);
+
+        assert!(matches!(
+            from_host_ptr_partial::<u64>(ptr as i64),
+            Err(StateError::Deserialization(_))
+        ));
+    }
+
+    #[test]
+    fn misaligned_pointer_is_rejected() {
+        let buf = [0_u64; 2];
+        let aligned = buf.as_ptr().cast::<u8>();
+
+        assert!(assert_aligned::<u64>(aligned).is_ok());
+        assert!(matches!(
+            assert_aligned::<u64>(aligned.wrapping_add(1)),
+            Err(StateError::Misaligned)
+        ));
+        // bytes are always aligned
+        assert!(assert_aligned::<u8>(aligned.wrapping_add(1)).is_ok());
+    }
+
+    #[test]
+    fn or_default_rejects_malformed_value() {
+        // too short for a u64
+        let ptr = alloc_zeroed(3);
+
+        assert!(matches!(
+            from_host_ptr_or_default::<u64>(ptr as i64),
+            Err(StateError::Deserialization(_))
+        ));
+    }
+
+    #[cfg(feature = "strict-alloc")]
+    #[test]
+    #[should_panic = "leaked 1 block(s)"]
+    fn leaked_block_is_reported() {
+        let ptr = alloc_zeroed(8);
+        let _ = into_bytes(alloc_zeroed(4) as i64);
+        assert!(!ptr.is_null());
+
+        super::assert_no_leaks();
+    }
+
+    #[test]
+    fn dealloc_frees_the_block() {
+        let ptr = alloc_zeroed(8);
+        dealloc(ptr);
+        assert_eq!(tracked_blocks(), 0);
+    }
+
+    // native addresses don't fit a packed pointer, so these hand blocks over
+    // without packing them
+    #[test]
+    fn the_host_frees_handed_over_blocks() {
+        let ptr = alloc_zeroed(8);
+        super::give_to_host(ptr);
+        assert_eq!(tracked_blocks(), 0);
+        assert_eq!(
+            super::HANDED_OVER.with_borrow(|blocks| blocks.get(&ptr.cast_const()).copied()),
+            Some(8)
+        );
+
+        dealloc(ptr);
+        assert!(super::HANDED_OVER.with_borrow(HashMap::is_empty));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn handed_over_blocks_are_live_until_the_host_frees_them() {
+        use super::stats;
+
+        let ptr = alloc_zeroed(8);
+        super::give_to_host(ptr);
+        assert_eq!((stats().live_blocks, stats().total_frees), (1, 0));
+
+        dealloc(ptr);
+        assert_eq!((stats().live_blocks, stats().total_frees), (0, 1));
+    }
+
+    #[cfg(feature = "strict-alloc")]
+    #[test]
+    #[should_panic = "double free of the block"]
+    fn freeing_a_handed_over_block_twice_panics() {
+        let ptr = alloc_zeroed(8);
+        super::give_to_host(ptr);
+        dealloc(ptr);
+        super::free_block(ptr);
+    }
+
+    #[cfg(feature = "strict-alloc")]
+    #[test]
+    fn leak_check_forgets_the_freed_blocks() {
+        for len in 1..=16 {
+            dealloc(alloc_zeroed(len));
+        }
+        assert!(super::FREED.with_borrow(|freed| !freed.is_empty()));
+
+        super::assert_no_leaks();
+        assert!(super::FREED.with_borrow(std::collections::HashSet::is_empty));
+    }
+
+    #[cfg(feature = "strict-alloc")]
+    #[test]
+    #[should_panic = "freed a pointer not allocated by `alloc`"]
+    fn dealloc_of_unknown_pointer_panics() {
+        let mut local = [0_u8; 4];
+        super::free_block(local.as_mut_ptr());
+    }
+
+    #[cfg(feature = "strict-alloc")]
+    #[test]
+    #[should_panic = "double free of the block"]
+    fn double_free_panics() {
+        let ptr = alloc_zeroed(8);
+        dealloc(ptr);
+        super::free_block(ptr);
+    }
+
+    #[cfg(feature = "strict-alloc")]
+    #[test]
+    #[should_panic = "double free of the block"]
+    fn reading_a_freed_block_panics() {
+        let ptr = alloc_zeroed(8);
+        let _ = from_host_ptr::<u32>(ptr as i64);
+        let _ = from_host_ptr::<u32>(ptr as i64);
+    }
+
+    #[test]
+    fn sweep_keeps_only_the_returned_block() {
+        let scratch = [alloc_zeroed(8), alloc_zeroed(16)];
+        let returned = alloc(4);
+        unsafe { std::ptr::copy([1_u8, 2, 3, 4].as_ptr(), returned, 4) };
+        let more_scratch = alloc_zeroed(32);
+
+        dealloc_all_except(returned);
+
+        GLOBAL_STORE.with_borrow(|s| {
+            assert_eq!(s.len(), 1);
+            assert_eq!(s.get(&returned.cast_const()), Some(&4));
+            for ptr in scratch.iter().chain([&more_scratch]) {
+                assert!(!s.contains_key(&ptr.cast_const()));
+            }
+        });
+        assert_eq!(into_bytes(returned as i64), Some(vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn packed_pointer_keeps_the_address() {
+        let host_ptr = 0x1234_i64 | (16 << 32);
+
+        assert_eq!(
+            <*const u8>::from(Pointer::from_packed(host_ptr)) as usize,
+            0x1234
+        );
+        assert_eq!(
+            <*const u8>::from(Pointer::from(host_ptr)) as usize,
+            0x10_0000_1234
+        );
+    }
+
+    #[test]
+    fn shrink_store_releases_capacity() {
+        let ptrs: Vec<_> = (0..1024).map(|_| alloc(1)).collect();
+        let grown = GLOBAL_STORE.with_borrow(HashMap::capacity);
+
+        for ptr in ptrs {
+            let _ = into_bytes(ptr as i64);
+        }
+
+        super::shrink_store();
+        assert!(GLOBAL_STORE.with_borrow(HashMap::capacity) < grown);
+    }
+
+    #[test]
+    #[should_panic = "cannot allocate 0 sized data"]
+    fn zero_allocation_panics() {
+        alloc_block(0);
+    }
+
+    #[test]
+    #[should_panic = "capacity overflow"]
     fn big_allocation_fails() {
         // see https://doc.rust-lang.org/1.77.2/std/alloc/struct.Layout.html#method.array
-        alloc(isize::MAX as usize + 1);
-    }
-
-    // TODO these two tests make the code abort and not panic, it's hard to write an assertion here
-    // #[test]
-    // #[should_panic]
-    // fn two_big_allocation_fails() {
-    //     // see https://doc.rust-lang.org/1.77.2/std/alloc/struct.Layout.html#method.array
-    //     alloc((isize::MAX / 2) as usize + 1);
-    //     alloc((isize::MAX / 2) as usize + 1);
-    // }
-
-    //     #[test]
-    //     #[should_panic]
-    //     fn null_pointer_allocation() {
-    //         // see https://doc.rust-lang.org/1.77.2/std/alloc/struct.Layout.html#method.array
-    //         alloc(isize::MAX as usize);
-    //     }
+        alloc_block(isize::MAX as usize + 1);
+    }
+
+    #[test]
+    fn two_big_allocations_fail() {
+        let len = (isize::MAX / 2) as usize + 1;
+        // together they exceed the address space, so at least one must fail
+        let first = try_alloc(len);
+        let second = try_alloc(len);
+
+        assert!(
+            matches!(first, Err(StateError::AllocationFailed))
+                || matches!(second, Err(StateError::AllocationFailed))
+        );
+    }
+
+    #[test]
+    #[should_panic = "allocation failed"]
+    fn null_pointer_allocation() {
+        alloc_block(isize::MAX as usize);
+    }
+
+    #[test]
+    fn try_alloc_rejects_invalid_lengths() {
+        assert!(matches!(try_alloc(0), Err(StateError::AllocationFailed)));
+        assert!(matches!(
+            try_alloc(isize::MAX as usize + 1),
+            Err(StateError::AllocationFailed)
+        ));
+    }
+}
+
+/// Checks the packing of [`HostPtr`]s against a fixture shared with the host,
+/// whose own tests decode the same values, so a change to the layout on either
+/// side fails a test instead of corrupting every call across the boundary.
+#[cfg(test)]
+mod conformance {
+    use super::{pack, Pointer};
+
+    const GOLDEN: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../tests/fixture/host_ptr.golden"
+    );
+
+    struct Case<'a> {
+        name: &'a str,
+        offset: u32,
+        len: u32,
+        packed: i64,
+    }
+
+    fn parse(line: &str) -> Case<'_> {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let hex = |field: &str| u64::from_str_radix(field.trim_start_matches("0x"), 16).unwrap();
+        let [name, offset, len, packed] = fields[..] else {
+            panic!("malformed golden line: {line}");
+        };
+
+        Case {
+            name,
+            offset: u32::try_from(hex(offset)).unwrap(),
+            len: u32::try_from(hex(len)).unwrap(),
+            packed: hex(packed).cast_signed(),
+        }
+    }
+
+    /// Rewrites the packed column from this side's packing when
+    /// `UPDATE_GOLDEN` is set, which should only be needed to add a case.
+    fn update(golden: &str) {
+        let lines: Vec<_> = golden
+            .lines()
+            .map(|line| {
+                if line.starts_with('#') {
+                    return line.to_owned();
+                }
+                let case = parse(line);
+                let packed = pack(case.offset as usize, case.len as usize).unwrap();
+                let (offset, len) = (case.offset, case.len);
+                format!("{} {offset:#010x} {len:#010x} {packed:016x}", case.name)
+            })
+            .collect();
+        std::fs::write(GOLDEN, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn packing_matches_the_golden_file() {
+        let golden = std::fs::read_to_string(GOLDEN).unwrap();
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            update(&golden);
+        }
+
+        let cases: Vec<_> = golden
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(parse)
+            .collect();
+        for name in ["empty", "single_byte", "near_max_len"] {
+            assert!(
+                cases.iter().any(|case| case.name == name),
+                "{name} is missing"
+            );
+        }
+
+        for case in cases {
+            let packed = pack(case.offset as usize, case.len as usize).unwrap();
+            assert_eq!(packed, case.packed, "{}", case.name);
+
+            // decoded the way the host does: the high half, then the low half
+            let bits = case.packed.cast_unsigned();
+            assert_eq!(bits >> 32, u64::from(case.len), "{}", case.name);
+            assert_eq!(
+                bits & u64::from(u32::MAX),
+                u64::from(case.offset),
+                "{}",
+                case.name
+            );
+            assert_eq!(
+                <*const u8>::from(Pointer::from_packed(case.packed)) as usize,
+                case.offset as usize,
+                "{}",
+                case.name
+            );
+        }
+    }
 }
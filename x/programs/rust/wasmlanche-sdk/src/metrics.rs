@@ -0,0 +1,264 @@
+//! Instrumentation of the calls the SDK makes to the host.
+
+use std::cell::RefCell;
+
+/// Receives a notification for every host import the SDK calls.
+pub trait MetricsSink {
+    /// Called right before the import `name` (for example `state.get`) is
+    /// called with `bytes` bytes of keys, values or events.
+    fn on_host_call(&self, name: &str, bytes: usize);
+}
+
+thread_local! {
+    static SINK: RefCell<Option<Box<dyn MetricsSink>>> = const { RefCell::new(None) };
+}
+
+#[cfg(feature = "trace")]
+thread_local! {
+    static TRACE: RefCell<Vec<(String, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the name and byte length of every host call recorded on the current
+/// thread since the last call, in order, and clears the trace. Calls are
+/// recorded whether or not a sink is installed.
+#[cfg(feature = "trace")]
+#[must_use]
+pub fn take_trace() -> Vec<(String, usize)> {
+    TRACE.take()
+}
+
+/// Installs `sink` for the current thread, replacing the previous one. No sink
+/// is installed by default, in which case recording a call is a no-op.
+///
+/// The sink must not call [`set_sink`] or [`clear_sink`] itself.
+pub fn set_sink<S>(sink: S)
+where
+    S: MetricsSink + 'static,
+{
+    SINK.with_borrow_mut(|current| *current = Some(Box::new(sink)));
+}
+
+/// Removes the sink installed for the current thread, if any.
+pub fn clear_sink() {
+    SINK.with_borrow_mut(|current| *current = None);
+}
+
+/// Notifies the installed sink, if any, of a host call, then makes the call
+/// by running `call`. With the `tracing` feature, `call` runs inside a
+/// `host_call` span with the `import` name and the `bytes`. Pass only the
+/// import to `call`, so that the span does not cover decoding its result.
+pub(crate) fn record<R>(name: &str, bytes: usize, call: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "trace")]
+    TRACE.with_borrow_mut(|trace| trace.push((name.to_owned(), bytes)));
+
+    SINK.with_borrow(|sink| {
+        if let Some(sink) = sink {
+            sink.on_host_call(name, bytes);
+        }
+    });
+
+    #[cfg(feature = "tracing")]
+    let _span = tracing::trace_span!("host_call", import = name, bytes).entered();
+    call()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clear_sink, set_sink, MetricsSink};
+    use crate::{
+        program::Program,
+        state::{Key, State},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[derive(Clone, Default)]
+    struct Recorder(Rc<RefCell<Vec<(String, usize)>>>);
+
+    impl MetricsSink for Recorder {
+        fn on_host_call(&self, name: &str, bytes: usize) {
+            self.0.borrow_mut().push((name.to_owned(), bytes));
+        }
+    }
+
+    #[test]
+    fn state_crossings_are_recorded() {
+        let recorder = Recorder::default();
+        set_sink(recorder.clone());
+
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        state.get::<u64>(Key::new(vec![0])).unwrap();
+        // cached, so no crossing
+        state.get::<u64>(Key::new(vec![0])).unwrap();
+        state.store(Key::new(vec![1]), &42_u64).unwrap();
+        state.delete(Key::new(vec![2])).unwrap();
+        drop(state);
+        clear_sink();
+
+        assert_eq!(
+            *recorder.0.borrow(),
+            [
+                ("state.get".to_owned(), 1),
+                ("state.delete".to_owned(), 1),
+                // the key and the 8 byte value
+                ("state.put".to_owned(), 9),
+            ]
+        );
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn trace_records_host_calls_in_order() {
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        state.get::<u64>(Key::new(vec![0])).unwrap();
+        state.delete(Key::new(vec![0, 1])).unwrap();
+        state.store(Key::new(vec![2]), &1_u8).unwrap();
+        drop(state);
+
+        assert_eq!(
+            super::take_trace(),
+            [
+                ("state.get".to_owned(), 1),
+                ("state.delete".to_owned(), 2),
+                ("state.put".to_owned(), 2),
+            ]
+        );
+        assert!(super::take_trace().is_empty());
+    }
+
+    #[cfg(all(feature = "trace", feature = "unstable"))]
+    #[test]
+    fn transactions_trace_their_version_checks() {
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        let mut transaction = state.transaction().unwrap();
+        transaction.get::<u64>(Key::new(vec![0])).unwrap();
+        transaction.store(Key::new(vec![1]), &1_u8).unwrap();
+        transaction.commit().unwrap();
+        drop(state);
+
+        let imports: Vec<_> = super::take_trace()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            imports,
+            [
+                "state.version",
+                "state.get",
+                "state.check_versions",
+                "state.put"
+            ]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn host_calls_are_wrapped_in_spans() {
+        use std::{
+            fmt::Write,
+            sync::{Arc, Mutex},
+        };
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        #[derive(Clone, Default)]
+        struct Spans(Arc<Mutex<Vec<String>>>);
+
+        struct Fields(String);
+
+        impl Visit for Fields {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                write!(self.0, " {field}={value}").unwrap();
+            }
+
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                write!(self.0, " {field}={value}").unwrap();
+            }
+
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                write!(self.0, " {field}={value:?}").unwrap();
+            }
+        }
+
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+                let mut fields = Fields(span.metadata().name().to_owned());
+                span.record(&mut fields);
+                let mut spans = self.0.lock().unwrap();
+                spans.push(fields.0);
+                let opened = spans.iter().filter(|s| s.starts_with("host_call")).count();
+                span::Id::from_u64(u64::try_from(opened).unwrap())
+            }
+
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, _: &Event<'_>) {}
+
+            fn enter(&self, span: &span::Id) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("enter {}", span.into_u64()));
+            }
+
+            fn exit(&self, span: &span::Id) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("exit {}", span.into_u64()));
+            }
+        }
+
+        let spans = Spans::default();
+        tracing::subscriber::with_default(spans.clone(), || {
+            let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+            state.get::<u64>(Key::new(vec![0])).unwrap();
+            state.delete(Key::new(vec![0, 1])).unwrap();
+            state.store(Key::new(vec![2]), &1_u8).unwrap();
+            drop(state);
+            // every binding is traced, not only `state`
+            #[cfg(feature = "unstable")]
+            let _ = crate::time::Timestamp::now();
+        });
+
+        #[cfg_attr(not(feature = "unstable"), allow(unused_mut))]
+        let mut expected = vec![
+            "host_call import=state.get bytes=1",
+            "enter 1",
+            "exit 1",
+            "host_call import=state.delete bytes=2",
+            "enter 2",
+            "exit 2",
+            "host_call import=state.put bytes=2",
+            "enter 3",
+            "exit 3",
+        ];
+        #[cfg(feature = "unstable")]
+        expected.extend([
+            "host_call import=context.timestamp bytes=0",
+            "enter 4",
+            "exit 4",
+        ]);
+        assert_eq!(*spans.0.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn nothing_is_recorded_without_a_sink() {
+        let recorder = Recorder::default();
+        set_sink(recorder.clone());
+        clear_sink();
+
+        State::<Key>::new(Program::new([1; Program::LEN]))
+            .get::<u64>(Key::new(vec![0]))
+            .unwrap();
+
+        assert!(recorder.0.borrow().is_empty());
+    }
+}
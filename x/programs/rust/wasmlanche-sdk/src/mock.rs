@@ -0,0 +1,803 @@
+//! An in-process stand-in for the host used by the unit tests. It mirrors the
+//! behaviour of the host imports closely enough that the bindings can be
+//! exercised without a VM, and records every call so tests can assert on the
+//! number of host crossings.
+
+// most mocks of the `unstable` imports are unused without the feature
+#![cfg_attr(not(feature = "unstable"), allow(dead_code, unused_imports))]
+
+use crate::{program::Program, types::Address};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+};
+
+/// A function of a deployed program, called with the program whose storage it
+/// runs against and the serialized arguments. Returns the serialized result.
+type Function = Rc<dyn Fn(Program, &[u8]) -> Vec<u8>>;
+
+/// A host function callable by name, taking and returning raw bytes.
+type Handler = Rc<dyn Fn(&[u8]) -> Vec<u8>>;
+
+thread_local! {
+    static HOST: RefCell<MockHost> = RefCell::new(MockHost::default());
+}
+
+#[derive(Default)]
+pub(crate) struct MockHost {
+    /// Persistent storage keyed by the program id followed by the key.
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// The number of times each storage key has been written or deleted.
+    versions: HashMap<Vec<u8>, u64>,
+    /// The number of entries returned by `state.paginate`.
+    paged: usize,
+    /// The `module.name` of every import called, in order.
+    calls: Vec<&'static str>,
+    /// Every event emitted, in order.
+    events: Vec<Vec<u8>>,
+    /// Every log message recorded and its level, in order.
+    messages: Vec<(u8, String)>,
+    /// The native amount attached to the current call.
+    value: u64,
+    /// The number of calls the current one is nested in.
+    depth: u32,
+    /// The units every metered call consumes.
+    call_units: u64,
+    /// The timestamp of the current block.
+    timestamp: u64,
+    /// The account or program that made the current call.
+    caller: Address,
+    /// The id of the chain the program is deployed on.
+    chain_id: [u8; 32],
+    /// The number of bytes the transaction may still write. Every put uses
+    /// up the length of its key and value.
+    storage_quota: u64,
+    /// The native balance of each program.
+    balances: HashMap<[u8; Program::LEN], u64>,
+    /// The hash of every block, indexed by height. The last one is the current
+    /// block.
+    blocks: Vec<[u8; 32]>,
+    /// The functions of deployed programs, keyed by code address and name.
+    code: HashMap<([u8; Address::LEN], String), Function>,
+    /// The host functions callable by name.
+    handlers: HashMap<String, Handler>,
+    /// The address returned for every registered public key. Other keys are
+    /// invalid.
+    accounts: HashMap<Vec<u8>, Address>,
+    /// The imports that fail without touching storage.
+    failing: Vec<&'static str>,
+}
+
+impl MockHost {
+    /// Returns the number of times the import `name` has been called.
+    pub(crate) fn calls(&self, name: &str) -> usize {
+        self.calls.iter().filter(|call| **call == name).count()
+    }
+
+    /// Returns the serialized events emitted so far.
+    pub(crate) fn events(&self) -> &[Vec<u8>] {
+        &self.events
+    }
+
+    /// Returns the number of entries `state.paginate` has returned so far.
+    pub(crate) fn paged(&self) -> usize {
+        self.paged
+    }
+
+    /// Records a write to the storage key `key`.
+    fn bump(&mut self, key: Vec<u8>) {
+        *self.versions.entry(key).or_default() += 1;
+    }
+
+    /// Returns the log messages recorded so far and their levels.
+    pub(crate) fn messages(&self) -> &[(u8, String)] {
+        &self.messages
+    }
+
+    pub(crate) fn set_call_units(&mut self, units: u64) {
+        self.call_units = units;
+    }
+
+    pub(crate) fn set_value(&mut self, value: u64) {
+        self.value = value;
+    }
+
+    /// Deploys `function` as `name` of the program at `code`.
+    pub(crate) fn deploy(
+        &mut self,
+        code: Address,
+        name: &str,
+        function: impl Fn(Program, &[u8]) -> Vec<u8> + 'static,
+    ) {
+        let code = code
+            .as_bytes()
+            .try_into()
+            .expect("addresses are fixed size");
+        self.code.insert((code, name.to_owned()), Rc::new(function));
+    }
+
+    /// Makes `address` the address returned for `public_key`. The mock
+    /// derives nothing itself.
+    pub(crate) fn add_account(&mut self, public_key: &[u8], address: Address) {
+        self.accounts.insert(public_key.to_vec(), address);
+    }
+
+    /// Registers `handler` as the host function `name`.
+    pub(crate) fn register(&mut self, name: &str, handler: impl Fn(&[u8]) -> Vec<u8> + 'static) {
+        self.handlers.insert(name.to_owned(), Rc::new(handler));
+    }
+
+    /// Makes every later call to the import `name` fail.
+    pub(crate) fn fail(&mut self, name: &'static str) {
+        self.failing.push(name);
+    }
+
+    pub(crate) fn set_call_depth(&mut self, depth: u32) {
+        self.depth = depth;
+    }
+
+    pub(crate) fn set_chain_id(&mut self, chain_id: [u8; 32]) {
+        self.chain_id = chain_id;
+    }
+
+    pub(crate) fn set_caller(&mut self, caller: Address) {
+        self.caller = caller;
+    }
+
+    pub(crate) fn set_balance(&mut self, program: [u8; Program::LEN], balance: u64) {
+        self.balances.insert(program, balance);
+    }
+
+    /// Appends a block with `hash` to the chain.
+    pub(crate) fn push_block(&mut self, hash: [u8; 32]) {
+        self.blocks.push(hash);
+    }
+
+    pub(crate) fn set_storage_quota(&mut self, quota: u64) {
+        self.storage_quota = quota;
+    }
+
+    pub(crate) fn set_timestamp(&mut self, timestamp: u64) {
+        self.timestamp = timestamp;
+    }
+}
+
+/// Runs `f` against this thread's mock host.
+pub(crate) fn with<R>(f: impl FnOnce(&mut MockHost) -> R) -> R {
+    HOST.with_borrow_mut(f)
+}
+
+/// Copies `bytes` into a newly tracked block, the way the host writes return
+/// values into program memory, and returns its pointer.
+fn write_bytes(bytes: &[u8]) -> i64 {
+    let ptr = crate::memory::alloc(bytes.len());
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+    ptr as i64
+}
+
+/// Runs a callee one call deeper than its caller, or returns `None` without
+/// running it if that would exceed [`MAX_CALL_DEPTH`].
+///
+/// [`MAX_CALL_DEPTH`]: crate::context::MAX_CALL_DEPTH
+fn nested<R>(callee: impl FnOnce() -> R) -> Option<R> {
+    let depth = with(|host| host.depth);
+    if depth >= crate::context::MAX_CALL_DEPTH {
+        return None;
+    }
+
+    with(|host| host.depth = depth + 1);
+    let result = callee();
+    with(|host| host.depth = depth);
+    Some(result)
+}
+
+fn storage_key(caller: &Program, key: &[u8]) -> Vec<u8> {
+    caller.id().iter().chain(key).copied().collect()
+}
+
+/// Mock implementations of the `state` imports.
+// the signatures mirror the real bindings, including their fallibility
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) mod state {
+    use super::{storage_key, with, write_bytes};
+    use crate::{
+        program::Program,
+        state::{Error, Key},
+    };
+    use borsh::BorshSerialize;
+    use std::ops::Bound;
+
+    pub(crate) unsafe fn put_bytes<V>(caller: &Program, key: &Key, value: &V) -> Result<(), Error>
+    where
+        V: BorshSerialize,
+    {
+        let value = borsh::to_vec(value).map_err(|_| Error::Serialization)?;
+        with(|host| {
+            host.calls.push("state.put");
+            let written = (key.len() + value.len()) as u64;
+            host.storage_quota = host.storage_quota.saturating_sub(written);
+            host.bump(storage_key(caller, key));
+            host.storage.insert(storage_key(caller, key), value);
+        });
+
+        Ok(())
+    }
+
+    pub(crate) unsafe fn get_bytes(caller: &Program, key: &Key) -> Result<i64, Error> {
+        let value = with(|host| {
+            host.calls.push("state.get");
+            host.storage.get(&storage_key(caller, key)).cloned()
+        });
+
+        Ok(value.map_or(-1, |value| write_bytes(&value)))
+    }
+
+    pub(crate) unsafe fn len_bytes(caller: &Program, key: &Key) -> Result<i64, Error> {
+        let len = with(|host| {
+            host.calls.push("state.len");
+            // values are stored as the serialized bytes they were put with
+            host.storage.get(&storage_key(caller, key)).map(|value| {
+                let value: Vec<u8> = borsh::from_slice(value).expect("stored values are vecs");
+                value.len()
+            })
+        });
+
+        Ok(len.map_or(-1, |len| i64::try_from(len).expect("values fit an i64")))
+    }
+
+    pub(crate) unsafe fn swap_bytes<V>(caller: &Program, key: &Key, value: &V) -> Result<i64, Error>
+    where
+        V: BorshSerialize,
+    {
+        let value = borsh::to_vec(value).map_err(|_| Error::Serialization)?;
+        let old = with(|host| {
+            host.calls.push("state.swap");
+            host.bump(storage_key(caller, key));
+            host.storage.insert(storage_key(caller, key), value)
+        });
+
+        Ok(old.map_or(-1, |old| write_bytes(&old)))
+    }
+
+    pub(crate) unsafe fn take_bytes(caller: &Program, key: &Key) -> Result<i64, Error> {
+        let old = with(|host| {
+            host.calls.push("state.take");
+            if host.failing.contains(&"state.take") {
+                return Err(());
+            }
+            host.bump(storage_key(caller, key));
+            Ok(host.storage.remove(&storage_key(caller, key)))
+        });
+
+        // any negative value other than -1 reports a host error
+        Ok(old.map_or(-2, |old| old.map_or(-1, |old| write_bytes(&old))))
+    }
+
+    pub(crate) unsafe fn paginate_bytes(
+        caller: &Program,
+        prefix: &[u8],
+        start_after: Option<&[u8]>,
+        limit: u32,
+    ) -> Result<i64, Error> {
+        let page = with(|host| {
+            host.calls.push("state.paginate");
+            let program = caller.id().len();
+            let prefix = storage_key(caller, prefix);
+            let start = start_after.map_or(Bound::Included(prefix.clone()), |key| {
+                Bound::Excluded(storage_key(caller, key))
+            });
+
+            let mut entries = host
+                .storage
+                .range((start, Bound::Unbounded))
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, value)| {
+                    // values are stored as the serialized bytes they were put with
+                    let value: Vec<u8> = borsh::from_slice(value).expect("stored values are vecs");
+                    (key[program..].to_vec(), value)
+                });
+            let page: Vec<_> = entries.by_ref().take(limit as usize).collect();
+            host.paged += page.len();
+            let next = match (page.last(), entries.next()) {
+                (Some((last, _)), Some(_)) => Some(last.clone()),
+                _ => None,
+            };
+            (page, next)
+        });
+
+        Ok(write_bytes(
+            &borsh::to_vec(&page).map_err(|_| Error::Serialization)?,
+        ))
+    }
+
+    pub(crate) unsafe fn version(caller: &Program, key: &Key) -> Result<u64, Error> {
+        Ok(with(|host| {
+            host.calls.push("state.version");
+            host.versions
+                .get(&storage_key(caller, key))
+                .copied()
+                .unwrap_or_default()
+        }))
+    }
+
+    pub(crate) unsafe fn check_versions(caller: &Program, reads: &[u8]) -> Result<bool, Error> {
+        let reads: Vec<(Vec<u8>, u64)> =
+            borsh::from_slice(reads).map_err(|err| Error::from_borsh(&err))?;
+        Ok(with(|host| {
+            host.calls.push("state.check_versions");
+            reads.iter().all(|(key, version)| {
+                let current = host.versions.get(&storage_key(caller, key));
+                current.copied().unwrap_or_default() == *version
+            })
+        }))
+    }
+
+    pub(crate) unsafe fn delete_bytes(caller: &Program, key: &Key) -> Result<(), Error> {
+        with(|host| {
+            host.calls.push("state.delete");
+            if host.failing.contains(&"state.delete") {
+                return Err(Error::Delete);
+            }
+            host.bump(storage_key(caller, key));
+            host.storage.remove(&storage_key(caller, key));
+            Ok(())
+        })
+    }
+}
+
+/// Mock implementations of the `log` imports.
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) mod log {
+    use super::with;
+    use crate::state::Error;
+
+    pub(crate) fn emit(event: &[u8]) -> Result<(), Error> {
+        with(|host| {
+            host.calls.push("log.emit");
+            host.events.push(event.to_vec());
+        });
+
+        Ok(())
+    }
+
+    pub(crate) fn message(level: u8, message: &[u8]) -> Result<(), Error> {
+        with(|host| {
+            host.calls.push("log.message");
+            let message = String::from_utf8_lossy(message).into_owned();
+            host.messages.push((level, message));
+        });
+
+        Ok(())
+    }
+
+    pub(crate) fn emit_batch(mut events: &[u8]) -> Result<(), Error> {
+        with(|host| {
+            host.calls.push("log.emit_batch");
+
+            while let Some((len, rest)) = events.split_first_chunk::<4>() {
+                let (event, rest) = rest.split_at(u32::from_be_bytes(*len) as usize);
+                host.events.push(event.to_vec());
+                events = rest;
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Mock implementations of the `context` imports.
+pub(crate) mod context {
+    use super::{with, write_bytes};
+    use crate::{context::BLOCK_HASH_WINDOW, program::Program};
+
+    pub(crate) fn value() -> u64 {
+        with(|host| {
+            host.calls.push("context.value");
+            host.value
+        })
+    }
+
+    pub(crate) fn caller() -> i64 {
+        let caller = with(|host| {
+            host.calls.push("context.caller");
+            host.caller
+        });
+
+        write_bytes(caller.as_bytes())
+    }
+
+    pub(crate) fn chain_id() -> i64 {
+        let chain_id = with(|host| {
+            host.calls.push("context.chain_id");
+            host.chain_id
+        });
+
+        write_bytes(&chain_id)
+    }
+
+    pub(crate) fn balance(program: &Program) -> u64 {
+        with(|host| {
+            host.calls.push("context.balance");
+            host.balances.get(program.id()).copied().unwrap_or_default()
+        })
+    }
+
+    pub(crate) fn block_hash(height: u64) -> i64 {
+        let hash = with(|host| {
+            host.calls.push("context.block_hash");
+            let current = host.blocks.len() as u64;
+            let in_window = height < current && current - height <= BLOCK_HASH_WINDOW;
+            in_window
+                .then(|| usize::try_from(height).ok())
+                .flatten()
+                .map(|height| host.blocks[height])
+        });
+
+        hash.map_or(-1, |hash| write_bytes(&hash))
+    }
+
+    pub(crate) fn call_depth() -> u32 {
+        with(|host| {
+            host.calls.push("context.call_depth");
+            host.depth
+        })
+    }
+
+    pub(crate) fn remaining_storage_quota() -> u64 {
+        with(|host| {
+            host.calls.push("context.remaining_storage_quota");
+            host.storage_quota
+        })
+    }
+
+    pub(crate) fn timestamp() -> u64 {
+        with(|host| {
+            host.calls.push("context.timestamp");
+            host.timestamp
+        })
+    }
+}
+
+/// Mock implementations of the `clock` imports, backed by the real clock.
+#[cfg(feature = "test-utils")]
+pub(crate) mod clock {
+    use super::with;
+    use std::time::Instant;
+
+    thread_local! {
+        static START: Instant = Instant::now();
+    }
+
+    pub(crate) fn monotonic_nanos() -> u128 {
+        with(|host| host.calls.push("clock.monotonic_nanos"));
+        START.with(Instant::elapsed).as_nanos()
+    }
+}
+
+/// Mock implementations of the `program` imports.
+#[cfg(feature = "unstable")]
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) mod program {
+    use super::{nested, with, write_bytes};
+    use crate::{
+        program::{CallSpec, Program},
+        state::Error,
+        types::Address,
+    };
+
+    pub(crate) fn delegate_call(
+        caller: &Program,
+        code: &Address,
+        function: &str,
+        args: &[u8],
+        _max_units: i64,
+    ) -> Result<i64, Error> {
+        let function = with(|host| {
+            host.calls.push("program.delegate_call");
+            let code = code
+                .as_bytes()
+                .try_into()
+                .expect("addresses are fixed size");
+            host.code.get(&(code, function.to_owned())).cloned()
+        });
+
+        // the borrow is released so the callee can use the host itself
+        Ok(function
+            .and_then(|function| nested(|| function(*caller, args)))
+            .map_or(-1, |value| write_bytes(&value)))
+    }
+
+    /// Never reached by the unit tests: the host pointers of the arguments
+    /// can't be packed natively, so `Program::call_function` fails first.
+    pub(crate) unsafe fn call_program(
+        _target: i64,
+        _function: i64,
+        _args: i64,
+        _max_units: i64,
+    ) -> i64 {
+        with(|host| host.calls.push("program.call_program"));
+        -1
+    }
+
+    pub(crate) fn call_metered(
+        target: &Program,
+        function: &str,
+        args: &[u8],
+        max_units: i64,
+    ) -> Result<i64, Error> {
+        let (function, units) = with(|host| {
+            host.calls.push("program.call_metered");
+            let function = host.code.get(&(*target.id(), function.to_owned())).cloned();
+            (function, host.call_units)
+        });
+
+        // a callee that needs more units than it is given fails
+        let Some(function) =
+            function.filter(|_| i64::try_from(units).is_ok_and(|units| units <= max_units))
+        else {
+            return Ok(-1);
+        };
+        let Some(value) = nested(|| function(*target, args)) else {
+            return Ok(-1);
+        };
+        Ok(write_bytes(
+            &borsh::to_vec(&(units, value)).map_err(|_| Error::Serialization)?,
+        ))
+    }
+
+    pub(crate) fn deploy(
+        caller: &Program,
+        code: &Address,
+        init_args: &[u8],
+        salt: &[u8],
+    ) -> Result<i64, Error> {
+        // a stand-in, the SDK leaves the derivation to the host
+        let derived = [&caller.id()[..], code.as_bytes(), salt].concat();
+        let address = super::proof::hash(&derived);
+        // the new program runs every function of its code
+        let init = with(|host| {
+            host.calls.push("program.deploy");
+            let functions: Vec<_> = host
+                .code
+                .iter()
+                .filter(|((owner, _), _)| owner == code.as_bytes())
+                .map(|((_, name), function)| (name.clone(), function.clone()))
+                .collect();
+            if functions.is_empty() || host.code.keys().any(|(owner, _)| *owner == address) {
+                return None;
+            }
+
+            let init = functions
+                .iter()
+                .find(|(name, _)| name == "init")
+                .map(|(_, init)| init.clone());
+            for (name, function) in functions {
+                host.code.insert((address, name), function);
+            }
+            Some(init)
+        });
+
+        let Some(init) = init else {
+            return Ok(-1);
+        };
+        if let Some(init) = init {
+            if nested(|| init(Program::new(address), init_args)).is_none() {
+                return Ok(-1);
+            }
+        }
+
+        Ok(write_bytes(&address))
+    }
+
+    pub(crate) fn self_destruct(caller: &Program, beneficiary: &Address) -> Result<i64, Error> {
+        with(|host| {
+            host.calls.push("program.self_destruct");
+            let id = *caller.id();
+            if !host.code.keys().any(|(owner, _)| *owner == id) {
+                return Ok(-1);
+            }
+
+            host.code.retain(|(owner, _), _| *owner != id);
+            host.storage.retain(|key, _| !key.starts_with(&id));
+            let balance = host.balances.remove(&id).unwrap_or_default();
+            let beneficiary = beneficiary
+                .as_bytes()
+                .try_into()
+                .expect("addresses are fixed size");
+            *host.balances.entry(beneficiary).or_default() += balance;
+            Ok(0)
+        })
+    }
+
+    pub(crate) fn call_batch(
+        _caller: &Program,
+        calls: &[u8],
+        _max_units: i64,
+    ) -> Result<i64, Error> {
+        let calls: Vec<CallSpec> =
+            borsh::from_slice(calls).map_err(|err| Error::from_borsh(&err))?;
+        with(|host| host.calls.push("program.call_batch"));
+
+        // each call runs against the storage of its target, and a call to a
+        // function that isn't deployed fails without affecting the others
+        let results: Vec<Option<Vec<u8>>> = calls
+            .iter()
+            .map(|call| {
+                let function = with(|host| {
+                    host.code
+                        .get(&(*call.target.id(), call.function.clone()))
+                        .cloned()
+                });
+                function.and_then(|function| nested(|| function(call.target, &call.args)))
+            })
+            .collect();
+
+        Ok(write_bytes(
+            &borsh::to_vec(&results).expect("results serialize"),
+        ))
+    }
+}
+
+/// Mock implementations of the `address` imports.
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) mod address {
+    use super::{with, write_bytes};
+    use crate::state::Error;
+
+    pub(crate) fn from_public_key(public_key: &[u8]) -> Result<i64, Error> {
+        let address = with(|host| {
+            host.calls.push("address.from_public_key");
+            host.accounts.get(public_key).copied()
+        });
+
+        Ok(address.map_or(-1, |address| write_bytes(address.as_bytes())))
+    }
+}
+
+/// Mock implementations of the `host` imports.
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) mod host {
+    use super::{with, write_bytes};
+    use crate::state::Error;
+
+    pub(crate) fn call_named(name: &str, input: &[u8]) -> Result<i64, Error> {
+        let handler = with(|host| {
+            host.calls.push("host.call_named");
+            host.handlers.get(name).cloned()
+        });
+
+        Ok(handler.map_or(-1, |handler| write_bytes(&handler(input))))
+    }
+}
+
+/// Mock implementations of the `proof` imports.
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) mod proof {
+    use super::with;
+    use crate::state::Error;
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    /// A stand-in for the chain's hash function. It is deterministic but not
+    /// collision resistant, which is all the tests need.
+    pub(crate) fn hash(bytes: &[u8]) -> [u8; 32] {
+        let mut digest = [0; 32];
+        for (i, word) in digest.chunks_exact_mut(8).enumerate() {
+            let mut hasher = DefaultHasher::new();
+            i.hash(&mut hasher);
+            bytes.hash(&mut hasher);
+            word.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+        digest
+    }
+
+    /// Verifies the proof against a binary tree whose leaves are the hashes
+    /// of the leaf values and whose nodes hash the concatenation of their
+    /// children.
+    pub(crate) fn verify_merkle(
+        root: &[u8; 32],
+        leaf: &[u8],
+        proof: &[u8],
+        mut index: u64,
+    ) -> Result<i64, Error> {
+        with(|host| host.calls.push("proof.verify_merkle"));
+
+        if !proof.len().is_multiple_of(32) {
+            return Ok(-1);
+        }
+
+        let mut node = hash(leaf);
+        for sibling in proof.chunks_exact(32) {
+            node = if index & 1 == 0 {
+                hash(&[&node, sibling].concat())
+            } else {
+                hash(&[sibling, &node].concat())
+            };
+            index >>= 1;
+        }
+
+        Ok(i64::from(index == 0 && node == *root))
+    }
+}
+
+/// Mock implementations of the `bigint` imports, with schoolbook arithmetic
+/// that is slow but easy to check.
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) mod bigint {
+    use super::{with, write_bytes};
+    use crate::state::Error;
+    use std::cmp::Ordering;
+
+    /// The bits of big-endian `bytes`, most significant first.
+    fn bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+        bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1 == 1))
+    }
+
+    /// Adds `b` to `a`, both big-endian and of the same length.
+    fn add(a: &mut [u8], b: &[u8]) {
+        let mut carry = 0;
+        for (a, b) in a.iter_mut().zip(b).rev() {
+            let sum = u16::from(*a) + u16::from(*b) + carry;
+            *a = sum.to_le_bytes()[0];
+            carry = sum >> 8;
+        }
+    }
+
+    /// Subtracts `modulus` from `a` if `a` is not less than it.
+    fn reduce(a: &mut [u8], modulus: &[u8]) {
+        if (*a).cmp(modulus) == Ordering::Less {
+            return;
+        }
+
+        let mut borrow = 0;
+        for (a, m) in a.iter_mut().zip(modulus).rev() {
+            let diff = i16::from(*a) - i16::from(*m) - borrow;
+            borrow = i16::from(diff < 0);
+            *a = diff.rem_euclid(256).to_le_bytes()[0];
+        }
+    }
+
+    /// Returns `a * b mod modulus`, by doubling and adding. `a` and `b` are
+    /// reduced and as wide as `modulus`, which has a spare leading byte.
+    fn mul(a: &[u8], b: &[u8], modulus: &[u8]) -> Vec<u8> {
+        let mut product = vec![0; modulus.len()];
+        for bit in bits(b) {
+            let double = product.clone();
+            add(&mut product, &double);
+            reduce(&mut product, modulus);
+            if bit {
+                add(&mut product, a);
+                reduce(&mut product, modulus);
+            }
+        }
+        product
+    }
+
+    pub(crate) fn mod_exp(base: &[u8], exp: &[u8], modulus: &[u8]) -> Result<i64, Error> {
+        with(|host| host.calls.push("bigint.mod_exp"));
+
+        if modulus.iter().all(|byte| *byte == 0) {
+            return Ok(-1);
+        }
+
+        // one spare byte keeps the sum of two reduced values from overflowing
+        let width = modulus.len() + 1;
+        let modulus: Vec<u8> = std::iter::once(0).chain(modulus.iter().copied()).collect();
+
+        let mut one = vec![0; width];
+        one[width - 1] = 1;
+        let base = mul(&one, base, &modulus);
+        let mut result = one;
+        reduce(&mut result, &modulus);
+        for bit in bits(exp) {
+            result = mul(&result, &result, &modulus);
+            if bit {
+                result = mul(&result, &base, &modulus);
+            }
+        }
+
+        Ok(write_bytes(&result[1..]))
+    }
+}
@@ -1,9 +1,16 @@
+#[cfg(feature = "multi-format")]
+use crate::memory::{into_boxed_bytes, missing};
 use crate::{
-    memory::{to_host_ptr, HostPtr},
+    memory::{from_host_ptr, into_return_ptr, to_host_ptr, HostPtr},
     state::Error as StateError,
     Error,
 };
-use borsh::BorshSerialize;
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "multi-format")]
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "multi-format")]
+use std::io::Read;
+use std::io::{self, Write};
 
 #[macro_export]
 macro_rules! params {
@@ -26,6 +33,11 @@ impl Params {
     pub(crate) fn into_host_ptr(self) -> Result<HostPtr, StateError> {
         to_host_ptr(&self.0)
     }
+
+    /// Returns the length of the serialized parameters.
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
 }
 
 impl FromIterator<Param> for Params {
@@ -53,3 +65,345 @@ where
 
     Ok(Param(bytes))
 }
+
+/// The encoding of the parameters and the returned `Result` of a function
+/// annotated with `#[public(multi_format)]` or `#[view(multi_format)]`, which
+/// needs the `multi-format` feature.
+///
+/// Such a function takes every parameter after the [`Context`](crate::Context)
+/// from a single buffer, which starts with a tag byte picking the format of
+/// the whole call. The parameters follow in that format: one after the other
+/// in [borsh], or as a JSON array. The result is returned in the same format,
+/// after the same tag. A function that only takes a
+/// [`Context`](crate::Context) is still passed the tag, alone or followed by
+/// `[]` in JSON.
+#[cfg(feature = "multi-format")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Format {
+    /// [borsh], tag `0`.
+    #[default]
+    Borsh = 0,
+    /// JSON, tag `1`. Results are written as [deterministic JSON](crate::json),
+    /// with a `Result` as an object whose only key is `Ok` or `Err`. Integers
+    /// are plain decimal numbers, including 128-bit ones.
+    Json = 1,
+}
+
+#[cfg(feature = "multi-format")]
+impl Format {
+    /// Returns the format with the given tag.
+    /// # Errors
+    /// Returns [`StateError::InvalidTag`] if no format has the tag.
+    pub fn from_tag(tag: u8) -> Result<Self, StateError> {
+        match tag {
+            0 => Ok(Self::Borsh),
+            1 => Ok(Self::Json),
+            tag => Err(StateError::InvalidTag(tag)),
+        }
+    }
+}
+
+/// A type that can be a parameter of a `#[public]` or `#[view]` function,
+/// which is any [borsh] deserializable type. The macros decode each
+/// parameter through this trait, so a parameter of another type is reported
+/// against the parameter itself rather than inside the generated wrapper.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be a parameter of an entrypoint, since it does not implement `BorshDeserialize`",
+    label = "parameters of `#[public]` and `#[view]` functions are borsh deserialized",
+    note = "derive `borsh::BorshDeserialize` for `{Self}`"
+)]
+pub trait EntrypointArg: Sized {
+    /// Decodes the parameter the host passed at `ptr`.
+    /// # Errors
+    /// Returns a [`StateError`] if the parameter cannot be deserialized.
+    fn from_arg(ptr: HostPtr) -> Result<Self, StateError>;
+}
+
+impl<T> EntrypointArg for T
+where
+    T: BorshDeserialize,
+{
+    fn from_arg(ptr: HostPtr) -> Result<Self, StateError> {
+        from_host_ptr(ptr)
+    }
+}
+
+/// A type that can be a parameter of a `#[public(multi_format)]` function,
+/// which is any type that can be deserialized from both [borsh] and JSON.
+#[cfg(feature = "multi-format")]
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be a parameter of a `multi_format` entrypoint, since it does not implement `BorshDeserialize` and `Deserialize`",
+    label = "parameters of `multi_format` functions are borsh or JSON deserialized",
+    note = "derive `borsh::BorshDeserialize` and `serde::Deserialize` for `{Self}`"
+)]
+pub trait FormatArg: BorshDeserialize + DeserializeOwned {}
+
+#[cfg(feature = "multi-format")]
+impl<T> FormatArg for T where T: BorshDeserialize + DeserializeOwned {}
+
+/// The parameters of a `#[public(multi_format)]` function after its
+/// [`Context`](crate::Context), as a tuple of [`FormatArg`]s.
+#[cfg(feature = "multi-format")]
+pub trait FormatArgs: Sized {
+    /// Decodes the parameters the host passed at `ptr`, in the [`Format`]
+    /// picked by the leading tag, and returns them with that format.
+    /// # Errors
+    /// Returns [`StateError::InvalidTag`] if the tag is unknown, or a
+    /// [`StateError`] if the parameters cannot be deserialized or are
+    /// followed by more bytes.
+    fn from_tagged_args(ptr: HostPtr) -> Result<(Format, Self), StateError> {
+        let bytes = into_boxed_bytes(ptr).ok_or_else(|| missing(ptr))?;
+        let (&tag, bytes) = bytes.split_first().ok_or(StateError::InvalidBytes)?;
+        let format = Format::from_tag(tag)?;
+
+        let args = match format {
+            Format::Borsh => {
+                let mut reader = bytes;
+                let args =
+                    Self::from_borsh(&mut reader).map_err(|err| StateError::from_borsh(&err))?;
+                if !reader.is_empty() {
+                    return Err(StateError::InvalidBytes);
+                }
+                args
+            }
+            Format::Json => Self::from_json(bytes)
+                .map_err(|err| StateError::Deserialization(err.to_string()))?,
+        };
+
+        Ok((format, args))
+    }
+
+    /// Decodes the parameters one after the other from `reader`.
+    /// # Errors
+    /// Returns an error if a parameter cannot be deserialized.
+    fn from_borsh<R: Read>(reader: &mut R) -> io::Result<Self>;
+
+    /// Decodes the parameters from a JSON array.
+    /// # Errors
+    /// Returns an error if `bytes` are not an array of the parameters.
+    fn from_json(bytes: &[u8]) -> serde_json::Result<Self>;
+}
+
+#[cfg(feature = "multi-format")]
+impl FormatArgs for () {
+    fn from_borsh<R: Read>(_: &mut R) -> io::Result<Self> {
+        Ok(())
+    }
+
+    fn from_json(bytes: &[u8]) -> serde_json::Result<Self> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        serde_json::from_slice::<[(); 0]>(bytes).map(|_| ())
+    }
+}
+
+#[cfg(feature = "multi-format")]
+macro_rules! impl_format_args {
+    ($($arg:ident),+) => {
+        impl<$($arg),+> FormatArgs for ($($arg,)+)
+        where
+            $($arg: FormatArg),+
+        {
+            fn from_borsh<R: Read>(reader: &mut R) -> io::Result<Self> {
+                Ok(($($arg::deserialize_reader(reader)?,)+))
+            }
+
+            fn from_json(bytes: &[u8]) -> serde_json::Result<Self> {
+                serde_json::from_slice(bytes)
+            }
+        }
+    };
+}
+
+// the largest tuple serde deserializes has 16 elements
+#[cfg(feature = "multi-format")]
+impl_format_args!(A);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H, I);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H, I, J);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H, I, J, K);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H, I, J, K, L);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H, I, J, K, L, M);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H, I, J, K, L, M, N);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O);
+#[cfg(feature = "multi-format")]
+impl_format_args!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
+
+/// The `Result` returned by a `#[public]` or `#[view]` function. Its success
+/// and error types must be [`EntrypointValue`]s to be returned to the host.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be returned by an entrypoint, since it is not a `Result`",
+    label = "functions with `#[public]` or `#[view]` return a `Result` or a WASM type"
+)]
+pub trait EntrypointReturn {
+    /// The success type.
+    type Ok;
+    /// The error type.
+    type Err;
+
+    /// Serializes the result for the host, see [`into_return_ptr`].
+    /// # Errors
+    /// Returns a [`StateError`] if the result cannot be serialized.
+    fn to_return_ptr(&self) -> Result<HostPtr, StateError>
+    where
+        Self::Ok: EntrypointValue,
+        Self::Err: EntrypointValue;
+
+    /// Serializes the result for the host in `format`, after its tag.
+    /// # Errors
+    /// Returns a [`StateError`] if the result cannot be serialized.
+    #[cfg(feature = "multi-format")]
+    fn to_tagged_return_ptr(&self, format: Format) -> Result<HostPtr, StateError>
+    where
+        Self::Ok: FormatValue,
+        Self::Err: FormatValue;
+}
+
+impl<T, E> EntrypointReturn for Result<T, E> {
+    type Ok = T;
+    type Err = E;
+
+    fn to_return_ptr(&self) -> Result<HostPtr, StateError>
+    where
+        T: EntrypointValue,
+        E: EntrypointValue,
+    {
+        into_return_ptr(&Returned(self))
+    }
+
+    #[cfg(feature = "multi-format")]
+    fn to_tagged_return_ptr(&self, format: Format) -> Result<HostPtr, StateError>
+    where
+        T: FormatValue,
+        E: FormatValue,
+    {
+        into_return_ptr(&Tagged(format, self))
+    }
+}
+
+/// A type that can be the success or error type of the `Result` returned by
+/// a `#[public]` or `#[view]` function, which is any [borsh] serializable
+/// type. Like [`EntrypointArg`], it lets the macros report a type that cannot
+/// be returned against the return type.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be returned by an entrypoint, since it does not implement `BorshSerialize`",
+    label = "results of `#[public]` and `#[view]` functions are borsh serialized",
+    note = "derive `borsh::BorshSerialize` for `{Self}`"
+)]
+pub trait EntrypointValue {
+    /// Serializes the value into `writer`.
+    /// # Errors
+    /// Returns an error if the value cannot be serialized.
+    fn serialize_value<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+}
+
+impl<T> EntrypointValue for T
+where
+    T: BorshSerialize,
+{
+    fn serialize_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.serialize(writer)
+    }
+}
+
+/// A type that can be the success or error type of the `Result` returned by
+/// a `#[public(multi_format)]` function, which is any type that can be
+/// serialized to both [borsh] and JSON.
+#[cfg(feature = "multi-format")]
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be returned by a `multi_format` entrypoint, since it does not implement `BorshSerialize` and `Serialize`",
+    label = "results of `multi_format` functions are borsh or JSON serialized",
+    note = "derive `borsh::BorshSerialize` and `serde::Serialize` for `{Self}`"
+)]
+pub trait FormatValue: BorshSerialize + Serialize {}
+
+#[cfg(feature = "multi-format")]
+impl<T> FormatValue for T where T: BorshSerialize + Serialize {}
+
+/// Serializes a `Result` the way [borsh] does, a variant byte followed by
+/// the value, for success and error types only known to be
+/// [`EntrypointValue`]s.
+struct Returned<'a, T, E>(&'a Result<T, E>);
+
+impl<T, E> BorshSerialize for Returned<'_, T, E>
+where
+    T: EntrypointValue,
+    E: EntrypointValue,
+{
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self.0 {
+            Err(err) => {
+                writer.write_all(&[0])?;
+                err.serialize_value(writer)
+            }
+            Ok(value) => {
+                writer.write_all(&[1])?;
+                value.serialize_value(writer)
+            }
+        }
+    }
+}
+
+/// Serializes a `Result` in a [`Format`], after the format's tag.
+#[cfg(feature = "multi-format")]
+struct Tagged<'a, T, E>(Format, &'a Result<T, E>);
+
+#[cfg(feature = "multi-format")]
+impl<T, E> BorshSerialize for Tagged<'_, T, E>
+where
+    T: FormatValue,
+    E: FormatValue,
+{
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let Tagged(format, result) = *self;
+        writer.write_all(&[format as u8])?;
+
+        match format {
+            Format::Borsh => BorshSerialize::serialize(result, writer),
+            Format::Json => {
+                let json = crate::json::to_vec(result)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                writer.write_all(&json)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Returned;
+
+    #[test]
+    fn results_are_returned_as_borsh_serializes_them() {
+        let results: [Result<u32, String>; 2] = [Ok(7), Err("reverted".to_owned())];
+
+        for result in &results {
+            assert_eq!(
+                borsh::to_vec(&Returned(result)).unwrap(),
+                borsh::to_vec(result).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! The items most programs use, for a single glob import:
+//!
+//! ```
+//! use wasmlanche_sdk::prelude::*;
+//!
+//! #[derive(BorshSerialize, BorshDeserialize)]
+//! #[borsh(crate = "wasmlanche_sdk::borsh")]
+//! pub struct Votes {
+//!     by_voter: BTreeMap<[u8; 32], u64>,
+//! }
+//! ```
+//!
+//! Only the ordered collections are included. The iteration order of a
+//! [`HashMap`](std::collections::HashMap) depends on its hasher's seed, so a
+//! program that iterates one, or serializes it, can disagree with itself
+//! from one node to the next. A [`BTreeMap`] or [`BTreeSet`] always iterates
+//! in key order.
+
+pub use crate::{
+    borsh::{BorshDeserialize, BorshSerialize},
+    public,
+    state::{Error as StateError, Key, State},
+    view, Context, Program,
+};
+pub use std::collections::{BTreeMap, BTreeSet};
@@ -1,9 +1,118 @@
-use std::hash::Hash;
+use std::{cell::Cell, hash::Hash};
 
 use borsh::{BorshDeserialize, BorshSerialize};
 
 use crate::state::Key;
-use crate::{memory::to_host_ptr, state::Error as StateError, state::State, Params};
+#[cfg(feature = "unstable")]
+use crate::{
+    host::decode_result,
+    memory::{dealloc, from_host_ptr, tracked_len},
+    types::Address,
+};
+use crate::{
+    memory::to_host_ptr,
+    metrics,
+    state::{Direction, Error as StateError, State},
+    Params,
+};
+
+/// The default limit on the serialized arguments of a call to another
+/// program, in bytes.
+pub const MAX_CALL_INPUT: usize = 64 * 1024;
+
+/// The default limit on the serialized result of a call to another program,
+/// in bytes.
+pub const MAX_CALL_OUTPUT: usize = 64 * 1024;
+
+thread_local! {
+    static CALL_INPUT_LIMIT: Cell<usize> = const { Cell::new(MAX_CALL_INPUT) };
+    static CALL_OUTPUT_LIMIT: Cell<usize> = const { Cell::new(MAX_CALL_OUTPUT) };
+}
+
+/// Sets the limit on the serialized arguments of a call, for programs that
+/// legitimately pass more than [`MAX_CALL_INPUT`]. Arguments over the limit
+/// fail with [`StateError::LengthTooLarge`], with [`Direction::Input`], before
+/// the host is called, rather than with a trap inside it.
+pub fn set_max_call_input(limit: usize) {
+    CALL_INPUT_LIMIT.set(limit);
+}
+
+/// Returns the limit set by [`set_max_call_input`], [`MAX_CALL_INPUT`] by
+/// default.
+#[must_use]
+pub fn max_call_input() -> usize {
+    CALL_INPUT_LIMIT.get()
+}
+
+/// Sets the limit on the serialized result of a call, for programs that
+/// legitimately receive more than [`MAX_CALL_OUTPUT`]. A result over the
+/// limit is freed without being decoded and fails with
+/// [`StateError::LengthTooLarge`], with [`Direction::Output`].
+pub fn set_max_call_output(limit: usize) {
+    CALL_OUTPUT_LIMIT.set(limit);
+}
+
+/// Returns the limit set by [`set_max_call_output`], [`MAX_CALL_OUTPUT`] by
+/// default.
+#[must_use]
+pub fn max_call_output() -> usize {
+    CALL_OUTPUT_LIMIT.get()
+}
+
+fn check_call_input(len: usize) -> Result<(), StateError> {
+    let max = max_call_input();
+    if len > max {
+        return Err(StateError::LengthTooLarge {
+            len,
+            max,
+            direction: Direction::Input,
+        });
+    }
+
+    Ok(())
+}
+
+/// Decodes the result of a call at `ptr`, after checking it against
+/// [`max_call_output`].
+#[cfg(feature = "unstable")]
+fn call_output<V>(ptr: i64) -> Result<V, StateError>
+where
+    V: BorshDeserialize,
+{
+    let max = max_call_output();
+    if let Some(len) = tracked_len(ptr).filter(|len| *len > max) {
+        dealloc(ptr as *mut u8);
+        return Err(StateError::LengthTooLarge {
+            len,
+            max,
+            direction: Direction::Output,
+        });
+    }
+
+    from_host_ptr(ptr)
+}
+
+/// One call of a batch made with [`Program::call_batch`].
+#[cfg(feature = "unstable")]
+#[derive(Clone, BorshSerialize, BorshDeserialize)]
+pub struct CallSpec {
+    /// The program to call.
+    pub target: Program,
+    /// The name of the function to call.
+    pub function: String,
+    /// The serialized arguments of the function.
+    pub args: Vec<u8>,
+}
+
+/// The result of a call made with [`Program::call_metered`].
+#[cfg(feature = "unstable")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallResult<T> {
+    /// The decoded return value of the callee.
+    pub value: T,
+    /// The units the callee consumed, at most the `max_units` it was given.
+    pub units_used: u64,
+}
 
 /// Represents the current Program in the context of the caller. Or an external
 /// program that is being invoked.
@@ -37,8 +146,13 @@ impl Program {
 
     /// Attempts to call a function `name` with `args` on the given program. This method
     /// is used to call functions on external programs.
+    ///
+    /// The result is the raw `i64` the callee returned rather than a block of
+    /// memory, so [`max_call_output`] does not apply to it.
     /// # Errors
-    /// Returns a [`StateError`] if the call fails.
+    /// Returns a [`StateError`] if the call fails, or
+    /// [`StateError::LengthTooLarge`] if `args` are longer than
+    /// [`max_call_input`].
     /// # Safety
     /// The caller must ensure that `function_name` + `args` point to valid memory locations.
     pub fn call_function(
@@ -47,17 +161,547 @@ impl Program {
         args: Params,
         max_units: i64,
     ) -> Result<i64, StateError> {
+        check_call_input(args.len())?;
+        let args_len = args.len();
         // flatten the args into a single byte vector
         let target = to_host_ptr(self.id())?;
         let function = to_host_ptr(function_name.as_bytes())?;
         let args = args.into_host_ptr()?;
 
-        Ok(unsafe { _call_program(target, function, args, max_units) })
+        Ok(metrics::record(
+            "program.call_program",
+            args_len,
+            || unsafe { call_program(target, function, args, max_units) },
+        ))
+    }
+
+    /// Calls `function` of this program with `args`, allowing it to consume
+    /// at most `max_units`, and returns its decoded return value along with
+    /// the units it actually consumed, as reported by the host. A caller
+    /// that budgets units across several calls can subtract `units_used`
+    /// from its budget, or charge it back to whoever asked for the call.
+    /// # Errors
+    /// Returns [`StateError::HostError`] with the host's code if the call
+    /// fails, including when the callee runs out of units,
+    /// [`StateError::LengthTooLarge`] if the arguments or the result exceed
+    /// [`max_call_input`] or [`max_call_output`], or a [`StateError`]
+    /// if the arguments cannot be serialized or the return value cannot be
+    /// deserialized.
+    #[cfg(feature = "unstable")]
+    pub fn call_metered<Args, Ret>(
+        &self,
+        function: &str,
+        args: &Args,
+        max_units: u64,
+    ) -> Result<CallResult<Ret>, StateError>
+    where
+        Args: BorshSerialize,
+        Ret: BorshDeserialize,
+    {
+        let args = borsh::to_vec(args).map_err(|_| StateError::Serialization)?;
+        check_call_input(args.len())?;
+        let max_units = i64::try_from(max_units).map_err(|_| StateError::IntegerConversion)?;
+
+        let ptr = metrics::record("program.call_metered", args.len(), || {
+            host::call_metered(self, function, &args, max_units)
+        })?;
+        let ptr = decode_result(ptr)?;
+
+        // the host returns the units consumed followed by the return value
+        let (units_used, value): (u64, Vec<u8>) = call_output(ptr)?;
+        let value = borsh::from_slice(&value).map_err(|err| StateError::from_borsh(&err))?;
+        Ok(CallResult { value, units_used })
+    }
+
+    /// Executes `function` from the program deployed at `code` as if it were
+    /// part of this program. The callee runs against this program's storage
+    /// and sees the same [`Context`](crate::Context), so every read and write
+    /// it makes lands in `self`'s state rather than in the state of `code`.
+    ///
+    /// Writes cached in a [State] are only sent to the host when it is dropped,
+    /// and the callee's writes are not seen by a [State] that already cached
+    /// the keys, so make the call through [`State::call`] while a [State] of
+    /// this program is alive.
+    /// # Errors
+    /// Returns [`StateError::HostError`] with the host's code if the call
+    /// fails, a [`StateError`] if the arguments cannot be serialized or the
+    /// return value cannot be deserialized, and
+    /// [`StateError::LengthTooLarge`] if the arguments or the result exceed
+    /// [`max_call_input`] or [`max_call_output`].
+    #[cfg(feature = "unstable")]
+    pub fn delegate_call<Args, Ret>(
+        &self,
+        code: Address,
+        function: &str,
+        args: &Args,
+        max_units: u64,
+    ) -> Result<Ret, StateError>
+    where
+        Args: BorshSerialize,
+        Ret: BorshDeserialize,
+    {
+        let args = borsh::to_vec(args).map_err(|_| StateError::Serialization)?;
+        check_call_input(args.len())?;
+        let max_units = i64::try_from(max_units).map_err(|_| StateError::IntegerConversion)?;
+
+        let ptr = metrics::record("program.delegate_call", args.len(), || {
+            host::delegate_call(self, &code, function, &args, max_units)
+        })?;
+        let ptr = decode_result(ptr)?;
+
+        call_output(ptr)
+    }
+
+    /// Deploys a new instance of the code at `code_id`, on behalf of this
+    /// program, and returns its address. The new program's `init` function,
+    /// if it has one, is called with `init_args` before this returns.
+    ///
+    /// The host picks the address from this program, `code_id` and `salt`.
+    /// How it does so is up to the host, so use the returned address rather
+    /// than computing it. A factory deploys several instances of the same
+    /// code by varying the salt. Deploying twice with the same salt fails,
+    /// since the address is taken.
+    /// # Errors
+    /// Returns [`StateError::HostError`] with the host's code if it rejects
+    /// the deployment, for example because `code_id` is unknown, the address
+    /// is already taken or `init` fails, or a [`StateError`] if the arguments
+    /// cannot be passed to the host.
+    #[cfg(feature = "unstable")]
+    pub fn deploy(
+        &self,
+        code_id: Address,
+        init_args: &[u8],
+        salt: &[u8],
+    ) -> Result<Address, StateError> {
+        let ptr = metrics::record("program.deploy", init_args.len() + salt.len(), || {
+            host::deploy(self, &code_id, init_args, salt)
+        })?;
+        let ptr = decode_result(ptr)?;
+
+        from_host_ptr(ptr)
+    }
+
+    /// Deletes every key in this program's state and sends its whole native
+    /// balance to `beneficiary`, ending the program.
+    ///
+    /// This cannot be undone: the program's code is removed along with its
+    /// state, so every later call to it fails. Writes to this program's state
+    /// that are still cached, in a [State] or a field, are discarded rather
+    /// than flushed once the program is destroyed, so they don't bring back
+    /// the deleted keys.
+    /// # Errors
+    /// Returns [`StateError::HostError`] if the host refuses to destroy the
+    /// program, or a [`StateError`] if the arguments cannot be passed to the
+    /// host.
+    #[cfg(feature = "unstable")]
+    pub fn self_destruct(&self, beneficiary: Address) -> Result<(), StateError> {
+        let ret = metrics::record("program.self_destruct", Address::LEN, || {
+            host::self_destruct(self, &beneficiary)
+        })?;
+        decode_result(ret)?;
+        crate::state::mark_destroyed(self);
+
+        Ok(())
+    }
+
+    /// Makes every call in `calls`, in order, in a single trip to the host, and
+    /// returns the serialized return value of each call, or
+    /// [`StateError::Call`] for a call that failed, in the same order.
+    ///
+    /// Calls are not atomic as a batch: each call commits or reverts on its
+    /// own, so a failed call neither rolls back the calls before it nor stops
+    /// the ones after it. A program that needs all of them to succeed should
+    /// check every result and fail itself, which reverts the whole
+    /// transaction. `max_units` is shared by the whole batch.
+    /// # Errors
+    /// Returns a [`StateError`] if the calls cannot be serialized, or
+    /// [`StateError::HostError`] with the host's code if it fails to run the
+    /// batch at all. The serialized calls and the
+    /// results of the whole batch are checked against [`max_call_input`] and
+    /// [`max_call_output`].
+    #[cfg(feature = "unstable")]
+    pub fn call_batch(
+        &self,
+        calls: &[CallSpec],
+        max_units: u64,
+    ) -> Result<Vec<Result<Vec<u8>, StateError>>, StateError> {
+        let calls = borsh::to_vec(calls).map_err(|_| StateError::Serialization)?;
+        check_call_input(calls.len())?;
+        let max_units = i64::try_from(max_units).map_err(|_| StateError::IntegerConversion)?;
+
+        let ptr = metrics::record("program.call_batch", calls.len(), || {
+            host::call_batch(self, &calls, max_units)
+        })?;
+        let ptr = decode_result(ptr)?;
+
+        // the host returns `None` for each call that failed
+        let results: Vec<Option<Vec<u8>>> = call_output(ptr)?;
+        Ok(results
+            .into_iter()
+            .map(|result| result.ok_or(StateError::Call))
+            .collect())
     }
 }
 
+#[cfg(all(test, feature = "unstable"))]
+use crate::mock::program::call_program;
+
+#[cfg(not(all(test, feature = "unstable")))]
 #[link(wasm_import_module = "program")]
 extern "C" {
     #[link_name = "call_program"]
-    fn _call_program(target_id: i64, function: i64, args_ptr: i64, max_units: i64) -> i64;
+    fn call_program(target_id: i64, function: i64, args_ptr: i64, max_units: i64) -> i64;
+}
+
+#[cfg(all(test, feature = "unstable"))]
+use crate::mock::program as host;
+
+#[cfg(all(not(test), feature = "unstable"))]
+mod host {
+    use super::{to_host_ptr, Address, Program, StateError};
+
+    #[link(wasm_import_module = "program")]
+    extern "C" {
+        #[link_name = "delegate_call"]
+        fn _delegate_call(caller: i64, code: i64, function: i64, args: i64, max_units: i64) -> i64;
+
+        #[link_name = "call_batch"]
+        fn _call_batch(caller: i64, calls: i64, max_units: i64) -> i64;
+
+        #[link_name = "call_metered"]
+        fn _call_metered(target: i64, function: i64, args: i64, max_units: i64) -> i64;
+
+        #[link_name = "deploy"]
+        fn _deploy(caller: i64, code: i64, init_args: i64, salt: i64) -> i64;
+
+        #[link_name = "self_destruct"]
+        fn _self_destruct(caller: i64, beneficiary: i64) -> i64;
+    }
+
+    /// Runs `function` of `code` against the storage of `caller`.
+    pub(super) fn delegate_call(
+        caller: &Program,
+        code: &Address,
+        function: &str,
+        args: &[u8],
+        max_units: i64,
+    ) -> Result<i64, StateError> {
+        let caller = to_host_ptr(caller.id())?;
+        let code = to_host_ptr(code.as_bytes())?;
+        let function = to_host_ptr(function.as_bytes())?;
+        let args = to_host_ptr(args)?;
+
+        Ok(unsafe { _delegate_call(caller, code, function, args, max_units) })
+    }
+
+    /// Calls `function` of `target`, returning the units it consumed.
+    pub(super) fn call_metered(
+        target: &Program,
+        function: &str,
+        args: &[u8],
+        max_units: i64,
+    ) -> Result<i64, StateError> {
+        let target = to_host_ptr(target.id())?;
+        let function = to_host_ptr(function.as_bytes())?;
+        let args = to_host_ptr(args)?;
+
+        Ok(unsafe { _call_metered(target, function, args, max_units) })
+    }
+
+    /// Deploys the code at `code` on behalf of `caller`.
+    pub(super) fn deploy(
+        caller: &Program,
+        code: &Address,
+        init_args: &[u8],
+        salt: &[u8],
+    ) -> Result<i64, StateError> {
+        let caller = to_host_ptr(caller.id())?;
+        let code = to_host_ptr(code.as_bytes())?;
+        let init_args = to_host_ptr(init_args)?;
+        let salt = to_host_ptr(salt)?;
+
+        Ok(unsafe { _deploy(caller, code, init_args, salt) })
+    }
+
+    /// Clears the state of `caller` and sends its balance to `beneficiary`.
+    pub(super) fn self_destruct(
+        caller: &Program,
+        beneficiary: &Address,
+    ) -> Result<i64, StateError> {
+        let caller = to_host_ptr(caller.id())?;
+        let beneficiary = to_host_ptr(beneficiary.as_bytes())?;
+
+        Ok(unsafe { _self_destruct(caller, beneficiary) })
+    }
+
+    /// Runs the serialized `calls` in order on behalf of `caller`.
+    pub(super) fn call_batch(
+        caller: &Program,
+        calls: &[u8],
+        max_units: i64,
+    ) -> Result<i64, StateError> {
+        let caller = to_host_ptr(caller.id())?;
+        let calls = to_host_ptr(calls)?;
+
+        Ok(unsafe { _call_batch(caller, calls, max_units) })
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod tests {
+    use super::{
+        set_max_call_input, set_max_call_output, CallResult, CallSpec, Direction, Program,
+    };
+    use crate::{mock, params::serialize_param, state::Key, types::Address, Params};
+
+    const CODE: [u8; Address::LEN] = [2; Address::LEN];
+
+    fn counter() -> Key {
+        Key::new(b"counter".to_vec())
+    }
+
+    /// Deploys an `increment` function that adds its argument to the counter
+    /// in the storage it runs against and returns the new count.
+    fn deploy_increment() {
+        mock::with(|host| {
+            host.deploy(Address::new(CODE), "increment", |program, args| {
+                let amount: u64 = borsh::from_slice(args).unwrap();
+                let mut state = program.state::<Key>();
+                let count = state.get::<u64>(counter()).unwrap().unwrap_or_default() + amount;
+                state.store(counter(), &count).unwrap();
+                borsh::to_vec(&count).unwrap()
+            });
+        });
+    }
+
+    #[test]
+    fn delegate_call_uses_caller_storage() {
+        deploy_increment();
+        let proxy = Program::new([1; Program::LEN]);
+        proxy.state::<Key>().store(counter(), &5_u64).unwrap();
+
+        let count: u64 = proxy
+            .delegate_call(Address::new(CODE), "increment", &1_u64, 1_000)
+            .unwrap();
+
+        assert_eq!(count, 6);
+        assert_eq!(proxy.state::<Key>().get::<u64>(counter()).unwrap(), Some(6));
+        assert_eq!(
+            Program::new(CODE)
+                .state::<Key>()
+                .get::<u64>(counter())
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn caller_sees_writes_made_during_call() {
+        deploy_increment();
+        let proxy = Program::new([1; Program::LEN]);
+        let mut state = proxy.state::<Key>();
+        assert_eq!(state.get::<u64>(counter()).unwrap(), None);
+        state.store(counter(), &5_u64).unwrap();
+
+        let count: u64 = state
+            .call(|| proxy.delegate_call(Address::new(CODE), "increment", &1_u64, 1_000))
+            .unwrap()
+            .unwrap();
+
+        // the callee saw the pending write and the caller sees the callee's
+        assert_eq!(count, 6);
+        assert_eq!(state.get::<u64>(counter()).unwrap(), Some(6));
+    }
+
+    #[test]
+    fn batch_returns_a_result_per_call() {
+        deploy_increment();
+        let target = Program::new(CODE);
+        let calls = [
+            CallSpec {
+                target,
+                function: "increment".to_owned(),
+                args: borsh::to_vec(&3_u64).unwrap(),
+            },
+            CallSpec {
+                target,
+                function: "missing".to_owned(),
+                args: Vec::new(),
+            },
+        ];
+
+        let results = Program::new([1; Program::LEN])
+            .call_batch(&calls, 1_000)
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &borsh::to_vec(&3_u64).unwrap()
+        );
+        assert!(matches!(results[1], Err(crate::state::Error::Call)));
+        // the failed call did not undo the one before it
+        assert_eq!(
+            target.state::<Key>().get::<u64>(counter()).unwrap(),
+            Some(3)
+        );
+        assert_eq!(mock::with(|host| host.calls("program.call_batch")), 1);
+    }
+
+    #[test]
+    fn deploy_runs_init_once_per_salt() {
+        mock::with(|host| {
+            host.deploy(Address::new(CODE), "init", |program, args| {
+                program.state::<Key>().store(counter(), &args[0]).unwrap();
+                Vec::new()
+            });
+        });
+        let factory = Program::new([1; Program::LEN]);
+
+        let child = factory.deploy(Address::new(CODE), &[7], b"first").unwrap();
+
+        let child = Program::new(child.as_bytes().try_into().unwrap());
+        assert_eq!(child.state::<Key>().get::<u8>(counter()).unwrap(), Some(7));
+
+        let other = factory.deploy(Address::new(CODE), &[7], b"second").unwrap();
+        assert_ne!(other.as_bytes(), child.id());
+        assert!(matches!(
+            factory.deploy(Address::new(CODE), &[7], b"first"),
+            Err(crate::state::Error::HostError(-1))
+        ));
+    }
+
+    #[test]
+    fn metered_call_reports_units_used() {
+        deploy_increment();
+        mock::with(|host| host.set_call_units(750));
+        let target = Program::new(CODE);
+
+        let result = target.call_metered::<_, u64>("increment", &2_u64, 1_000);
+        assert_eq!(
+            result.unwrap(),
+            CallResult {
+                value: 2,
+                units_used: 750
+            }
+        );
+
+        // the callee runs out of units before it returns
+        assert!(matches!(
+            target.call_metered::<_, u64>("increment", &2_u64, 500),
+            Err(crate::state::Error::HostError(-1))
+        ));
+    }
+
+    #[test]
+    fn self_destruct_clears_state_and_pays_out_the_balance() {
+        deploy_increment();
+        let program = Program::new(CODE);
+        let beneficiary = Address::new([3; Address::LEN]);
+        mock::with(|host| {
+            host.set_balance(CODE, 40);
+            host.set_balance([3; Program::LEN], 2);
+        });
+        program.state::<Key>().store(counter(), &5_u64).unwrap();
+        let other = Program::new([1; Program::LEN]);
+        other.state::<Key>().store(counter(), &9_u64).unwrap();
+
+        program.self_destruct(beneficiary).unwrap();
+
+        assert_eq!(program.state::<Key>().get::<u64>(counter()).unwrap(), None);
+        assert_eq!(other.state::<Key>().get::<u64>(counter()).unwrap(), Some(9));
+        assert_eq!(mock::context::balance(&program), 0);
+        assert_eq!(mock::context::balance(&Program::new([3; Program::LEN])), 42);
+        // the program is gone, so calling it fails
+        assert!(matches!(
+            program.call_metered::<_, u64>("increment", &1_u64, 1_000),
+            Err(crate::state::Error::HostError(-1))
+        ));
+        assert!(matches!(
+            program.self_destruct(beneficiary),
+            Err(crate::state::Error::HostError(-1))
+        ));
+    }
+
+    #[test]
+    fn self_destruct_discards_the_cached_writes() {
+        deploy_increment();
+        let program = Program::new(CODE);
+        let mut state = program.state::<Key>();
+        state.store(counter(), &5_u64).unwrap();
+
+        program
+            .self_destruct(Address::new([3; Address::LEN]))
+            .unwrap();
+        drop(state);
+
+        assert_eq!(program.state::<Key>().get::<u64>(counter()).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_call_input_fails_before_the_host_is_called() {
+        deploy_increment();
+        set_max_call_input(4);
+
+        let result = Program::new(CODE).call_metered::<_, u64>("increment", &1_u64, 1_000);
+        assert!(matches!(
+            result,
+            Err(crate::state::Error::LengthTooLarge {
+                len: 8,
+                max: 4,
+                direction: Direction::Input,
+            })
+        ));
+        assert_eq!(mock::with(|host| host.calls("program.call_metered")), 0);
+
+        let args: Params = [serialize_param(&1_u64).unwrap()].into_iter().collect();
+        assert!(matches!(
+            Program::new(CODE).call_function("increment", args, 1_000),
+            Err(crate::state::Error::LengthTooLarge {
+                len: 12,
+                max: 4,
+                direction: Direction::Input,
+            })
+        ));
+        assert_eq!(mock::with(|host| host.calls("program.call_program")), 0);
+
+        set_max_call_input(8);
+        assert!(Program::new(CODE)
+            .call_metered::<_, u64>("increment", &1_u64, 1_000)
+            .is_ok());
+    }
+
+    #[test]
+    fn oversized_call_output_is_freed_undecoded() {
+        mock::with(|host| {
+            host.deploy(Address::new(CODE), "dump", |_, _| {
+                borsh::to_vec(&vec![7_u8; 100]).unwrap()
+            });
+        });
+        let proxy = Program::new([1; Program::LEN]);
+        set_max_call_output(100);
+
+        let result = proxy.delegate_call::<_, Vec<u8>>(Address::new(CODE), "dump", &(), 1_000);
+        assert!(matches!(
+            result,
+            Err(crate::state::Error::LengthTooLarge {
+                len: 104,
+                max: 100,
+                direction: Direction::Output,
+            })
+        ));
+        assert_eq!(crate::memory::tracked_blocks(), 0);
+
+        set_max_call_output(104);
+        let result = proxy.delegate_call::<_, Vec<u8>>(Address::new(CODE), "dump", &(), 1_000);
+        assert_eq!(result.unwrap(), vec![7; 100]);
+    }
+
+    #[test]
+    fn delegate_call_to_unknown_code_fails() {
+        let proxy = Program::new([1; Program::LEN]);
+        let result = proxy.delegate_call::<_, u64>(Address::new(CODE), "increment", &1_u64, 1_000);
+
+        assert!(matches!(result, Err(crate::state::Error::HostError(-1))));
+    }
 }
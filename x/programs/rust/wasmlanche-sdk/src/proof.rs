@@ -0,0 +1,107 @@
+//! Verification of inclusion proofs against roots committed elsewhere, for
+//! example a Merkle airdrop or the state root of another chain.
+
+use crate::{metrics, state::Error as StateError};
+
+/// Returns `true` if `proof` shows that `leaf` is the `index`th leaf of the
+/// Merkle tree with `root`. The proof lists the sibling of each node on the
+/// path from the leaf up to the root, starting with the sibling of the leaf.
+///
+/// Hashing and tree layout are done by the host, so a proof built with the
+/// chain's tooling verifies the same way on every node. An invalid proof is
+/// not an error: it returns `false`.
+/// # Errors
+/// Returns a [`StateError`] if the arguments cannot be passed to the host or
+/// the host fails to run the verification.
+pub fn verify_merkle(
+    root: [u8; 32],
+    leaf: &[u8],
+    proof: &[[u8; 32]],
+    index: u64,
+) -> Result<bool, StateError> {
+    let proof = proof.as_flattened();
+    let verified = metrics::record("proof.verify_merkle", leaf.len() + proof.len(), || {
+        host::verify_merkle(&root, leaf, proof, index)
+    })?;
+    match verified {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(StateError::Verify),
+    }
+}
+
+#[cfg(test)]
+use crate::mock::proof as host;
+
+#[cfg(not(test))]
+mod host {
+    use crate::{memory::to_host_ptr, state::Error};
+
+    #[link(wasm_import_module = "proof")]
+    extern "C" {
+        #[link_name = "verify_merkle"]
+        fn _verify_merkle(root: i64, leaf: i64, proof: i64, index: i64) -> i64;
+    }
+
+    /// Returns 1 if the proof is valid, 0 if it is not and a negative value
+    /// if the host fails to verify it.
+    #[allow(clippy::cast_possible_wrap)] // the host reads back the `u64` bit pattern
+    pub(super) fn verify_merkle(
+        root: &[u8; 32],
+        leaf: &[u8],
+        proof: &[u8],
+        index: u64,
+    ) -> Result<i64, Error> {
+        let root = to_host_ptr(root)?;
+        let leaf = to_host_ptr(leaf)?;
+        let proof = to_host_ptr(proof)?;
+
+        Ok(unsafe { _verify_merkle(root, leaf, proof, index as i64) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify_merkle;
+    use crate::mock::{self, proof::hash};
+
+    /// Builds a tree over four leaves and returns its root and the proof of
+    /// the leaf at `index`.
+    fn tree(leaves: &[&[u8]; 4], index: usize) -> ([u8; 32], Vec<[u8; 32]>) {
+        let hashes = leaves.map(hash);
+        let left = hash(&[hashes[0], hashes[1]].concat());
+        let right = hash(&[hashes[2], hashes[3]].concat());
+        let root = hash(&[left, right].concat());
+
+        let parents = [left, right];
+        let proof = vec![hashes[index ^ 1], parents[(index >> 1) ^ 1]];
+        (root, proof)
+    }
+
+    const LEAVES: [&[u8]; 4] = [b"alice", b"bob", b"carol", b"dave"];
+
+    #[test]
+    fn valid_proof_verifies() {
+        for (index, leaf) in LEAVES.into_iter().enumerate() {
+            let (root, proof) = tree(&LEAVES, index);
+            assert!(verify_merkle(root, leaf, &proof, index as u64).unwrap());
+        }
+        assert_eq!(mock::with(|host| host.calls("proof.verify_merkle")), 4);
+    }
+
+    #[test]
+    fn tampered_leaf_does_not_verify() {
+        let (root, proof) = tree(&LEAVES, 2);
+        assert!(!verify_merkle(root, b"mallory", &proof, 2).unwrap());
+        // the right leaf at the wrong position
+        assert!(!verify_merkle(root, LEAVES[2], &proof, 3).unwrap());
+    }
+
+    #[test]
+    fn wrong_root_does_not_verify() {
+        let (root, proof) = tree(&LEAVES, 1);
+        let mut wrong = root;
+        wrong[0] ^= 1;
+        assert!(!verify_merkle(wrong, LEAVES[1], &proof, 1).unwrap());
+    }
+}
@@ -0,0 +1,134 @@
+//! Deterministic pseudo-randomness for programs.
+//!
+//! Every validator must compute the same result, so randomness is derived from
+//! a seed all of them agree on, such as a block hash. The output is only as
+//! unpredictable as that seed.
+
+/// A xoshiro256** generator. The same seed produces the same sequence on
+/// every platform.
+#[derive(Clone, Debug)]
+pub struct Rng([u64; 4]);
+
+impl Rng {
+    /// Returns a generator seeded with `seed`.
+    #[must_use]
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        let mut state = [0; 4];
+        for (i, word) in state.iter_mut().enumerate() {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&seed[i * 8..(i + 1) * 8]);
+            *word = u64::from_le_bytes(bytes);
+        }
+
+        // the all-zero state is a fixed point of the generator
+        if state == [0; 4] {
+            state[0] = 0x9e37_79b9_7f4a_7c15;
+        }
+
+        Self(state)
+    }
+
+    /// Returns the next `u64` in the sequence.
+    pub fn next_u64(&mut self) -> u64 {
+        let [s0, s1, s2, s3] = &mut self.0;
+        let result = s1.wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = *s1 << 17;
+
+        *s2 ^= *s0;
+        *s3 ^= *s1;
+        *s1 ^= *s2;
+        *s0 ^= *s3;
+        *s2 ^= t;
+        *s3 = s3.rotate_left(45);
+
+        result
+    }
+
+    /// Returns a uniformly distributed value in `0..bound`.
+    /// # Panics
+    /// Panics if `bound` is zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        assert!(bound > 0, "bound must be positive");
+        // reject the values past the last full multiple of `bound` so every
+        // remainder is equally likely
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// Shuffles `items` in place with the Fisher-Yates algorithm, so every
+/// permutation is equally likely and the same seed always yields the same
+/// permutation.
+pub fn shuffle<T>(rng: &mut Rng, items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        #[allow(clippy::cast_possible_truncation)] // `j <= i`, which is a usize
+        let j = rng.below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shuffle, Rng};
+
+    fn shuffled(seed: u8) -> Vec<u8> {
+        let mut items: Vec<u8> = (0..52).collect();
+        shuffle(&mut Rng::from_seed([seed; 32]), &mut items);
+        items
+    }
+
+    #[test]
+    fn matches_the_reference_implementation() {
+        let mut seed = [0; 32];
+        for (bytes, word) in seed.chunks_exact_mut(8).zip([1_u64, 2, 3, 4]) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        let mut rng = Rng::from_seed(seed);
+
+        // the first outputs of xoshiro256starstar.c from
+        // https://prng.di.unimi.it for the state {1, 2, 3, 4}, the vector the
+        // `rand_xoshiro` crate checks against
+        let expected = [
+            11520,
+            0,
+            1_509_978_240,
+            1_215_971_899_390_074_240,
+            1_216_172_134_540_287_360,
+            607_988_272_756_665_600,
+            16_172_922_978_634_559_625,
+            8_476_171_486_693_032_832,
+            10_595_114_339_597_558_777,
+            2_904_607_092_377_533_576,
+        ];
+        for value in expected {
+            assert_eq!(rng.next_u64(), value);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_permutation() {
+        assert_eq!(shuffled(1), shuffled(1));
+        assert_ne!(shuffled(1), shuffled(2));
+    }
+
+    #[test]
+    fn shuffle_is_a_permutation() {
+        let mut items = shuffled(1);
+        assert_ne!(items, (0..52).collect::<Vec<_>>());
+
+        items.sort_unstable();
+        assert_eq!(items, (0..52).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn below_stays_in_bounds() {
+        let mut rng = Rng::from_seed([0; 32]);
+        assert!((0..1_000).all(|_| rng.below(7) < 7));
+        assert_eq!(rng.below(1), 0);
+    }
+}
@@ -0,0 +1,66 @@
+//! Isolation of faults in code the program does not control, such as a
+//! callback supplied by a plugin.
+
+use crate::state::Error as StateError;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Runs `f` and returns its result, or [`StateError::Panicked`] with the panic
+/// message if it panics, so the program can recover instead of the whole
+/// invocation aborting.
+///
+/// Catching a panic requires the program to be built with `panic = "unwind"`.
+/// With `panic = "abort"`, the default for `wasm32-unknown-unknown`, a panic
+/// in `f` still aborts the invocation and this function never returns an
+/// error.
+///
+/// `f` may leave anything it had mutable access to half updated when it
+/// panics, so values it touched should be discarded rather than written back
+/// after an error.
+/// # Errors
+/// Returns [`StateError::Panicked`] if `f` panics.
+pub fn catch_program_panic<F, R>(f: F) -> Result<R, StateError>
+where
+    F: FnOnce() -> R,
+{
+    catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(ToString::to_string)
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        StateError::Panicked(message)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::catch_program_panic;
+    use crate::state::Error as StateError;
+
+    #[test]
+    fn panic_is_returned_as_error() {
+        let result = catch_program_panic(|| -> u32 { panic!("plugin failed: {}", 7) });
+        assert!(matches!(
+            result,
+            Err(StateError::Panicked(message)) if message == "plugin failed: 7"
+        ));
+
+        let result = catch_program_panic(|| -> u32 { panic!("static message") });
+        assert!(matches!(
+            result,
+            Err(StateError::Panicked(message)) if message == "static message"
+        ));
+    }
+
+    #[test]
+    fn result_is_returned_without_panic() {
+        let mut calls = 0;
+        let result = catch_program_panic(|| {
+            calls += 1;
+            calls * 2
+        });
+
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(calls, 1);
+    }
+}
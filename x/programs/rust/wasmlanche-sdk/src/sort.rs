@@ -0,0 +1,53 @@
+//! Sorting that produces the same order on every node.
+
+/// Sorts `items` in ascending order, keeping items that compare equal in the
+/// order they were given. The result depends only on the input order and
+/// [`Ord`], never on memory addresses or hashing, so every validator sorting the
+/// same slice produces identical output.
+///
+/// Prefer this over [`slice::sort_unstable`], which may reorder equal items.
+pub fn deterministic_sort<T>(items: &mut [T])
+where
+    T: Ord,
+{
+    // `sort` is a stable sort
+    items.sort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::deterministic_sort;
+    use std::cmp::Ordering;
+
+    /// A bid ordered by price alone, so bids with the same price compare equal.
+    #[derive(Debug, PartialEq, Eq)]
+    struct Bid {
+        price: u64,
+        bidder: char,
+    }
+
+    impl PartialOrd for Bid {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Bid {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.price.cmp(&other.price)
+        }
+    }
+
+    #[test]
+    fn equal_items_keep_input_order() {
+        let mut bids: Vec<_> = [(3, 'a'), (1, 'b'), (3, 'c'), (2, 'd'), (1, 'e'), (3, 'f')]
+            .into_iter()
+            .map(|(price, bidder)| Bid { price, bidder })
+            .collect();
+
+        deterministic_sort(&mut bids);
+
+        let bidders: String = bids.iter().map(|bid| bid.bidder).collect();
+        assert_eq!(bidders, "bedacf");
+    }
+}
@@ -1,6 +1,145 @@
-use crate::{from_host_ptr, program::Program, state::Error as StateError};
-use borsh::{from_slice, to_vec, BorshDeserialize, BorshSerialize};
-use std::{collections::HashMap, hash::Hash, ops::Deref};
+#[cfg(feature = "compression")]
+use crate::compress;
+#[cfg(feature = "unstable")]
+use crate::{context, host::decode_result};
+use crate::{
+    format::{BorshFormat, WireDecode, WireEncode, WireFormat},
+    from_host_ptr,
+    host::decode_optional,
+    memo,
+    memory::Block,
+    metrics,
+    program::Program,
+};
+use borsh::{to_vec, BorshDeserialize, BorshSerialize};
+use std::{
+    cell::{Cell, OnceCell},
+    collections::{hash_map::Entry as CacheEntry, BTreeMap, HashMap},
+    hash::Hash,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+/// The default limit on the encoded size of a single stored value, in bytes.
+pub const MAX_VALUE_SIZE: usize = 64 * 1024;
+
+thread_local! {
+    static VALUE_SIZE_LIMIT: Cell<usize> = const { Cell::new(MAX_VALUE_SIZE) };
+    static READ_ONLY: Cell<bool> = const { Cell::new(false) };
+}
+
+#[cfg(feature = "unstable")]
+thread_local! {
+    /// Programs that destroyed themselves during this call, see
+    /// [`Program::self_destruct`].
+    static DESTROYED: std::cell::RefCell<Vec<[u8; Program::LEN]>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Discards the writes to the state of `program` that are still cached or
+/// buffered, instead of sending them to the host once the program is gone,
+/// which would bring back the keys it deleted.
+#[cfg(feature = "unstable")]
+pub(crate) fn mark_destroyed(program: &Program) {
+    DESTROYED.with_borrow_mut(|destroyed| destroyed.push(*program.id()));
+}
+
+#[cfg(feature = "unstable")]
+fn is_destroyed(program: &Program) -> bool {
+    DESTROYED.with_borrow(|destroyed| destroyed.contains(program.id()))
+}
+
+#[cfg(not(feature = "unstable"))]
+fn is_destroyed(_: &Program) -> bool {
+    false
+}
+
+/// Returns `true` while a `#[view]` function runs. Writes are then rejected
+/// with [`Error::ReadOnly`].
+#[must_use]
+pub fn is_read_only() -> bool {
+    READ_ONLY.get()
+}
+
+/// Marks the current invocation as read-only until dropped. Entered by the
+/// wrapper `#[view]` generates around the function it annotates.
+#[doc(hidden)]
+pub struct ReadOnlyGuard {
+    previous: bool,
+}
+
+impl ReadOnlyGuard {
+    #[must_use]
+    pub fn enter() -> Self {
+        Self {
+            previous: READ_ONLY.replace(true),
+        }
+    }
+}
+
+impl Drop for ReadOnlyGuard {
+    fn drop(&mut self) {
+        READ_ONLY.set(self.previous);
+    }
+}
+
+/// Rejects writes in a view, and otherwise invalidates memoized values ahead
+/// of the write.
+fn check_writable() -> Result<(), Error> {
+    if is_read_only() {
+        return Err(Error::ReadOnly);
+    }
+    memo::invalidate();
+
+    Ok(())
+}
+
+/// Sets the limit on the encoded size of a single stored value, for programs
+/// that legitimately store values larger than [`MAX_VALUE_SIZE`]. The host
+/// enforces its own limits regardless; this one only turns an oversized write
+/// into an [`Error::LengthTooLarge`] instead of a trap.
+pub fn set_max_value_size(limit: usize) {
+    VALUE_SIZE_LIMIT.set(limit);
+}
+
+/// Returns the limit set by [`set_max_value_size`], [`MAX_VALUE_SIZE`] by
+/// default.
+#[must_use]
+pub fn max_value_size() -> usize {
+    VALUE_SIZE_LIMIT.get()
+}
+
+fn check_value_size(bytes: &[u8]) -> Result<(), Error> {
+    let max = max_value_size();
+    if bytes.len() > max {
+        return Err(Error::LengthTooLarge {
+            len: bytes.len(),
+            max,
+            direction: Direction::Input,
+        });
+    }
+
+    Ok(())
+}
+
+/// Which way the bytes rejected with [`Error::LengthTooLarge`] were going.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes passed into a call: the arguments of a cross-program call, or a
+    /// value or event passed to the host.
+    Input,
+    /// Bytes returned by a call: the result of a cross-program call, or the
+    /// return value of a `#[public]` function.
+    Output,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Input => "input",
+            Self::Output => "output",
+        })
+    }
+}
 
 #[derive(Clone, thiserror::Error, Debug)]
 pub enum Error {
@@ -10,11 +149,14 @@ pub enum Error {
     #[error("invalid byte format")]
     InvalidBytes,
 
-    #[error("invalid byte length: {0}")]
-    InvalidByteLength(usize),
+    #[error("invalid byte length: expected {expected}, got {got}")]
+    InvalidByteLength { expected: usize, got: usize },
+
+    #[error("invalid pointer offset: {ptr:#x}")]
+    InvalidPointer { ptr: i64 },
 
-    #[error("invalid pointer offset")]
-    InvalidPointer,
+    #[error("null pointer")]
+    NullPointer,
 
     #[error("invalid tag: {0}")]
     InvalidTag(u8),
@@ -28,25 +170,116 @@ pub enum Error {
     #[error("failed to serialize bytes")]
     Serialization,
 
-    #[error("failed to deserialize bytes")]
-    Deserialization,
+    #[error("failed to deserialize bytes: {0}")]
+    Deserialization(String),
+
+    #[error("stored value of key {key:02x?} cannot be decoded")]
+    CorruptState {
+        key: Vec<u8>,
+        /// Why the value cannot be decoded.
+        #[source]
+        source: Box<Error>,
+    },
 
     #[error("failed to convert integer")]
     IntegerConversion,
 
+    #[error("division by zero")]
+    DivisionByZero,
+
+    #[error("arithmetic overflow")]
+    Overflow,
+
     #[error("failed to delete from host storage")]
     Delete,
+
+    #[error("failed to emit event")]
+    Emit,
+
+    #[error("invalid encoding")]
+    InvalidEncoding,
+
+    #[error("failed to call program")]
+    Call,
+
+    #[error("failed to verify proof")]
+    Verify,
+
+    #[error("host function failed with code {0}")]
+    HostError(i64),
+
+    #[error("program panicked: {0}")]
+    Panicked(String),
+
+    #[error("failed to allocate memory")]
+    AllocationFailed,
+
+    #[error("misaligned pointer")]
+    Misaligned,
+
+    #[error("unknown enum variant: {index}")]
+    UnknownVariant { index: u8 },
+
+    #[error("{direction} of {len} bytes exceeds the limit of {max} bytes")]
+    LengthTooLarge {
+        len: usize,
+        max: usize,
+        direction: Direction,
+    },
+
+    #[error("state is read-only in a view")]
+    ReadOnly,
+
+    #[error("a key read by the transaction was written before it committed")]
+    Conflict,
+}
+
+impl Error {
+    /// Wraps a [borsh] deserialization error. An enum decoded through
+    /// [`from_host_ptr_enum`](crate::from_host_ptr_enum) has its variant
+    /// index checked before borsh sees it, so an out-of-range index is
+    /// reported as [`Error::UnknownVariant`] rather than through here.
+    pub(crate) fn from_borsh(err: &std::io::Error) -> Self {
+        Self::Deserialization(err.to_string())
+    }
+
+    /// Reports that the value stored under `key` cannot be decoded, because
+    /// of `source`.
+    pub(crate) fn corrupt_state(key: Vec<u8>, source: Self) -> Self {
+        Self::CorruptState {
+            key,
+            source: Box::new(source),
+        }
+    }
 }
 
-pub struct State<K>
+/// The number of entries [`State::scan_raw`] fetches from the host at a time.
+#[cfg(feature = "unstable")]
+pub const SCAN_PAGE_SIZE: u32 = 64;
+
+/// A key and its value, as raw bytes.
+#[cfg(feature = "unstable")]
+pub type RawEntry = (Vec<u8>, Vec<u8>);
+
+/// A page of entries returned by [`State::paginate`], followed by the cursor
+/// of the next page.
+#[cfg(feature = "unstable")]
+pub type Page = (Vec<RawEntry>, Option<Vec<u8>>);
+
+/// Values are encoded with the [`WireFormat`] `F`, [borsh] by default.
+pub struct State<K, F = BorshFormat>
 where
     K: Into<Key> + Hash + PartialEq + Eq + Clone,
 {
     program: Program,
-    cache: HashMap<K, Vec<u8>>,
+    /// Values read from or pending a write to the host.
+    cache: StateCache<K>,
+    /// Whether the cache holds a write the host has not seen yet.
+    modified: bool,
+    format: PhantomData<F>,
 }
 
-impl<K> Drop for State<K>
+impl<K, F> Drop for State<K, F>
 where
     K: Into<Key> + Hash + PartialEq + Eq + Clone,
 {
@@ -58,15 +291,31 @@ where
     }
 }
 
-impl<K> State<K>
+impl<K, F> State<K, F>
 where
     K: Into<Key> + Hash + PartialEq + Eq + Clone,
 {
     #[must_use]
     pub fn new(program: Program) -> Self {
+        Self::with_cache(program, StateCache::new(usize::MAX))
+    }
+
+    /// Like [`State::new`], but caches at most `max_entries` keys, which
+    /// bounds the memory of a [State] that touches many keys. Once the cache
+    /// is full, the least recently used key is evicted to make room for a new
+    /// one, after its pending write, if any, is sent to the host. At least one
+    /// key is always cached.
+    #[must_use]
+    pub fn with_capacity(program: Program, max_entries: usize) -> Self {
+        Self::with_cache(program, StateCache::new(max_entries.max(1)))
+    }
+
+    fn with_cache(program: Program, cache: StateCache<K>) -> Self {
         Self {
             program,
-            cache: HashMap::new(),
+            cache,
+            modified: false,
+            format: PhantomData,
         }
     }
 
@@ -74,164 +323,1677 @@ where
     /// the value will be overwritten.
     /// # Errors
     /// Returns an [Error] if the key or value cannot be
-    /// serialized or if the host fails to handle the operation.
+    /// serialized or if the host fails to handle the operation. Returns
+    /// [`Error::LengthTooLarge`] if the encoded value is longer than
+    /// [`max_value_size`], or [`Error::ReadOnly`] in a `#[view]` function.
     pub fn store<V>(&mut self, key: K, value: &V) -> Result<(), Error>
     where
-        V: BorshSerialize,
+        F: WireEncode<V>,
     {
-        let serialized = to_vec(&value).map_err(|_| StateError::Deserialization)?;
-        self.cache.insert(key, serialized);
+        check_writable()?;
+        let serialized = F::encode(value)?;
+        check_value_size(&serialized)?;
+        self.make_room(&key)?;
+        self.cache.insert(key, Some(serialized), true);
+        self.modified = true;
 
         Ok(())
     }
 
-    /// Get a value from the host's storage.
+    /// Get a value from the host's storage, or `None` if the key is absent.
+    /// Both present and absent results are cached, so repeated lookups of the
+    /// same key only cross to the host once per [State].
     ///
     /// Note: The pointer passed to the host are only valid for the duration of this
     /// function call. This function will take ownership of the pointer and free it.
     ///
     /// # Errors
     /// Returns an [Error] if the key cannot be serialized or if
-    /// the host fails to read the key and value.
+    /// the host fails to read the key and value. Returns
+    /// [`Error::CorruptState`] with the key if the stored bytes cannot be
+    /// decoded as a `V`.
     /// # Panics
     /// Panics if the value cannot be converted from i32 to usize.
-    pub fn get<V>(&mut self, key: K) -> Result<V, Error>
+    pub fn get<V>(&mut self, key: K) -> Result<Option<V>, Error>
     where
-        V: BorshDeserialize,
+        F: WireDecode<V>,
     {
-        let val_bytes = if let Some(val) = self.cache.get(&key) {
-            val
-        } else {
-            let val_ptr = unsafe { host::get_bytes(&self.program, &key.clone().into())? };
-            if val_ptr < 0 {
-                return Err(Error::Read);
-            }
+        let Some(bytes) = self.get_encoded(&key)? else {
+            return Ok(None);
+        };
+
+        F::decode(bytes)
+            .map(Some)
+            .map_err(|err| Error::corrupt_state(key.into().to_vec(), err))
+    }
+
+    /// Returns the encoded value of `key`, read through the cache.
+    fn get_encoded(&mut self, key: &K) -> Result<Option<&[u8]>, Error> {
+        if self.cache.contains(key) {
+            return Ok(self.cache.get(key).and_then(Option::as_deref));
+        }
+
+        let host_key = key.clone().into();
+        let val_ptr = metrics::record("state.get", host_key.len(), || unsafe {
+            host::get_bytes(&self.program, &host_key)
+        })?;
+        // the host has nothing to return for a missing key
+        let bytes = decode_optional(val_ptr)?.map(from_host_ptr).transpose()?;
+
+        self.make_room(key)?;
+        Ok(self.cache.insert(key.clone(), bytes, false))
+    }
+
+    /// Returns the length of the encoded value of `key`, or `None` if the key
+    /// is absent, without reading the value itself. A cached value is
+    /// measured locally; otherwise only the length crosses from the host, so
+    /// this is much cheaper than [`State::get`] for a large value. The result
+    /// is not cached.
+    /// # Errors
+    /// Returns an [Error] if the host fails to look up the key.
+    #[cfg(feature = "unstable")]
+    pub fn value_len(&mut self, key: K) -> Result<Option<usize>, Error> {
+        if let Some(value) = self.cache.get(&key) {
+            return Ok(value.as_ref().map(Vec::len));
+        }
+
+        let key = key.into();
+        let len = metrics::record("state.len", key.len(), || unsafe {
+            host::len_bytes(&self.program, &key)
+        })?;
+        // the host has nothing to return for a missing key
+        decode_optional(len)?
+            .map(|len| usize::try_from(len).map_err(|_| Error::IntegerConversion))
+            .transpose()
+    }
+
+    /// Replaces the value stored for `key` with `new` and returns the previous
+    /// value, or `None` if the key was absent. Unless the key is already
+    /// cached, both happen in a single call to the host, which writes `new`
+    /// immediately rather than when the [State] is flushed.
+    /// # Errors
+    /// Returns an [Error] if the key or value cannot be serialized, if the
+    /// host fails to handle the operation or if the previous value cannot be
+    /// decoded. Returns [`Error::LengthTooLarge`] if the encoded value is
+    /// longer than [`max_value_size`], or [`Error::ReadOnly`] in a `#[view]`
+    /// function.
+    #[cfg(feature = "unstable")]
+    pub fn swap<V>(&mut self, key: K, new: &V) -> Result<Option<V>, Error>
+    where
+        F: WireFormat<V>,
+    {
+        check_writable()?;
+        let serialized = F::encode(new)?;
+        check_value_size(&serialized)?;
+
+        if let Some(cached) = self.cache.get_mut(&key) {
+            let old = cached.value.replace(serialized);
+            cached.dirty = true;
+            self.modified = true;
+            return old.as_deref().map(F::decode).transpose();
+        }
+
+        let host_key = key.into();
+        let old_ptr =
+            metrics::record("state.swap", host_key.len() + serialized.len(), || unsafe {
+                host::swap_bytes(&self.program, &host_key, &serialized)
+            })?;
+        // the host has nothing to return for a missing key
+        let Some(old_ptr) = decode_optional(old_ptr)? else {
+            return Ok(None);
+        };
+
+        let old: Vec<u8> = from_host_ptr(old_ptr)?;
+        F::decode(&old).map(Some)
+    }
+
+    /// Deletes the value stored for `key` and returns it, or `None` if the key
+    /// was absent, like [`HashMap::remove`]. Both happen in a single call to
+    /// the host, which deletes the key immediately.
+    /// # Errors
+    /// Returns an [Error] if the key cannot be serialized, if the host fails
+    /// to handle the operation or if the previous value cannot be decoded.
+    /// Returns [`Error::ReadOnly`] in a `#[view]` function.
+    #[cfg(feature = "unstable")]
+    pub fn take<V>(&mut self, key: K) -> Result<Option<V>, Error>
+    where
+        F: WireDecode<V>,
+    {
+        check_writable()?;
+
+        // the cache is only updated once the host has deleted the key, so a
+        // failed call leaves it as it was
+        let host_key = key.clone().into();
+
+        // a cached value, pending or not, is the latest one
+        if let Some(cached) = self.cache.get_mut(&key) {
+            metrics::record("state.delete", host_key.len(), || unsafe {
+                host::delete_bytes(&self.program, &host_key)
+            })?;
+            cached.dirty = false;
+            return cached.value.take().as_deref().map(F::decode).transpose();
+        }
+
+        let old_ptr = metrics::record("state.take", host_key.len(), || unsafe {
+            host::take_bytes(&self.program, &host_key)
+        })
+        .and_then(decode_optional)?;
+        self.make_room(&key)?;
+        self.cache.insert(key, None, false);
+        // the host has nothing to return for a missing key
+        let Some(old_ptr) = old_ptr else {
+            return Ok(None);
+        };
+
+        let old: Vec<u8> = from_host_ptr(old_ptr)?;
+        F::decode(&old).map(Some)
+    }
+
+    /// Like [`State::get`], but returns the encoded value in place instead of
+    /// decoding it, for values that are only inspected briefly, like a
+    /// signature to verify.
+    ///
+    /// A value read from the host is neither copied nor cached: the returned
+    /// [`StateRef`] borrows the block the host wrote it to and frees it when
+    /// dropped. A value that is already cached is borrowed from the cache, so
+    /// the guard also keeps the [State] borrowed.
+    /// # Errors
+    /// Returns an [Error] if the key cannot be serialized, the host fails to
+    /// read the value or the block it returns is malformed.
+    pub fn get_borrowed(&mut self, key: K) -> Result<Option<StateRef<'_>>, Error> {
+        if let Some(cached) = self.cache.get(&key) {
+            return Ok(cached
+                .as_deref()
+                .map(|bytes| StateRef(Borrowed::Cached(bytes))));
+        }
 
-            // TODO Wrap in OK for now, change from_raw_ptr to return Result
-            let bytes = from_host_ptr(val_ptr)?;
-            self.cache.entry(key).or_insert(bytes)
+        let host_key = key.into();
+        let val_ptr = metrics::record("state.get", host_key.len(), || unsafe {
+            host::get_bytes(&self.program, &host_key)
+        })?;
+        // the host has nothing to return for a missing key
+        let Some(val_ptr) = decode_optional(val_ptr)? else {
+            return Ok(None);
         };
 
-        from_slice::<V>(val_bytes).map_err(|_| StateError::Deserialization)
+        let block = Block::borrow(val_ptr).ok_or(Error::InvalidPointer { ptr: val_ptr })?;
+        // the host writes the value as a length-prefixed byte vector
+        match block.split_first_chunk::<4>() {
+            Some((len, value)) if u32::from_le_bytes(*len) as usize == value.len() => {
+                Ok(Some(StateRef(Borrowed::Host(block))))
+            }
+            _ => Err(Error::InvalidBytes),
+        }
+    }
+
+    /// Reads the value stored for `key` and returns an [Entry] to insert or
+    /// modify it in place. Committing the entry stores the result in this
+    /// [State], so the whole operation costs at most one read and one write.
+    /// # Errors
+    /// Returns an [Error] if the host fails to read the value or it cannot be
+    /// decoded.
+    pub fn entry<V>(&mut self, key: K) -> Result<Entry<'_, K, V, F>, Error>
+    where
+        F: WireFormat<V>,
+    {
+        let value = self.get(key.clone())?;
+
+        Ok(Entry {
+            state: self,
+            key,
+            value,
+            dirty: false,
+        })
     }
 
     /// Delete a value from the hosts's storage.
     /// # Errors
     /// Returns an [Error] if the key cannot be serialized
-    /// or if the host fails to delete the key and the associated value
+    /// or if the host fails to delete the key and the associated value.
+    /// Returns [`Error::ReadOnly`] in a `#[view]` function.
     pub fn delete(&mut self, key: K) -> Result<(), Error> {
-        self.cache.remove(&key);
+        check_writable()?;
+        self.make_room(&key)?;
+        // the host deletes the key right away, so there is nothing to write
+        self.cache.insert(key.clone(), None, false);
+
+        let key = key.into();
+        metrics::record("state.delete", key.len(), || unsafe {
+            host::delete_bytes(&self.program, &key)
+        })
+    }
+
+    /// Runs `call`, a call into another program or a delegate call, with the
+    /// cache written back before it and emptied after it. The callee may read
+    /// or write any key this [State] has cached, including by re-entering the
+    /// program: flushing first lets it see the pending writes, and emptying
+    /// the cache lets the next read see whatever it wrote instead of a stale
+    /// copy. Memoized values are invalidated as well.
+    ///
+    /// Both [`Program::delegate_call`] and [`Program::call_function`] should
+    /// be made through this method while a [State] of the caller is alive.
+    /// # Errors
+    /// Returns an [Error] if the pending writes cannot be flushed, in which
+    /// case `call` is not run.
+    pub fn call<R>(&mut self, call: impl FnOnce() -> R) -> Result<R, Error> {
+        self.flush()?;
+        let result = call();
+        self.cache.clear();
+        memo::invalidate();
+
+        Ok(result)
+    }
+
+    /// Starts a [Transaction] over the program's storage. Pending writes are
+    /// flushed first, so the transaction reads them.
+    /// # Errors
+    /// Returns an [Error] if the pending writes cannot be flushed.
+    #[cfg(feature = "unstable")]
+    pub fn transaction(&mut self) -> Result<Transaction<'_, K, F>, Error> {
+        self.flush()?;
+
+        Ok(Transaction {
+            state: self,
+            reads: Vec::new(),
+            writes: HashMap::new(),
+        })
+    }
+
+    /// Returns up to `limit` entries of the program's storage whose keys start
+    /// with `prefix`, in ascending key order, and the cursor to pass as
+    /// `start_after` to fetch the next page. The cursor is `None` once the
+    /// last entry has been returned.
+    ///
+    /// `start_after` is exclusive: the page starts with the first key strictly
+    /// greater than it, so passing back the cursor never repeats an entry.
+    /// `None` starts at the first key under `prefix`. A `limit` of zero
+    /// returns an empty page and no cursor.
+    ///
+    /// Keys are returned in full, including `prefix`, and values as they were
+    /// encoded. Pending writes are flushed first so the page includes them.
+    /// # Errors
+    /// Returns an [Error] if the pending writes cannot be flushed, the host
+    /// fails to read the page or the page it returns is malformed.
+    #[cfg(feature = "unstable")]
+    pub fn paginate(
+        &mut self,
+        prefix: &[u8],
+        start_after: Option<&[u8]>,
+        limit: u32,
+    ) -> Result<Page, Error> {
+        self.flush()?;
+
+        let ptr = metrics::record("state.paginate", prefix.len(), || unsafe {
+            host::paginate_bytes(&self.program, prefix, start_after, limit)
+        })?;
+        from_host_ptr(decode_result(ptr)?)
+    }
+
+    /// Iterates over every entry of the program's storage whose key starts
+    /// with `prefix`, in ascending key order, as raw key and value bytes. No
+    /// value is decoded, so entries of different types can be scanned
+    /// together.
+    ///
+    /// Entries are fetched lazily, [`SCAN_PAGE_SIZE`] at a time, with
+    /// [`State::paginate`]. The first page is fetched before returning;
+    /// errors fetching a later one are yielded by the iterator, which then
+    /// ends.
+    ///
+    /// Stopping early with [`Iterator::take`] still has the host read whole
+    /// pages, so `take(1)` pays for [`SCAN_PAGE_SIZE`] entries. Use
+    /// [`State::scan_raw_limit`] to have the host read no more than needed.
+    /// # Errors
+    /// Returns an [Error] if the first page cannot be fetched.
+    #[cfg(feature = "unstable")]
+    pub fn scan_raw(&mut self, prefix: &[u8]) -> Result<StateIterator<'_, K, F>, Error> {
+        StateIterator::new(self, prefix, None)
+    }
 
-        unsafe { host::delete_bytes(&self.program, &key.into()) }
+    /// Like [`State::scan_raw`], but yields at most `limit` entries, and the
+    /// host never reads more than that: the last page is shrunk to the
+    /// entries still wanted. Bounding a scan this way keeps a large prefix
+    /// from using up the call's units.
+    /// # Errors
+    /// Returns an [Error] if the first page cannot be fetched.
+    #[cfg(feature = "unstable")]
+    pub fn scan_raw_limit(
+        &mut self,
+        prefix: &[u8],
+        limit: u32,
+    ) -> Result<StateIterator<'_, K, F>, Error> {
+        StateIterator::new(self, prefix, Some(limit))
     }
 
     /// Apply all pending operations to storage and mark the cache as flushed
     fn flush(&mut self) -> Result<(), Error> {
-        for (key, value) in self.cache.drain() {
-            unsafe {
-                host::put_bytes(&self.program, &key.into(), &value)?;
-            }
+        // writes are rejected in a view, so the cache only holds what was read,
+        // and a destroyed program has no state left to write to
+        if is_read_only() || is_destroyed(&self.program) {
+            self.cache.clear();
+            return Ok(());
+        }
+
+        for (key, value) in self.cache.drain_pending() {
+            let key = key.into();
+            metrics::record("state.put", key.len() + value.len(), || unsafe {
+                host::put_bytes(&self.program, &key, &value)
+            })?;
+        }
+
+        // values memoized since the cached writes were made read the old ones
+        if std::mem::take(&mut self.modified) {
+            memo::invalidate();
         }
 
         Ok(())
     }
-}
 
-/// Key is a wrapper around a `Vec<u8>` that represents a key in the host storage.
-#[derive(Debug, Default, Clone)]
-pub struct Key(Vec<u8>);
+    /// Evicts the least recently used key if the cache is full and `key` is
+    /// not in it, sending its pending write to the host first.
+    fn make_room(&mut self, key: &K) -> Result<(), Error> {
+        let Some(victim) = self.cache.victim(key) else {
+            return Ok(());
+        };
 
-impl Deref for Key {
-    type Target = [u8];
+        if let Some(value) = self
+            .cache
+            .pending(&victim)
+            .filter(|_| !is_destroyed(&self.program))
+        {
+            let host_key = victim.clone().into();
+            metrics::record("state.put", host_key.len() + value.len(), || unsafe {
+                host::put_bytes(&self.program, &host_key, &value)
+            })?;
+        }
+        self.cache.remove(&victim);
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+        Ok(())
     }
 }
 
-impl Key {
-    /// Returns a new Key from the bytes.
-    #[must_use]
-    pub fn new(bytes: Vec<u8>) -> Self {
-        Self(bytes)
-    }
+/// An iterator over the raw entries under a prefix, returned by
+/// [`State::scan_raw`] and [`State::scan_raw_limit`].
+#[cfg(feature = "unstable")]
+pub struct StateIterator<'a, K, F = BorshFormat>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+{
+    state: &'a mut State<K, F>,
+    prefix: Vec<u8>,
+    /// The fetched entries not yielded yet.
+    page: std::vec::IntoIter<RawEntry>,
+    /// The key the next page starts after, or `None` once the host has
+    /// returned the last entry.
+    cursor: Option<Vec<u8>>,
+    /// The number of entries the host may still read, if limited.
+    remaining: Option<u32>,
 }
 
-macro_rules! ffi_linker {
-    ($mod:literal, $link:literal, $caller:ident, $key:ident) => {
-        #[link(wasm_import_module = $mod)]
-        extern "C" {
-            #[link_name = $link]
-            fn ffi(caller: i64, key: i64) -> i64;
+#[cfg(feature = "unstable")]
+impl<'a, K, F> StateIterator<'a, K, F>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+{
+    fn new(
+        state: &'a mut State<K, F>,
+        prefix: &[u8],
+        remaining: Option<u32>,
+    ) -> Result<Self, Error> {
+        let mut iter = Self {
+            state,
+            prefix: prefix.to_vec(),
+            page: Vec::new().into_iter(),
+            cursor: None,
+            remaining,
+        };
+        iter.rewind()?;
+
+        Ok(iter)
+    }
+
+    /// Repositions the iterator at the first key under the prefix that is
+    /// greater than or equal to `key`, dropping the entries fetched but not
+    /// yielded yet. Seeking backwards is allowed, and yields entries again.
+    ///
+    /// A `key` that sorts before every key under the prefix, such as the
+    /// prefix itself, starts over from the first entry. A `key` that sorts
+    /// after every key under the prefix ends the iteration. Seeking does not
+    /// reset the limit of [`State::scan_raw_limit`]: entries the host read
+    /// before the seek still count against it.
+    /// # Errors
+    /// Returns an [Error] if the pending writes cannot be flushed or the host
+    /// fails to read the entry at `key` or the first page.
+    pub fn seek(&mut self, key: &[u8]) -> Result<(), Error> {
+        if key <= self.prefix.as_slice() {
+            return self.rewind();
         }
 
-        let $caller = to_host_ptr($caller.id())?;
-        let $key = to_host_ptr($key)?;
-    };
-    ($mod:literal, $link:literal, $caller:ident, $key:ident, $value:ident) => {
-        #[link(wasm_import_module = $mod)]
-        extern "C" {
-            #[link_name = $link]
-            fn ffi(caller: i64, key: i64, value: i64) -> i64;
+        self.page = Vec::new().into_iter();
+        self.cursor = None;
+        if !key.starts_with(&self.prefix) || self.remaining == Some(0) {
+            return Ok(());
         }
 
-        let $caller = to_host_ptr($caller.id())?;
-        let $key = to_host_ptr($key)?;
-        let value_bytes = borsh::to_vec($value).map_err(|_| Error::Serialization)?;
-        let $value = to_host_ptr(&value_bytes)?;
-    };
-}
+        // pages start after a key, so the entry at `key` itself is read on
+        // its own
+        self.state.flush()?;
+        let host_key = Key::new(key.to_vec());
+        let ptr = metrics::record("state.get", host_key.len(), || unsafe {
+            host::get_bytes(&self.state.program, &host_key)
+        })?;
+        // the host has nothing to return for a missing key
+        if let Some(ptr) = decode_optional(ptr)? {
+            let value: Vec<u8> = from_host_ptr(ptr)?;
+            self.remaining = self.remaining.map(|left| left - 1);
+            self.page = vec![(key.to_vec(), value)].into_iter();
+        }
+        self.cursor = Some(key.to_vec());
 
-macro_rules! call_host_fn {
-    (
-        wasm_import_module = $mod:literal
-        link_name = $link:literal
-        args = ($caller:ident, $key:ident)
-    ) => {{
-        ffi_linker!($mod, $link, $caller, $key);
+        Ok(())
+    }
 
-        unsafe { ffi($caller, $key) }
-    }};
+    /// Fetches the first page under the prefix.
+    fn rewind(&mut self) -> Result<(), Error> {
+        let size = self.page_size();
+        let (page, cursor) = self.state.paginate(&self.prefix, None, size)?;
+        self.page = page.into_iter();
+        self.cursor = cursor;
 
-    (
-        wasm_import_module = $mod:literal
-        link_name = $link:literal
-        args = ($caller:ident, $key:ident, $value:ident)
-    ) => {{
-        ffi_linker!($mod, $link, $caller, $key, $value);
+        Ok(())
+    }
 
-        unsafe { ffi($caller, $key, $value) }
-    }};
+    /// Returns the size of the next page, which uses up what the limit leaves.
+    fn page_size(&mut self) -> u32 {
+        let size = self
+            .remaining
+            .map_or(SCAN_PAGE_SIZE, |left| left.min(SCAN_PAGE_SIZE));
+        self.remaining = self.remaining.map(|left| left - size);
+        size
+    }
 }
 
-mod host {
-    use super::{BorshSerialize, Key, Program};
-    use crate::{memory::to_host_ptr, state::Error};
+#[cfg(feature = "unstable")]
+impl<K, F> Iterator for StateIterator<'_, K, F>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+{
+    type Item = Result<RawEntry, Error>;
 
-    /// Persists the bytes at `value` at key on the host storage.
-    pub(super) unsafe fn put_bytes<V>(caller: &Program, key: &Key, value: &V) -> Result<(), Error>
-    where
-        V: BorshSerialize,
-    {
-        match call_host_fn! {
-            wasm_import_module = "state"
-            link_name = "put"
-            args = (caller, key, value)
-        } {
-            0 => Ok(()),
-            _ => Err(Error::Write),
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.page.next() {
+                return Some(Ok(entry));
+            }
+
+            let start_after = self.cursor.take()?;
+            let size = self.page_size();
+            if size == 0 {
+                return None;
+            }
+            match self.state.paginate(&self.prefix, Some(&start_after), size) {
+                Ok((next, cursor)) => {
+                    self.page = next.into_iter();
+                    self.cursor = cursor;
+                }
+                Err(err) => return Some(Err(err)),
+            }
         }
     }
+}
 
-    /// Gets the bytes associated with the key from the host.
-    pub(super) unsafe fn get_bytes(caller: &Program, key: &Key) -> Result<i64, Error> {
-        Ok(call_host_fn! {
-            wasm_import_module = "state"
-                link_name = "get"
+/// The values a [State] has read from or is about to write to the host, in at
+/// most `capacity` entries.
+struct StateCache<K> {
+    entries: HashMap<K, CacheSlot>,
+    /// The keys of `entries` by the clock at their last access, so the least
+    /// recently used one is found without scanning them.
+    recency: BTreeMap<u64, K>,
+    capacity: usize,
+    /// Counts accesses, to order entries by when they were last used.
+    clock: u64,
+}
+
+struct CacheSlot {
+    /// The encoded value. `None` records that the key is known to be absent,
+    /// so repeated lookups stay local.
+    value: Option<Vec<u8>>,
+    /// Whether the value is a write the host has not seen yet.
+    dirty: bool,
+    /// The clock at the last access.
+    used: u64,
+}
+
+impl<K> StateCache<K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            capacity,
+            clock: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Returns the cached value of `key`, marking it as used.
+    fn get(&mut self, key: &K) -> Option<&Option<Vec<u8>>> {
+        self.get_mut(key).map(|slot| &slot.value)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut CacheSlot> {
+        let slot = self.entries.get_mut(key)?;
+        self.clock += 1;
+        if let Some(key) = self.recency.remove(&slot.used) {
+            self.recency.insert(self.clock, key);
+        }
+        slot.used = self.clock;
+        Some(slot)
+    }
+
+    /// Caches `value`, which is pending a write to the host if `dirty`.
+    fn insert(&mut self, key: K, value: Option<Vec<u8>>, dirty: bool) -> Option<&[u8]> {
+        self.clock += 1;
+        let slot = CacheSlot {
+            value,
+            dirty,
+            used: self.clock,
+        };
+
+        self.recency.insert(self.clock, key.clone());
+        match self.entries.entry(key) {
+            CacheEntry::Occupied(mut entry) => {
+                let previous = entry.insert(slot);
+                self.recency.remove(&previous.used);
+                entry.into_mut().value.as_deref()
+            }
+            CacheEntry::Vacant(entry) => entry.insert(slot).value.as_deref(),
+        }
+    }
+
+    /// Returns the key to evict before `incoming` can be inserted, if any.
+    fn victim(&self, incoming: &K) -> Option<K> {
+        if self.entries.len() < self.capacity || self.entries.contains_key(incoming) {
+            return None;
+        }
+
+        self.recency.values().next().cloned()
+    }
+
+    /// Returns the value of `key` if it is pending a write.
+    fn pending(&self, key: &K) -> Option<Vec<u8>> {
+        let slot = self.entries.get(key)?;
+        slot.value.clone().filter(|_| slot.dirty)
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(slot) = self.entries.remove(key) {
+            self.recency.remove(&slot.used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    /// Empties the cache, returning the values pending a write.
+    fn drain_pending(&mut self) -> impl Iterator<Item = (K, Vec<u8>)> + '_ {
+        self.recency.clear();
+        self.entries
+            .drain()
+            .filter(|(_, slot)| slot.dirty)
+            .filter_map(|(key, slot)| slot.value.map(|value| (key, value)))
+    }
+}
+
+/// The encoded bytes of a value, returned by [`State::get_borrowed`]. Derefs to
+/// the bytes and frees the block backing them, if any, when dropped.
+pub struct StateRef<'a>(Borrowed<'a>);
+
+enum Borrowed<'a> {
+    /// A value read from the host, in the block the host wrote it to.
+    Host(Block),
+    /// A value held in the cache of the [State].
+    Cached(&'a [u8]),
+}
+
+impl Deref for StateRef<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match &self.0 {
+            // skip the length prefix, which was checked when reading the block
+            Borrowed::Host(block) => &block[4..],
+            Borrowed::Cached(bytes) => bytes,
+        }
+    }
+}
+
+/// A value of a [State] being inserted or modified, returned by
+/// [`State::entry`]. Changes are stored on [commit](Entry::commit), or when the
+/// entry is dropped, which ignores any error. Commit to handle one.
+pub struct Entry<'a, K, V, F = BorshFormat>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+    F: WireFormat<V>,
+{
+    state: &'a mut State<K, F>,
+    key: K,
+    value: Option<V>,
+    dirty: bool,
+}
+
+impl<K, V, F> Entry<'_, K, V, F>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+    F: WireFormat<V>,
+{
+    /// Calls `f` with the value if it is present.
+    #[must_use]
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Some(value) = &mut self.value {
+            f(value);
+            self.dirty = true;
+        }
+        self
+    }
+
+    /// Inserts `default` if the value is absent and returns the value.
+    pub fn or_insert(&mut self, default: V) -> &mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `f` if the value is absent and returns the value.
+    pub fn or_insert_with(&mut self, f: impl FnOnce() -> V) -> &mut V {
+        // the caller may mutate the value through the returned reference
+        self.dirty = true;
+        self.value.get_or_insert_with(f)
+    }
+
+    /// Stores the value in the [State] if it was inserted or modified.
+    /// # Errors
+    /// Returns an [Error] if the value cannot be encoded.
+    pub fn commit(mut self) -> Result<(), Error> {
+        self.write()
+    }
+
+    fn write(&mut self) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        match &self.value {
+            Some(value) => self.state.store(self.key.clone(), value),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<K, V, F> Drop for Entry<'_, K, V, F>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+    F: WireFormat<V>,
+{
+    fn drop(&mut self) {
+        // the error, if any, is lost, see `commit`
+        let _ = self.write();
+    }
+}
+
+/// A set of reads and writes of a [State] that is applied only if none of the
+/// keys it read were written in the meantime, for example by a call into
+/// another program that re-entered this one. Returned by [`State::transaction`].
+///
+/// Every key is read straight from the host together with its version, a
+/// counter the host bumps whenever the key is written or deleted, whether or
+/// not it was present before. Writes are buffered in the transaction. On
+/// [commit](Transaction::commit), the host is asked whether every key read
+/// still has the version it was read at; only then are the buffered writes
+/// applied. A host that doesn't keep per-key versions can't support
+/// transactions.
+///
+/// Dropping a transaction without committing it discards its writes.
+#[cfg(feature = "unstable")]
+pub struct Transaction<'a, K, F = BorshFormat>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+{
+    state: &'a mut State<K, F>,
+    /// Every key read from the host and the version it was read at.
+    reads: Vec<(Vec<u8>, u64)>,
+    /// Writes to apply on commit. A `None` entry is a delete.
+    writes: HashMap<K, Option<Vec<u8>>>,
+}
+
+#[cfg(feature = "unstable")]
+impl<K, F> Transaction<'_, K, F>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+{
+    /// Returns the value of `key`, or `None` if the key is absent. A key
+    /// written by the transaction reads as the buffered value; any other key
+    /// is added to the set checked on commit.
+    /// # Errors
+    /// Returns an [Error] if the host fails to read the key, or
+    /// [`Error::CorruptState`] if the stored value cannot be decoded.
+    pub fn get<V>(&mut self, key: K) -> Result<Option<V>, Error>
+    where
+        F: WireDecode<V>,
+    {
+        if let Some(value) = self.writes.get(&key) {
+            return value.as_deref().map(F::decode).transpose();
+        }
+
+        let key: Key = key.into();
+        let version = metrics::record("state.version", key.len(), || unsafe {
+            host::version(&self.state.program, &key)
+        })?;
+        if !self.reads.iter().any(|(read, _)| **read == *key) {
+            self.reads.push((key.to_vec(), version));
+        }
+
+        let val_ptr = metrics::record("state.get", key.len(), || unsafe {
+            host::get_bytes(&self.state.program, &key)
+        })?;
+        // the host has nothing to return for a missing key
+        let Some(val_ptr) = decode_optional(val_ptr)? else {
+            return Ok(None);
+        };
+
+        let bytes: Vec<u8> = from_host_ptr(val_ptr)?;
+        F::decode(&bytes)
+            .map(Some)
+            .map_err(|err| Error::corrupt_state(key.to_vec(), err))
+    }
+
+    /// Buffers a write of `value` to `key`.
+    /// # Errors
+    /// Returns an [Error] if the value cannot be encoded, is longer than
+    /// [`max_value_size`], or [`Error::ReadOnly`] in a `#[view]` function.
+    pub fn store<V>(&mut self, key: K, value: &V) -> Result<(), Error>
+    where
+        F: WireEncode<V>,
+    {
+        check_writable()?;
+        let serialized = F::encode(value)?;
+        check_value_size(&serialized)?;
+        self.writes.insert(key, Some(serialized));
+
+        Ok(())
+    }
+
+    /// Buffers a delete of `key`.
+    /// # Errors
+    /// Returns [`Error::ReadOnly`] in a `#[view]` function.
+    pub fn delete(&mut self, key: K) -> Result<(), Error> {
+        check_writable()?;
+        self.writes.insert(key, None);
+
+        Ok(())
+    }
+
+    /// Applies the buffered writes if no key read by the transaction has been
+    /// written since it was read.
+    /// # Errors
+    /// Returns [`Error::Conflict`], without applying any write, if a key read
+    /// has changed, or an [Error] if the host fails to check the versions or
+    /// to apply a write.
+    pub fn commit(self) -> Result<(), Error> {
+        let reads = to_vec(&self.reads).map_err(|_| Error::Serialization)?;
+        let unchanged = metrics::record("state.check_versions", reads.len(), || unsafe {
+            host::check_versions(&self.state.program, &reads)
+        })?;
+        if !unchanged {
+            return Err(Error::Conflict);
+        }
+
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+
+        for (key, value) in self.writes {
+            let key = key.into();
+            if let Some(value) = value {
+                metrics::record("state.put", key.len() + value.len(), || unsafe {
+                    host::put_bytes(&self.state.program, &key, &value)
+                })?;
+            } else {
+                metrics::record("state.delete", key.len(), || unsafe {
+                    host::delete_bytes(&self.state.program, &key)
+                })?;
+            }
+        }
+        memo::invalidate();
+
+        Ok(())
+    }
+}
+
+/// Values that expire are only supported with the default [borsh] encoding.
+#[cfg(feature = "unstable")]
+impl<K> State<K, BorshFormat>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+{
+    /// Store `value` until the block timestamp reaches `expires_at`, after which
+    /// [`State::get_with_ttl`] treats it as absent. The expiry is stored next
+    /// to the value, so it must be read back with [`State::get_with_ttl`].
+    ///
+    /// Expiry is lazy: an expired value is hidden on read but stays in the
+    /// host storage, and keeps occupying it, until it is overwritten or
+    /// deleted.
+    /// # Errors
+    /// Returns an [Error] if the key or value cannot be serialized, or
+    /// [`Error::LengthTooLarge`] if the value and its expiry together are
+    /// longer than [`max_value_size`]. Returns [`Error::ReadOnly`] in a
+    /// `#[view]` function.
+    pub fn put_with_ttl<V>(&mut self, key: K, value: &V, expires_at: u64) -> Result<(), Error>
+    where
+        V: BorshSerialize,
+    {
+        check_writable()?;
+        let serialized = to_vec(&(expires_at, value)).map_err(|_| Error::Serialization)?;
+        check_value_size(&serialized)?;
+        self.make_room(&key)?;
+        self.cache.insert(key, Some(serialized), true);
+        self.modified = true;
+
+        Ok(())
+    }
+
+    /// Get a value stored with [`State::put_with_ttl`], or `None` if it is
+    /// absent or the block timestamp has reached its expiry.
+    /// # Errors
+    /// Returns an [Error] if the key cannot be serialized or if
+    /// the host fails to read the key and value.
+    pub fn get_with_ttl<V>(&mut self, key: K) -> Result<Option<V>, Error>
+    where
+        V: BorshDeserialize,
+    {
+        let Some((expires_at, value)) = self.get::<(u64, V)>(key)? else {
+            return Ok(None);
+        };
+
+        Ok((context::timestamp() < expires_at).then_some(value))
+    }
+}
+
+/// A view of a program's storage in which every key is prefixed with the name
+/// of a module, so that modules of the same program can use the same logical
+/// keys without overwriting each other.
+///
+/// The module name and the key are each prefixed with their length, see
+/// [`StateNamespace::key`], so no two modules share a key and a key of the
+/// namespace never matches an entry of a [`Map`](crate::collections::Map) in
+/// it. Keys written through a plain [State] of the same program are not
+/// prefixed and should not be mixed with namespaced ones.
+pub struct StateNamespace<K> {
+    prefix: Key,
+    state: State<Key>,
+    _marker: PhantomData<K>,
+}
+
+impl<K> StateNamespace<K> {
+    /// Returns the program whose storage the namespace is part of.
+    #[must_use]
+    pub fn program(&self) -> Program {
+        self.state.program
+    }
+
+    /// Returns the bytes every key of the namespace starts with.
+    #[must_use]
+    pub fn prefix(&self) -> &Key {
+        &self.prefix
+    }
+}
+
+impl<K> StateNamespace<K>
+where
+    K: Into<Key>,
+{
+    /// Returns the namespace of `module` in the storage of `program`.
+    /// # Errors
+    /// Returns an [Error] if the module name is longer than [`u32::MAX`] bytes.
+    pub fn new(program: Program, module: &str) -> Result<Self, Error> {
+        Ok(Self {
+            prefix: KeyBuilder::new().with_raw(module.as_bytes())?.build(),
+            state: State::new(program),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Like [`State::store`], within the namespace.
+    /// # Errors
+    /// Returns an [Error] if the key is longer than [`u32::MAX`] bytes or the
+    /// value cannot be serialized.
+    pub fn store<V>(&mut self, key: K, value: &V) -> Result<(), Error>
+    where
+        V: BorshSerialize,
+    {
+        let key = self.key(key)?;
+        self.state.store(key, value)
+    }
+
+    /// Like [`State::get`], within the namespace.
+    /// # Errors
+    /// Returns an [Error] if the key is longer than [`u32::MAX`] bytes, the
+    /// host fails to read the key and value or the value cannot be
+    /// deserialized.
+    pub fn get<V>(&mut self, key: K) -> Result<Option<V>, Error>
+    where
+        V: BorshDeserialize,
+    {
+        let key = self.key(key)?;
+        self.state.get(key)
+    }
+
+    /// Like [`State::delete`], within the namespace.
+    /// # Errors
+    /// Returns an [Error] if the key is longer than [`u32::MAX`] bytes or the
+    /// host fails to delete the key.
+    pub fn delete(&mut self, key: K) -> Result<(), Error> {
+        let key = self.key(key)?;
+        self.state.delete(key)
+    }
+
+    /// Returns the storage key of `key`, laid out as
+    ///
+    /// ```text
+    /// u32 BE length of module | module | u32 BE length of key | key
+    /// ```
+    ///
+    /// where `module` is the UTF-8 name of the module and `key` the bytes of
+    /// the [Key], neither encoded any further. A key that already carries
+    /// its own length, such as one of a `#[state_keys]` enum, is prefixed as
+    /// is.
+    fn key(&self, key: K) -> Result<Key, Error> {
+        Ok(KeyBuilder::from(self.prefix.clone())
+            .with_raw(&key.into())?
+            .build())
+    }
+}
+
+/// Compressed values are only supported with the default [borsh] encoding.
+#[cfg(feature = "compression")]
+impl<K> State<K, BorshFormat>
+where
+    K: Into<Key> + Hash + PartialEq + Eq + Clone,
+{
+    /// The format byte of a value stored as is.
+    const STORED: u8 = 0;
+    /// The format byte of a value compressed with [`compress`].
+    const COMPRESSED: u8 = 1;
+
+    /// Stores `value` compressed, for large values such as config blobs. The
+    /// value must be read back with [`State::get_compressed`].
+    ///
+    /// The compressor is part of the SDK and its output depends only on the
+    /// serialized value, so every node stores the same bytes. A value that
+    /// does not shrink is stored as is, behind a format byte, so compressing
+    /// never costs more than one byte.
+    /// # Errors
+    /// Returns an [Error] if the value cannot be serialized,
+    /// [`Error::LengthTooLarge`] if the bytes stored are longer than
+    /// [`max_value_size`], or [`Error::ReadOnly`] in a `#[view]` function.
+    pub fn put_compressed<V>(&mut self, key: K, value: &V) -> Result<(), Error>
+    where
+        V: BorshSerialize,
+    {
+        check_writable()?;
+        let serialized = to_vec(value).map_err(|_| Error::Serialization)?;
+        let compressed = compress::compress(&serialized);
+
+        let stored = if compressed.len() < serialized.len() {
+            [&[Self::COMPRESSED], compressed.as_slice()].concat()
+        } else {
+            [&[Self::STORED], serialized.as_slice()].concat()
+        };
+        check_value_size(&stored)?;
+        self.make_room(&key)?;
+        self.cache.insert(key, Some(stored), true);
+        self.modified = true;
+
+        Ok(())
+    }
+
+    /// Get a value stored with [`State::put_compressed`], or `None` if it is
+    /// absent.
+    /// # Errors
+    /// Returns an [Error] if the host fails to read the value, or
+    /// [`Error::InvalidEncoding`] if it was not stored with
+    /// [`State::put_compressed`] or cannot be deserialized.
+    // takes the key by value like every other accessor
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn get_compressed<V>(&mut self, key: K) -> Result<Option<V>, Error>
+    where
+        V: BorshDeserialize,
+    {
+        let Some(stored) = self.get_encoded(&key)? else {
+            return Ok(None);
+        };
+
+        let serialized = match stored.split_first() {
+            Some((&Self::STORED, serialized)) => serialized.to_vec(),
+            Some((&Self::COMPRESSED, compressed)) => compress::decompress(compressed)?,
+            _ => return Err(Error::InvalidEncoding),
+        };
+        borsh::from_slice(&serialized)
+            .map(Some)
+            .map_err(|err| Error::from_borsh(&err))
+    }
+}
+
+/// A single value read from the host storage, for the read-modify-maybe-write
+/// pattern. The cell keeps the bytes it was read from and only serializes and
+/// writes the value back on [flush](StateCell::flush) if it was mutably
+/// accessed and its encoding actually changed.
+///
+/// Pending changes are also flushed when the cell is dropped, which ignores any
+/// error. Flush to handle one.
+pub struct StateCell<V>
+where
+    V: BorshSerialize,
+{
+    program: Program,
+    key: Key,
+    value: Option<V>,
+    /// The bytes the value was read from or last written as.
+    original: Option<Vec<u8>>,
+    dirty: bool,
+}
+
+impl<V> StateCell<V>
+where
+    V: BorshSerialize + BorshDeserialize,
+{
+    /// Reads the value stored for `key`.
+    /// # Errors
+    /// Returns an [Error] if the host fails to read the value or it cannot be
+    /// deserialized.
+    pub fn load<K>(program: Program, key: K) -> Result<Self, Error>
+    where
+        K: Into<Key>,
+    {
+        let key = key.into();
+        let val_ptr = metrics::record("state.get", key.len(), || unsafe {
+            host::get_bytes(&program, &key)
+        })?;
+        // the host has nothing to return for a missing key
+        let original: Option<Vec<u8>> = decode_optional(val_ptr)?.map(from_host_ptr).transpose()?;
+        let value = original
+            .as_deref()
+            .map(BorshFormat::decode)
+            .transpose()
+            .map_err(|err| Error::corrupt_state(key.to_vec(), err))?;
+
+        Ok(Self {
+            program,
+            key,
+            value,
+            original,
+            dirty: false,
+        })
+    }
+}
+
+impl<V> StateCell<V>
+where
+    V: BorshSerialize,
+{
+    /// Returns the value, or `None` if the key is absent.
+    #[must_use]
+    pub fn get(&self) -> Option<&V> {
+        self.value.as_ref()
+    }
+
+    /// Returns the value for mutation, marking the cell as modified.
+    pub fn get_mut(&mut self) -> Option<&mut V> {
+        self.dirty = true;
+        self.value.as_mut()
+    }
+
+    /// Replaces the value, marking the cell as modified.
+    pub fn set(&mut self, value: V) {
+        self.dirty = true;
+        self.value = Some(value);
+    }
+
+    /// Writes the value back if it was modified since it was read or last
+    /// flushed.
+    /// # Errors
+    /// Returns an [Error] if the value cannot be serialized or the host fails
+    /// to write it, or [`Error::LengthTooLarge`] if the encoded value is longer
+    /// than [`max_value_size`], or [`Error::ReadOnly`] if the value was changed
+    /// in a `#[view]` function.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        let Some(value) = &self.value else {
+            return Ok(());
+        };
+        let bytes = to_vec(value).map_err(|_| Error::Serialization)?;
+        if self.original.as_ref() == Some(&bytes) || is_destroyed(&self.program) {
+            return Ok(());
+        }
+        check_writable()?;
+        check_value_size(&bytes)?;
+
+        metrics::record("state.put", self.key.len() + bytes.len(), || unsafe {
+            host::put_bytes(&self.program, &self.key, &bytes)
+        })?;
+        self.original = Some(bytes);
+
+        Ok(())
+    }
+}
+
+impl<V> Drop for StateCell<V>
+where
+    V: BorshSerialize,
+{
+    fn drop(&mut self) {
+        if self.dirty {
+            // the error, if any, is lost, see `flush`
+            let _ = self.flush();
+        }
+    }
+}
+
+/// A field of a struct annotated with `#[state]`, stored under its own key.
+/// The value is read the first time the field is dereferenced, and a value
+/// that was mutably dereferenced is written back on
+/// [flush](StateField::flush) if its encoding actually changed, like a
+/// [`StateCell`]. A field whose key is absent reads as `V::default()`.
+///
+/// Pending changes are also flushed when the field is dropped, which ignores
+/// any error. Flush to handle one.
+pub struct StateField<V>
+where
+    V: BorshSerialize,
+{
+    program: Program,
+    key: Key,
+    /// The value and the bytes it was read from or last written as.
+    loaded: OnceCell<(V, Option<Vec<u8>>)>,
+    dirty: bool,
+}
+
+impl<V> StateField<V>
+where
+    V: BorshSerialize + BorshDeserialize + Default,
+{
+    /// Returns the field stored under `key` of `program`, without reading it.
+    #[must_use]
+    pub fn new(program: Program, key: Key) -> Self {
+        Self {
+            program,
+            key,
+            loaded: OnceCell::new(),
+            dirty: false,
+        }
+    }
+
+    /// Returns the value, reading it from the host if it hasn't been yet.
+    /// Dereferencing the field does the same, but panics on error.
+    /// # Errors
+    /// Returns an [Error] if the host fails to read the value or it cannot be
+    /// deserialized.
+    pub fn load(&self) -> Result<&V, Error> {
+        if let Some((value, _)) = self.loaded.get() {
+            return Ok(value);
+        }
+
+        let val_ptr = metrics::record("state.get", self.key.len(), || unsafe {
+            host::get_bytes(&self.program, &self.key)
+        })?;
+        // the host has nothing to return for a missing key
+        let original: Option<Vec<u8>> = decode_optional(val_ptr)?.map(from_host_ptr).transpose()?;
+        let value = match &original {
+            Some(bytes) => BorshFormat::decode(bytes)
+                .map_err(|err| Error::corrupt_state(self.key.to_vec(), err))?,
+            None => V::default(),
+        };
+
+        Ok(&self.loaded.get_or_init(|| (value, original)).0)
+    }
+}
+
+impl<V> StateField<V>
+where
+    V: BorshSerialize,
+{
+    /// Writes the value back if it was modified since it was read or last
+    /// flushed.
+    /// # Errors
+    /// Returns an [Error] if the value cannot be serialized or the host fails
+    /// to write it, or [`Error::LengthTooLarge`] if the encoded value is longer
+    /// than [`max_value_size`], or [`Error::ReadOnly`] if the value was changed
+    /// in a `#[view]` function.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if !std::mem::take(&mut self.dirty) {
+            return Ok(());
+        }
+        let Some((value, original)) = self.loaded.get_mut() else {
+            return Ok(());
+        };
+
+        let bytes = to_vec(value).map_err(|_| Error::Serialization)?;
+        if original.as_ref() == Some(&bytes) || is_destroyed(&self.program) {
+            return Ok(());
+        }
+        check_writable()?;
+        check_value_size(&bytes)?;
+
+        metrics::record("state.put", self.key.len() + bytes.len(), || unsafe {
+            host::put_bytes(&self.program, &self.key, &bytes)
+        })?;
+        *original = Some(bytes);
+
+        Ok(())
+    }
+}
+
+impl<V> Deref for StateField<V>
+where
+    V: BorshSerialize + BorshDeserialize + Default,
+{
+    type Target = V;
+
+    /// # Panics
+    /// Panics if the value cannot be read, see [`StateField::load`].
+    fn deref(&self) -> &V {
+        self.load().expect("failed to load state field")
+    }
+}
+
+impl<V> DerefMut for StateField<V>
+where
+    V: BorshSerialize + BorshDeserialize + Default,
+{
+    /// Marks the field as modified.
+    /// # Panics
+    /// Panics if the value cannot be read, see [`StateField::load`].
+    fn deref_mut(&mut self) -> &mut V {
+        self.load().expect("failed to load state field");
+        self.dirty = true;
+        &mut self.loaded.get_mut().expect("the field was loaded").0
+    }
+}
+
+impl<V> Drop for StateField<V>
+where
+    V: BorshSerialize,
+{
+    fn drop(&mut self) {
+        if self.dirty {
+            // the error, if any, is lost, see `flush`
+            let _ = self.flush();
+        }
+    }
+}
+
+/// Returns the key of `field` of the `#[state]` struct `name`. Both names are
+/// [`KeyBuilder`] segments, so fields of different structs never share a key.
+/// # Panics
+/// Panics if either name is longer than [`u32::MAX`] bytes.
+#[doc(hidden)]
+#[must_use]
+pub fn field_key(name: &str, field: &str) -> Key {
+    KeyBuilder::new()
+        .with(&name)
+        .and_then(|builder| builder.with(&field))
+        .expect("names are short")
+        .build()
+}
+
+/// Key is a wrapper around a `Vec<u8>` that represents a key in the host storage.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Key(Vec<u8>);
+
+impl Deref for Key {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Key {
+    /// Returns a new Key from the bytes.
+    #[must_use]
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+/// Builds a [Key] out of typed segments. Each segment is [borsh] serialized and
+/// prefixed with its big-endian `u32` length, so two different sequences of
+/// segments can never produce the same key, even when their bytes concatenate
+/// to the same value.
+#[derive(Debug, Default, Clone)]
+pub struct KeyBuilder(Vec<u8>);
+
+impl KeyBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a segment to the key.
+    /// # Errors
+    /// Returns an [Error] if the segment cannot be serialized or its length
+    /// exceeds [`u32::MAX`].
+    pub fn with<T>(mut self, segment: &T) -> Result<Self, Error>
+    where
+        T: BorshSerialize,
+    {
+        let bytes = to_vec(segment).map_err(|_| Error::Serialization)?;
+        let len = u32::try_from(bytes.len()).map_err(|_| Error::IntegerConversion)?;
+        self.0.extend(len.to_be_bytes());
+        self.0.extend(bytes);
+
+        Ok(self)
+    }
+
+    /// Appends `bytes` to the key prefixed with their big-endian `u32` length,
+    /// without serializing them.
+    pub(crate) fn with_raw(mut self, bytes: &[u8]) -> Result<Self, Error> {
+        let len = u32::try_from(bytes.len()).map_err(|_| Error::IntegerConversion)?;
+        self.0.extend(len.to_be_bytes());
+        self.0.extend(bytes);
+
+        Ok(self)
+    }
+
+    #[must_use]
+    pub fn build(self) -> Key {
+        Key(self.0)
+    }
+}
+
+/// Starts a builder whose segments are appended to an existing key.
+impl From<Key> for KeyBuilder {
+    fn from(prefix: Key) -> Self {
+        Self(prefix.0)
+    }
+}
+
+#[cfg_attr(test, allow(unused_macros))]
+macro_rules! ffi_linker {
+    ($mod:literal, $link:literal, $caller:ident, $key:ident) => {
+        #[link(wasm_import_module = $mod)]
+        extern "C" {
+            #[link_name = $link]
+            fn ffi(caller: i64, key: i64) -> i64;
+        }
+
+        let $caller = to_host_ptr($caller.id())?;
+        let $key = to_host_ptr($key)?;
+    };
+    ($mod:literal, $link:literal, $caller:ident, $key:ident, $value:ident) => {
+        #[link(wasm_import_module = $mod)]
+        extern "C" {
+            #[link_name = $link]
+            fn ffi(caller: i64, key: i64, value: i64) -> i64;
+        }
+
+        let $caller = to_host_ptr($caller.id())?;
+        let $key = to_host_ptr($key)?;
+        let value_bytes = borsh::to_vec($value).map_err(|_| Error::Serialization)?;
+        let $value = to_host_ptr(&value_bytes)?;
+    };
+}
+
+#[cfg_attr(test, allow(unused_macros))]
+macro_rules! call_host_fn {
+    (
+        wasm_import_module = $mod:literal
+        link_name = $link:literal
+        args = ($caller:ident, $key:ident)
+    ) => {{
+        ffi_linker!($mod, $link, $caller, $key);
+
+        unsafe { ffi($caller, $key) }
+    }};
+
+    (
+        wasm_import_module = $mod:literal
+        link_name = $link:literal
+        args = ($caller:ident, $key:ident, $value:ident)
+    ) => {{
+        ffi_linker!($mod, $link, $caller, $key, $value);
+
+        unsafe { ffi($caller, $key, $value) }
+    }};
+}
+
+/// A write the SDK sent to the host, as recorded in the journal with the
+/// `test-utils` feature.
+#[cfg(feature = "test-utils")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JournalEntry {
+    /// `value`, as encoded, was stored under `key`.
+    Put { key: Vec<u8>, value: Vec<u8> },
+    /// `key` was deleted.
+    Delete { key: Vec<u8> },
+}
+
+#[cfg(feature = "test-utils")]
+thread_local! {
+    static JOURNAL: std::cell::RefCell<Vec<JournalEntry>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Returns every write sent to the host on the current thread since the last
+/// call, in the order the host received them, and clears the journal.
+///
+/// Only writes that reach the host are recorded: a value stored in a [State]
+/// is recorded when the state is flushed, and a write rejected in a
+/// `#[view]` function is not recorded at all. Taking or swapping a value is
+/// recorded as the delete or put it performs. Pass a prefix of the journal
+/// to [`replay`] to reproduce an invocation that stopped partway through.
+#[cfg(feature = "test-utils")]
+#[must_use]
+pub fn take_journal() -> Vec<JournalEntry> {
+    JOURNAL.take()
+}
+
+/// Sends the writes of `journal` to the host for `program`, in order. The
+/// writes are recorded in the journal again.
+/// # Errors
+/// Returns an [Error] if the host fails to handle a write.
+#[cfg(feature = "test-utils")]
+pub fn replay(program: &Program, journal: &[JournalEntry]) -> Result<(), Error> {
+    for entry in journal {
+        match entry {
+            JournalEntry::Put { key, value } => {
+                metrics::record("state.put", key.len() + value.len(), || unsafe {
+                    host::put_bytes(program, &Key::new(key.clone()), value)
+                })?;
+            }
+            JournalEntry::Delete { key } => {
+                metrics::record("state.delete", key.len(), || unsafe {
+                    host::delete_bytes(program, &Key::new(key.clone()))
+                })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "test-utils"))]
+use imports as host;
+
+/// The bindings, with every write recorded in the journal.
+#[cfg(feature = "test-utils")]
+mod host {
+    pub(super) use super::imports::*;
+    use super::{Error, JournalEntry, Key, Program, JOURNAL};
+
+    fn record(entry: JournalEntry) {
+        JOURNAL.with_borrow_mut(|journal| journal.push(entry));
+    }
+
+    pub(super) unsafe fn put_bytes(caller: &Program, key: &Key, value: &[u8]) -> Result<(), Error> {
+        super::imports::put_bytes(caller, key, &value)?;
+        record(JournalEntry::Put {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+        Ok(())
+    }
+
+    #[cfg(feature = "unstable")]
+    pub(super) unsafe fn swap_bytes(
+        caller: &Program,
+        key: &Key,
+        value: &[u8],
+    ) -> Result<i64, Error> {
+        let old = super::imports::swap_bytes(caller, key, &value)?;
+        record(JournalEntry::Put {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+        Ok(old)
+    }
+
+    pub(super) unsafe fn delete_bytes(caller: &Program, key: &Key) -> Result<(), Error> {
+        super::imports::delete_bytes(caller, key)?;
+        record(JournalEntry::Delete { key: key.to_vec() });
+        Ok(())
+    }
+
+    #[cfg(feature = "unstable")]
+    pub(super) unsafe fn take_bytes(caller: &Program, key: &Key) -> Result<i64, Error> {
+        let old = super::imports::take_bytes(caller, key)?;
+        // any other negative value is a failed take
+        if old >= crate::host::NOTHING {
+            record(JournalEntry::Delete { key: key.to_vec() });
+        }
+        Ok(old)
+    }
+}
+
+#[cfg(test)]
+use crate::mock::state as imports;
+
+#[cfg(not(test))]
+mod imports {
+    use super::{BorshSerialize, Key, Program};
+    use crate::{memory::to_host_ptr, state::Error};
+
+    /// Persists the bytes at `value` at key on the host storage.
+    pub(super) unsafe fn put_bytes<V>(caller: &Program, key: &Key, value: &V) -> Result<(), Error>
+    where
+        V: BorshSerialize,
+    {
+        match call_host_fn! {
+            wasm_import_module = "state"
+            link_name = "put"
+            args = (caller, key, value)
+        } {
+            0 => Ok(()),
+            _ => Err(Error::Write),
+        }
+    }
+
+    /// Gets the bytes associated with the key from the host.
+    pub(super) unsafe fn get_bytes(caller: &Program, key: &Key) -> Result<i64, Error> {
+        Ok(call_host_fn! {
+            wasm_import_module = "state"
+                link_name = "get"
                 args = (caller, key)
         })
     }
 
+    /// Gets the length of the bytes associated with the key, or -1.
+    #[cfg(feature = "unstable")]
+    pub(super) unsafe fn len_bytes(caller: &Program, key: &Key) -> Result<i64, Error> {
+        Ok(call_host_fn! {
+            wasm_import_module = "state"
+            link_name = "len"
+            args = (caller, key)
+        })
+    }
+
+    /// Persists the bytes at `value` at key and returns the bytes it replaced.
+    #[cfg(feature = "unstable")]
+    pub(super) unsafe fn swap_bytes<V>(caller: &Program, key: &Key, value: &V) -> Result<i64, Error>
+    where
+        V: BorshSerialize,
+    {
+        Ok(call_host_fn! {
+            wasm_import_module = "state"
+            link_name = "swap"
+            args = (caller, key, value)
+        })
+    }
+
+    /// Deletes the value at `key` and returns it.
+    #[cfg(feature = "unstable")]
+    pub(super) unsafe fn take_bytes(caller: &Program, key: &Key) -> Result<i64, Error> {
+        Ok(call_host_fn! {
+            wasm_import_module = "state"
+            link_name = "take"
+            args = (caller, key)
+        })
+    }
+
+    /// Gets a page of the entries under `prefix` that follow `start_after`.
+    #[cfg(feature = "unstable")]
+    pub(super) unsafe fn paginate_bytes(
+        caller: &Program,
+        prefix: &[u8],
+        start_after: Option<&[u8]>,
+        limit: u32,
+    ) -> Result<i64, Error> {
+        let cursor = &(start_after, limit);
+        Ok(call_host_fn! {
+            wasm_import_module = "state"
+            link_name = "paginate"
+            args = (caller, prefix, cursor)
+        })
+    }
+
+    /// Gets the version of the key, bumped on every write to it.
+    #[cfg(feature = "unstable")]
+    #[allow(clippy::cast_sign_loss)] // the host returns the `u64` bit pattern
+    pub(super) unsafe fn version(caller: &Program, key: &Key) -> Result<u64, Error> {
+        Ok(call_host_fn! {
+            wasm_import_module = "state"
+            link_name = "version"
+            args = (caller, key)
+        } as u64)
+    }
+
+    /// Returns whether every key in the serialized `reads` still has the
+    /// version it is listed with.
+    #[cfg(feature = "unstable")]
+    pub(super) unsafe fn check_versions(caller: &Program, reads: &[u8]) -> Result<bool, Error> {
+        match call_host_fn! {
+            wasm_import_module = "state"
+            link_name = "check_versions"
+            args = (caller, reads)
+        } {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Read),
+        }
+    }
+
     /// Deletes the bytes at key ptr from the host storage
     pub(super) unsafe fn delete_bytes(caller: &Program, key: &Key) -> Result<(), Error> {
         match call_host_fn! {
@@ -244,3 +2006,777 @@ mod host {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "unstable")]
+    use super::SCAN_PAGE_SIZE;
+    use super::{
+        is_read_only, set_max_value_size, Direction, Error, Key, KeyBuilder, ReadOnlyGuard, State,
+        StateCell, StateField, StateNamespace, MAX_VALUE_SIZE,
+    };
+    use crate::{memory, mock, program::Program};
+
+    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+    struct TestKey(u8);
+
+    impl From<TestKey> for Key {
+        fn from(key: TestKey) -> Self {
+            Key::new(vec![key.0])
+        }
+    }
+
+    fn new_state() -> State<TestKey> {
+        State::new(Program::new([1; Program::LEN]))
+    }
+
+    #[test]
+    fn errors_carry_context() {
+        let err = Error::InvalidPointer { ptr: 0x1c };
+        assert_eq!(err.to_string(), "invalid pointer offset: 0x1c");
+        assert_eq!(format!("{err:?}"), "InvalidPointer { ptr: 28 }");
+
+        let err = Error::InvalidByteLength {
+            expected: 4,
+            got: 3,
+        };
+        assert_eq!(err.to_string(), "invalid byte length: expected 4, got 3");
+        assert_eq!(
+            format!("{err:?}"),
+            "InvalidByteLength { expected: 4, got: 3 }"
+        );
+
+        let err = Error::from_borsh(&borsh::from_slice::<u64>(&[0; 3]).unwrap_err());
+        assert_eq!(
+            err.to_string(),
+            "failed to deserialize bytes: Unexpected length of input"
+        );
+        assert!(format!("{:?}", err.clone()).contains("Unexpected length of input"));
+    }
+
+    #[test]
+    fn missing_key_is_fetched_once() {
+        let mut state = new_state();
+
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), None);
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), None);
+
+        assert_eq!(mock::with(|host| host.calls("state.get")), 1);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn value_len_does_not_read_the_value() {
+        let mut state = new_state();
+        state.store(TestKey(0), &vec![0_u8; 96]).unwrap();
+        // a pending write is measured locally
+        assert_eq!(state.value_len(TestKey(0)).unwrap(), Some(100));
+        drop(state);
+
+        let mut state = new_state();
+        assert_eq!(state.value_len(TestKey(0)).unwrap(), Some(100));
+        assert_eq!(state.value_len(TestKey(1)).unwrap(), None);
+        assert_eq!(mock::with(|host| host.calls("state.len")), 2);
+        assert_eq!(mock::with(|host| host.calls("state.get")), 0);
+    }
+
+    #[test]
+    fn undecodable_value_reports_its_key() {
+        let mut state = new_state();
+        state.store(TestKey(3), &[0xde_u8, 0xad]).unwrap();
+        drop(state);
+
+        let program = Program::new([1; Program::LEN]);
+        let err = StateCell::<u64>::load(program, TestKey(3))
+            .err()
+            .expect("the value cannot be decoded");
+        assert!(matches!(&err, Error::CorruptState { key, .. } if *key == [3]));
+
+        let err = new_state().get::<u64>(TestKey(3)).unwrap_err();
+        assert!(matches!(&err, Error::CorruptState { key, .. } if *key == [3]));
+        let source = std::error::Error::source(&err).expect("the decode error is kept");
+        assert!(source
+            .to_string()
+            .starts_with("failed to deserialize bytes"));
+        assert_eq!(
+            err.to_string(),
+            "stored value of key [03] cannot be decoded"
+        );
+    }
+
+    #[test]
+    fn bounded_cache_evicts_the_least_recently_used_key() {
+        let mut state = State::<TestKey>::with_capacity(Program::new([1; Program::LEN]), 2);
+        new_state().store(TestKey(1), &1_u64).unwrap();
+
+        assert_eq!(state.get::<u64>(TestKey(1)).unwrap(), Some(1));
+        state.store(TestKey(0), &0_u64).unwrap();
+        // the clean read of key 1 is the oldest entry, so it goes first
+        assert_eq!(state.get::<u64>(TestKey(2)).unwrap(), None);
+        assert_eq!(mock::with(|host| host.calls("state.put")), 1);
+
+        // the pending write of key 0 is the oldest now, and is written back
+        assert_eq!(state.get::<u64>(TestKey(3)).unwrap(), None);
+        assert_eq!(mock::with(|host| host.calls("state.put")), 2);
+        assert_eq!(new_state().get::<u64>(TestKey(0)).unwrap(), Some(0));
+
+        // key 1 was evicted, so reading it goes back to the host
+        let reads = mock::with(|host| host.calls("state.get"));
+        assert_eq!(state.get::<u64>(TestKey(1)).unwrap(), Some(1));
+        assert_eq!(mock::with(|host| host.calls("state.get")), reads + 1);
+    }
+
+    #[test]
+    fn reading_a_cached_key_makes_it_the_most_recent() {
+        let mut state = State::<TestKey>::with_capacity(Program::new([1; Program::LEN]), 2);
+
+        state.store(TestKey(0), &0_u64).unwrap();
+        state.store(TestKey(1), &1_u64).unwrap();
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), Some(0));
+        // key 1 is the least recently used now, so its write goes first
+        assert_eq!(state.get::<u64>(TestKey(2)).unwrap(), None);
+        assert_eq!(new_state().get::<u64>(TestKey(1)).unwrap(), Some(1));
+        assert_eq!(new_state().get::<u64>(TestKey(0)).unwrap(), None);
+    }
+
+    #[test]
+    fn store_replaces_cached_absence() {
+        let mut state = new_state();
+
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), None);
+        state.store(TestKey(0), &42_u64).unwrap();
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), Some(42));
+
+        assert_eq!(mock::with(|host| host.calls("state.get")), 1);
+    }
+
+    #[test]
+    fn deleted_key_reads_as_absent() {
+        let mut state = new_state();
+        state.store(TestKey(0), &42_u64).unwrap();
+        drop(state);
+
+        let mut state = new_state();
+        state.delete(TestKey(0)).unwrap();
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), None);
+        drop(state);
+
+        assert_eq!(new_state().get::<u64>(TestKey(0)).unwrap(), None);
+        assert_eq!(mock::with(|host| host.calls("state.get")), 1);
+    }
+
+    #[test]
+    fn borrowed_value_is_freed_on_drop() {
+        new_state().store(TestKey(0), &[9_u8; 64]).unwrap();
+
+        let mut state = new_state();
+        let value = state.get_borrowed(TestKey(0)).unwrap().unwrap();
+        assert_eq!(*value, [9; 64]);
+        assert_eq!(memory::tracked_blocks(), 1);
+        drop(value);
+        assert_eq!(memory::tracked_blocks(), 0);
+
+        // not cached, so the next read goes to the host again
+        assert!(state.get_borrowed(TestKey(0)).unwrap().is_some());
+        assert!(state.get_borrowed(TestKey(1)).unwrap().is_none());
+        assert_eq!(mock::with(|host| host.calls("state.get")), 3);
+    }
+
+    #[test]
+    fn borrowed_value_prefers_the_cache() {
+        let mut state = new_state();
+        state.store(TestKey(0), &7_u8).unwrap();
+
+        assert_eq!(*state.get_borrowed(TestKey(0)).unwrap().unwrap(), [7]);
+        assert_eq!(mock::with(|host| host.calls("state.get")), 0);
+    }
+
+    #[test]
+    fn entry_inserts_if_absent() {
+        let mut state = new_state();
+        let mut entry = state.entry::<u64>(TestKey(0)).unwrap();
+        *entry.or_insert(10) += 1;
+        entry.commit().unwrap();
+        drop(state);
+
+        mock::with(|host| {
+            assert_eq!(host.calls("state.get"), 1);
+            assert_eq!(host.calls("state.put"), 1);
+        });
+        assert_eq!(new_state().get::<u64>(TestKey(0)).unwrap(), Some(11));
+    }
+
+    #[test]
+    fn entry_modifies_if_present() {
+        new_state().store(TestKey(0), &5_u64).unwrap();
+
+        let mut state = new_state();
+        let mut entry = state
+            .entry::<u64>(TestKey(0))
+            .unwrap()
+            .and_modify(|count| *count *= 2);
+        assert_eq!(*entry.or_insert(0), 10);
+        drop(entry);
+        drop(state);
+
+        assert_eq!(new_state().get::<u64>(TestKey(0)).unwrap(), Some(10));
+    }
+
+    #[test]
+    fn entry_of_absent_key_is_not_stored_unless_inserted() {
+        let mut state = new_state();
+        state
+            .entry::<u64>(TestKey(0))
+            .unwrap()
+            .and_modify(|count| *count += 1)
+            .commit()
+            .unwrap();
+        drop(state);
+
+        assert_eq!(mock::with(|host| host.calls("state.put")), 0);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn swap_returns_previous_value() {
+        let mut state = new_state();
+        state.store(TestKey(0), &1_u64).unwrap();
+        drop(state);
+
+        let mut state = new_state();
+        assert_eq!(state.swap(TestKey(0), &2_u64).unwrap(), Some(1));
+        assert_eq!(state.swap(TestKey(1), &3_u64).unwrap(), None);
+        drop(state);
+
+        let mut state = new_state();
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), Some(2));
+        assert_eq!(state.get::<u64>(TestKey(1)).unwrap(), Some(3));
+        assert_eq!(mock::with(|host| host.calls("state.swap")), 2);
+        assert_eq!(mock::with(|host| host.calls("state.put")), 1);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn swap_of_cached_key_stays_local() {
+        let mut state = new_state();
+        state.store(TestKey(0), &1_u64).unwrap();
+
+        assert_eq!(state.swap(TestKey(0), &2_u64).unwrap(), Some(1));
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), Some(2));
+        assert_eq!(mock::with(|host| host.calls("state.swap")), 0);
+    }
+
+    #[test]
+    fn unmodified_cell_is_not_written() {
+        new_state().store(TestKey(0), &[7_u64; 16]).unwrap();
+        let program = Program::new([1; Program::LEN]);
+
+        let mut cell = StateCell::<[u64; 16]>::load(program, TestKey(0)).unwrap();
+        assert_eq!(cell.get(), Some(&[7; 16]));
+        cell.flush().unwrap();
+        // mutable access that leaves the value as it was
+        cell.get_mut().unwrap()[0] = 7;
+        drop(cell);
+        assert_eq!(mock::with(|host| host.calls("state.put")), 1);
+
+        let mut cell = StateCell::<[u64; 16]>::load(program, TestKey(0)).unwrap();
+        cell.get_mut().unwrap()[0] = 8;
+        cell.flush().unwrap();
+        cell.flush().unwrap();
+        drop(cell);
+        assert_eq!(mock::with(|host| host.calls("state.put")), 2);
+
+        let cell = StateCell::<[u64; 16]>::load(program, TestKey(0)).unwrap();
+        assert_eq!(cell.get().unwrap()[0], 8);
+    }
+
+    #[test]
+    fn oversized_value_is_rejected_before_the_host() {
+        let mut state = new_state();
+        let value = vec![0_u8; MAX_VALUE_SIZE];
+
+        assert!(matches!(
+            state.store(TestKey(0), &value),
+            Err(Error::LengthTooLarge {
+                len,
+                max: MAX_VALUE_SIZE,
+                direction: Direction::Input,
+            }) if len == MAX_VALUE_SIZE + 4
+        ));
+        #[cfg(feature = "unstable")]
+        assert!(matches!(
+            state.swap(TestKey(0), &value),
+            Err(Error::LengthTooLarge { .. })
+        ));
+        assert_eq!(state.get::<Vec<u8>>(TestKey(0)).unwrap(), None);
+
+        set_max_value_size(MAX_VALUE_SIZE + 4);
+        state.store(TestKey(0), &value).unwrap();
+        drop(state);
+        assert_eq!(mock::with(|host| host.calls("state.put")), 1);
+    }
+
+    #[test]
+    fn writes_are_rejected_while_read_only() {
+        new_state().store(TestKey(0), &1_u8).unwrap();
+        let puts = mock::with(|host| host.calls("state.put"));
+
+        let guard = ReadOnlyGuard::enter();
+        let mut state = new_state();
+        assert_eq!(state.get::<u8>(TestKey(0)).unwrap(), Some(1));
+        assert!(matches!(
+            state.store(TestKey(0), &2_u8),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(state.delete(TestKey(0)), Err(Error::ReadOnly)));
+        drop(state);
+        drop(guard);
+
+        assert!(!is_read_only());
+        assert_eq!(mock::with(|host| host.calls("state.put")), puts);
+        assert_eq!(new_state().get::<u8>(TestKey(0)).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn writes_dropped_while_read_only_are_lost() {
+        new_state().store(TestKey(0), &1_u8).unwrap();
+        let puts = mock::with(|host| host.calls("state.put"));
+        let program = Program::new([1; Program::LEN]);
+
+        let guard = ReadOnlyGuard::enter();
+        let mut cell = StateCell::<u8>::load(program, TestKey(0)).unwrap();
+        cell.set(2);
+        assert!(matches!(cell.flush(), Err(Error::ReadOnly)));
+        cell.set(3);
+        drop(cell);
+        let mut field = StateField::<u8>::new(program, Key::from(TestKey(0)));
+        *field = 4;
+        drop(field);
+        let mut state = new_state();
+        *state.entry::<u8>(TestKey(0)).unwrap().or_insert(0) = 5;
+        drop(guard);
+
+        assert_eq!(mock::with(|host| host.calls("state.put")), puts);
+        assert_eq!(state.get::<u8>(TestKey(0)).unwrap(), Some(1));
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn pages_reassemble_the_prefix() {
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        for i in 0..5_u8 {
+            state.store(Key::new(vec![b'u', i]), &u32::from(i)).unwrap();
+        }
+        state.store(Key::new(vec![b'v']), &0_u32).unwrap();
+
+        let mut entries = Vec::new();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let (page, next) = state.paginate(b"u", cursor.as_deref(), 2).unwrap();
+            assert!(page.len() <= 2);
+            entries.extend(page);
+            pages += 1;
+            cursor = next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        let expected: Vec<_> = (0..5_u8)
+            .map(|i| (vec![b'u', i], borsh::to_vec(&u32::from(i)).unwrap()))
+            .collect();
+        assert_eq!(entries, expected);
+        assert_eq!(pages, 3);
+        assert_eq!(state.paginate(b"u", None, 0).unwrap(), (vec![], None));
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn raw_scan_returns_bytes_of_mixed_types() {
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        state.store(Key::new(b"m0".to_vec()), &7_u8).unwrap();
+        state
+            .store(Key::new(b"m1".to_vec()), &"seven".to_owned())
+            .unwrap();
+        state
+            .store(Key::new(b"m2".to_vec()), &(7_u64, [7_u8; 3]))
+            .unwrap();
+        state.store(Key::new(b"n0".to_vec()), &0_u8).unwrap();
+
+        let entries: Vec<_> = state
+            .scan_raw(b"m")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            [
+                (b"m0".to_vec(), borsh::to_vec(&7_u8).unwrap()),
+                (b"m1".to_vec(), borsh::to_vec(&"seven".to_owned()).unwrap()),
+                (b"m2".to_vec(), borsh::to_vec(&(7_u64, [7_u8; 3])).unwrap()),
+            ]
+        );
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn raw_scan_fetches_pages_lazily() {
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        let count = SCAN_PAGE_SIZE * 2 + 1;
+        for i in 0..count {
+            state.store(Key::new(i.to_be_bytes().to_vec()), &i).unwrap();
+        }
+
+        let mut entries = state.scan_raw(&[]).unwrap();
+        assert!(entries.next().is_some());
+        assert_eq!(mock::with(|host| host.calls("state.paginate")), 1);
+        assert_eq!(entries.count(), count as usize - 1);
+        assert_eq!(mock::with(|host| host.calls("state.paginate")), 3);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn limited_scan_reads_only_the_limit_from_host() {
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        for i in 0..SCAN_PAGE_SIZE * 3 {
+            state.store(Key::new(i.to_be_bytes().to_vec()), &i).unwrap();
+        }
+
+        assert_eq!(state.scan_raw_limit(&[], 3).unwrap().count(), 3);
+        assert_eq!(
+            mock::with(|host| (host.calls("state.paginate"), host.paged())),
+            (1, 3)
+        );
+
+        let limit = SCAN_PAGE_SIZE + 2;
+        let entries: Vec<_> = state
+            .scan_raw_limit(&[], limit)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), limit as usize);
+        assert_eq!(entries[0].0, 0_u32.to_be_bytes());
+        assert_eq!(
+            mock::with(|host| (host.calls("state.paginate"), host.paged())),
+            (3, 3 + limit as usize)
+        );
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    #[cfg(feature = "test-utils")]
+    fn journal_records_writes_in_order() {
+        use super::{replay, take_journal, JournalEntry};
+
+        let program = Program::new([1; Program::LEN]);
+        let key = |byte: u8| Key::new(vec![byte]);
+
+        let mut state = State::<Key>::new(program);
+        state.store(key(1), &10_u32).unwrap();
+        drop(state);
+        let mut state = State::<Key>::new(program);
+        assert_eq!(state.swap(key(2), &20_u32).unwrap(), None);
+        assert_eq!(state.take::<u32>(key(1)).unwrap(), Some(10));
+        // a write that is overwritten before the flush never reaches the host
+        state.store(key(3), &1_u32).unwrap();
+        state.store(key(3), &30_u32).unwrap();
+        drop(state);
+
+        let journal = take_journal();
+        assert_eq!(
+            journal,
+            [
+                JournalEntry::Put {
+                    key: vec![1],
+                    value: borsh::to_vec(&10_u32).unwrap()
+                },
+                JournalEntry::Put {
+                    key: vec![2],
+                    value: borsh::to_vec(&20_u32).unwrap()
+                },
+                JournalEntry::Delete { key: vec![1] },
+                JournalEntry::Put {
+                    key: vec![3],
+                    value: borsh::to_vec(&30_u32).unwrap()
+                },
+            ]
+        );
+        assert!(take_journal().is_empty());
+
+        // an invocation that stopped before deleting the first key
+        let replica = Program::new([2; Program::LEN]);
+        replay(&replica, &journal[..2]).unwrap();
+        let mut state = State::<Key>::new(replica);
+        assert_eq!(state.get::<u32>(key(1)).unwrap(), Some(10));
+        assert_eq!(state.get::<u32>(key(2)).unwrap(), Some(20));
+        assert_eq!(state.get::<u32>(key(3)).unwrap(), None);
+        assert_eq!(take_journal().len(), 2);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn seek_continues_from_the_key() {
+        let key = |i: u32| [&b"k"[..], &i.to_be_bytes()].concat();
+        let mut state = State::<Key>::new(Program::new([1; Program::LEN]));
+        for i in 0..SCAN_PAGE_SIZE * 2 {
+            state.store(Key::new(key(i)), &i).unwrap();
+        }
+        state.store(Key::new(b"l".to_vec()), &0_u32).unwrap();
+
+        let mut iter = state.scan_raw(b"k").unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().0, key(0));
+
+        let middle = SCAN_PAGE_SIZE + 5;
+        iter.seek(&key(middle)).unwrap();
+        let rest: Vec<_> = iter.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(rest.len(), (SCAN_PAGE_SIZE - 5) as usize);
+        assert_eq!(rest[0], (key(middle), borsh::to_vec(&middle).unwrap()));
+        assert_eq!(rest.last().unwrap().0, key(SCAN_PAGE_SIZE * 2 - 1));
+
+        // a key between two entries lands on the next one
+        iter.seek(&[key(3), vec![0]].concat()).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().0, key(4));
+
+        // outside of the prefix, seeking starts over or ends the iteration
+        iter.seek(b"a").unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().0, key(0));
+        iter.seek(b"l").unwrap();
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn state_struct_writes_only_changed_fields() {
+        #[crate::state]
+        struct Pool {
+            reserve: u64,
+            fee: u16,
+            owner: [u8; 4],
+        }
+
+        let program = Program::new([1; Program::LEN]);
+        let mut pool = Pool::load(program);
+        *pool.reserve = 100;
+        *pool.fee = 30;
+        pool.flush().unwrap();
+        drop(pool);
+        let puts = mock::with(|host| host.calls("state.put"));
+        assert_eq!(puts, 2);
+
+        // fields are only read once dereferenced
+        let mut pool = Pool::load(program);
+        assert_eq!(mock::with(|host| host.calls("state.get")), 2);
+        *pool.reserve += 1;
+        // mutated, but encoded as it was read
+        *pool.fee = 30;
+        assert_eq!(*pool.owner, [0; 4]);
+        drop(pool);
+
+        mock::with(|host| {
+            assert_eq!(host.calls("state.get"), 5);
+            assert_eq!(host.calls("state.put"), puts + 1);
+        });
+        let pool = Pool::load(program);
+        assert_eq!((*pool.reserve, *pool.fee), (101, 30));
+        assert_eq!(
+            super::field_key("Pool", "fee"),
+            KeyBuilder::new()
+                .with(&"Pool")
+                .unwrap()
+                .with(&"fee")
+                .unwrap()
+                .build()
+        );
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn take_deletes_and_returns_the_value() {
+        let mut state = new_state();
+        state.store(TestKey(0), &7_u64).unwrap();
+        drop(state);
+
+        let mut state = new_state();
+        assert_eq!(state.take::<u64>(TestKey(0)).unwrap(), Some(7));
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), None);
+        assert_eq!(state.take::<u64>(TestKey(1)).unwrap(), None);
+        // a pending write is taken as well
+        state.store(TestKey(2), &9_u64).unwrap();
+        assert_eq!(state.take::<u64>(TestKey(2)).unwrap(), Some(9));
+        drop(state);
+
+        mock::with(|host| {
+            assert_eq!(host.calls("state.take"), 2);
+            assert_eq!(host.calls("state.delete"), 1);
+            assert_eq!(host.calls("state.get"), 0);
+        });
+        let mut state = new_state();
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), None);
+        assert_eq!(state.get::<u64>(TestKey(2)).unwrap(), None);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn failed_take_keeps_the_value() {
+        let mut state = new_state();
+        state.store(TestKey(0), &7_u64).unwrap();
+        drop(state);
+        mock::with(|host| {
+            host.fail("state.take");
+            host.fail("state.delete");
+        });
+
+        let mut state = new_state();
+        assert!(matches!(
+            state.take::<u64>(TestKey(0)),
+            Err(Error::HostError(_))
+        ));
+        // a missing key is not cached by the failed take
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), Some(7));
+        // neither is a cached one taken
+        assert!(matches!(state.take::<u64>(TestKey(0)), Err(Error::Delete)));
+        assert_eq!(state.get::<u64>(TestKey(0)).unwrap(), Some(7));
+        assert_eq!(mock::with(|host| host.calls("state.get")), 1);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_value_round_trips_smaller() {
+        let config: Vec<String> = (0..50)
+            .map(|i| format!("{{\"pool\":{i},\"fee\":30,\"paused\":false}}"))
+            .collect();
+        let noise: Vec<u8> = (0..64_u8).map(|i| i.wrapping_mul(167)).collect();
+
+        let mut state = new_state();
+        state.put_compressed(TestKey(0), &config).unwrap();
+        state.put_compressed(TestKey(1), &noise).unwrap();
+        drop(state);
+
+        let mut state = new_state();
+        let stored = state.get_borrowed(TestKey(0)).unwrap().unwrap().len();
+        assert!(stored < borsh::to_vec(&config).unwrap().len() / 2);
+        assert_eq!(state.get_compressed(TestKey(0)).unwrap(), Some(config));
+        // incompressible bytes are stored as is behind the format byte
+        let stored = state.get_borrowed(TestKey(1)).unwrap().unwrap().len();
+        assert_eq!(stored, borsh::to_vec(&noise).unwrap().len() + 1);
+        assert_eq!(state.get_compressed(TestKey(1)).unwrap(), Some(noise));
+        assert_eq!(state.get_compressed::<u8>(TestKey(2)).unwrap(), None);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn transaction_applies_writes_without_conflict() {
+        let mut state = new_state();
+        state.store(TestKey(0), &10_u64).unwrap();
+
+        let mut tx = state.transaction().unwrap();
+        let balance = tx.get::<u64>(TestKey(0)).unwrap().unwrap();
+        tx.store(TestKey(0), &(balance - 3)).unwrap();
+        assert_eq!(tx.get::<u64>(TestKey(0)).unwrap(), Some(7));
+        tx.delete(TestKey(1)).unwrap();
+        tx.commit().unwrap();
+
+        assert_eq!(new_state().get::<u64>(TestKey(0)).unwrap(), Some(7));
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn conflicting_write_aborts_the_commit() {
+        let mut state = new_state();
+        state.store(TestKey(0), &10_u64).unwrap();
+
+        let mut tx = state.transaction().unwrap();
+        assert_eq!(tx.get::<u64>(TestKey(0)).unwrap(), Some(10));
+        // a read of an absent key is checked as well
+        assert_eq!(tx.get::<u64>(TestKey(1)).unwrap(), None);
+        tx.store(TestKey(2), &1_u64).unwrap();
+
+        // a write made while the transaction is open, as by a re-entrant call
+        new_state().store(TestKey(1), &5_u64).unwrap();
+
+        assert!(matches!(tx.commit(), Err(Error::Conflict)));
+        assert_eq!(new_state().get::<u64>(TestKey(2)).unwrap(), None);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn expired_value_reads_as_absent() {
+        mock::with(|host| host.set_timestamp(100));
+
+        let mut state = new_state();
+        state.put_with_ttl(TestKey(0), &42_u64, 100).unwrap();
+        state.put_with_ttl(TestKey(1), &7_u64, 101).unwrap();
+        drop(state);
+
+        let mut state = new_state();
+        assert_eq!(state.get_with_ttl::<u64>(TestKey(0)).unwrap(), None);
+        assert_eq!(state.get_with_ttl::<u64>(TestKey(1)).unwrap(), Some(7));
+        assert_eq!(state.get_with_ttl::<u64>(TestKey(2)).unwrap(), None);
+    }
+
+    #[test]
+    fn namespaces_are_isolated() {
+        let program = Program::new([1; Program::LEN]);
+        let mut auth = StateNamespace::new(program, "auth").unwrap();
+        let mut vault = StateNamespace::new(program, "vault").unwrap();
+        auth.store(TestKey(0), &1_u64).unwrap();
+        vault.store(TestKey(0), &2_u64).unwrap();
+        drop((auth, vault));
+
+        let mut auth = StateNamespace::new(program, "auth").unwrap();
+        let mut vault = StateNamespace::new(program, "vault").unwrap();
+        assert_eq!(auth.get::<u64>(TestKey(0)).unwrap(), Some(1));
+        assert_eq!(vault.get::<u64>(TestKey(0)).unwrap(), Some(2));
+        assert_eq!(new_state().get::<u64>(TestKey(0)).unwrap(), None);
+
+        vault.delete(TestKey(0)).unwrap();
+        drop((auth, vault));
+        let mut auth = StateNamespace::new(program, "auth").unwrap();
+        assert_eq!(auth.get::<u64>(TestKey(0)).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn namespaced_keys_do_not_collide() {
+        let program = Program::new([1; Program::LEN]);
+        let key = |module, key: &[u8]| {
+            StateNamespace::<Key>::new(program, module)
+                .and_then(|namespace| namespace.key(Key::new(key.to_vec())))
+                .unwrap()
+        };
+
+        assert_ne!(key("ab", b"c"), key("a", b"bc"));
+        assert_ne!(key("a", b""), key("", b"a"));
+        assert_eq!(
+            key("ab", &[7, 8, 9]).to_vec(),
+            [0, 0, 0, 2, b'a', b'b', 0, 0, 0, 3, 7, 8, 9]
+        );
+    }
+
+    #[test]
+    fn key_segments_do_not_collide() {
+        // fixed size arrays are serialized without a length, so the naive
+        // concatenation of both sequences is [1, 2, 3]
+        let naive = |segments: &[&[u8]]| segments.concat();
+        assert_eq!(naive(&[&[1, 2], &[3]]), naive(&[&[1], &[2, 3]]));
+
+        let split_last = KeyBuilder::new()
+            .with(&[1_u8, 2])
+            .and_then(|builder| builder.with(&[3_u8]))
+            .unwrap()
+            .build();
+        let split_first = KeyBuilder::new()
+            .with(&[1_u8])
+            .and_then(|builder| builder.with(&[2_u8, 3]))
+            .unwrap()
+            .build();
+        let unsplit = KeyBuilder::new()
+            .with(&[1_u8, 2, 3])
+            .and_then(|builder| builder.with(&[0_u8; 0]))
+            .unwrap()
+            .build();
+
+        assert_ne!(split_last, split_first);
+        assert_ne!(split_last, unsplit);
+        assert_ne!(split_first, unsplit);
+    }
+}
@@ -0,0 +1,186 @@
+//! Points in time and spans between them, both counted in whole seconds.
+//!
+//! Arithmetic is checked: a deadline past the end of [`u64`] or a span that
+//! would be negative is an error rather than a silently wrong value.
+
+#[cfg(feature = "unstable")]
+use crate::context;
+use crate::state::Error as StateError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use std::ops::{Add, Sub};
+
+/// A point in time, in seconds since the Unix epoch, as reported for blocks.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// Returns the timestamp `secs` seconds after the Unix epoch.
+    #[must_use]
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(secs)
+    }
+
+    /// Returns the number of seconds since the Unix epoch.
+    #[must_use]
+    pub const fn as_secs(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the timestamp of the block the current call is executed in.
+    #[cfg(feature = "unstable")]
+    #[must_use]
+    pub fn now() -> Self {
+        Self(context::timestamp())
+    }
+}
+
+/// A span of time, in seconds.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+)]
+pub struct Duration(u64);
+
+impl Duration {
+    /// Returns a span of `secs` seconds.
+    #[must_use]
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(secs)
+    }
+
+    /// Returns the length of the span in seconds.
+    #[must_use]
+    pub const fn as_secs(self) -> u64 {
+        self.0
+    }
+}
+
+/// Returns the timestamp `rhs` after `self`, or [`StateError::Overflow`] if it
+/// does not fit a `u64`.
+impl Add<Duration> for Timestamp {
+    type Output = Result<Timestamp, StateError>;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        self.0
+            .checked_add(rhs.0)
+            .map(Timestamp)
+            .ok_or(StateError::Overflow)
+    }
+}
+
+/// Returns the span from `rhs` to `self`, or [`StateError::Overflow`] if `rhs`
+/// is later than `self`.
+impl Sub for Timestamp {
+    type Output = Result<Duration, StateError>;
+
+    fn sub(self, rhs: Timestamp) -> Self::Output {
+        self.0
+            .checked_sub(rhs.0)
+            .map(Duration)
+            .ok_or(StateError::Overflow)
+    }
+}
+
+/// Returns the nanoseconds elapsed on a monotonic clock of the test host,
+/// for measuring how long a section of a program takes in tests and
+/// benchmarks. The clock starts at an arbitrary point, so only the difference
+/// between two readings means anything.
+///
+/// The clock is **not** consensus safe: every node reads a different value,
+/// so a program that lets it decide anything on chain would fork. It is only
+/// available with the `test-utils` feature, which must never be enabled for a
+/// program that is deployed, along with `unstable`.
+#[cfg(all(feature = "test-utils", feature = "unstable"))]
+#[must_use]
+pub fn monotonic_nanos() -> u128 {
+    crate::metrics::record("clock.monotonic_nanos", 0, host::monotonic_nanos)
+}
+
+#[cfg(all(test, feature = "test-utils", feature = "unstable"))]
+use crate::mock::clock as host;
+
+#[cfg(all(not(test), feature = "test-utils", feature = "unstable"))]
+mod host {
+    #[link(wasm_import_module = "clock")]
+    extern "C" {
+        #[link_name = "monotonic_nanos"]
+        fn _monotonic_nanos() -> i64;
+    }
+
+    /// Reads the test host's monotonic clock.
+    pub(super) fn monotonic_nanos() -> u128 {
+        // the host returns a non-negative count of nanoseconds
+        #[allow(clippy::cast_sign_loss)]
+        let nanos = unsafe { _monotonic_nanos() } as u64;
+        nanos.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Duration, Timestamp};
+    #[cfg(feature = "unstable")]
+    use crate::mock;
+    use crate::state::Error as StateError;
+
+    #[test]
+    fn deadline_at_the_end_of_time() {
+        let now = Timestamp::from_secs(u64::MAX - 10);
+
+        let deadline = (now + Duration::from_secs(10)).unwrap();
+        assert_eq!(deadline.as_secs(), u64::MAX);
+        assert!(matches!(
+            now + Duration::from_secs(11),
+            Err(StateError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn span_between_timestamps() {
+        let start = Timestamp::from_secs(1_000);
+        let end = Timestamp::from_secs(1_060);
+
+        assert_eq!((end - start).unwrap(), Duration::from_secs(60));
+        assert_eq!((start - start).unwrap(), Duration::default());
+        assert!(matches!(start - end, Err(StateError::Overflow)));
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn now_is_the_block_timestamp() {
+        mock::with(|host| host.set_timestamp(1_700_000_000));
+        assert_eq!(Timestamp::now(), Timestamp::from_secs(1_700_000_000));
+    }
+
+    #[cfg(all(feature = "test-utils", feature = "unstable"))]
+    #[test]
+    fn monotonic_clock_measures_a_sleep() {
+        let start = super::monotonic_nanos();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let elapsed = super::monotonic_nanos() - start;
+
+        assert!(elapsed >= 2_000_000, "measured {elapsed}ns");
+        assert_eq!(mock::with(|host| host.calls("clock.monotonic_nanos")), 2);
+    }
+}
@@ -1,8 +1,35 @@
+#[cfg(feature = "unstable")]
+use crate::{host::decode_result, memory::from_host_ptr, metrics, state::Error as StateError};
 use borsh::{BorshDeserialize, BorshSerialize};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    num::ParseIntError,
+    ops::{Deref, DerefMut},
+    str::FromStr,
+};
 
 /// A struct that enforces a fixed length of 32 bytes which represents an address.
-
-#[derive(Clone, Copy, PartialEq, Eq, Debug, BorshSerialize, BorshDeserialize, Hash)]
+///
+/// The default address is the zero address.
+///
+/// Addresses are ordered lexicographically by their bytes. The ordering
+/// depends on nothing but the bytes, so iterating a sorted collection of
+/// addresses visits them in the same order on every node, which makes them
+/// safe to use as keys of a [`BTreeMap`](std::collections::BTreeMap).
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Debug,
+    Default,
+    BorshSerialize,
+    BorshDeserialize,
+    Hash,
+)]
 pub struct Address([u8; Self::LEN]);
 
 impl Address {
@@ -17,6 +44,53 @@ impl Address {
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
+
+    /// Returns the address made of zero bytes. By convention it stands for "no
+    /// address", such as an owner that has not been set, and tokens sent to it
+    /// are considered burned since no key controls it.
+    #[must_use]
+    pub const fn zero() -> Self {
+        Self([0; Self::LEN])
+    }
+
+    /// Returns `true` if this is the [zero](Address::zero) address.
+    #[must_use]
+    pub fn is_zero(&self) -> bool {
+        *self == Self::zero()
+    }
+
+    /// Returns `true` if the addresses are equal, taking the same time
+    /// whichever bytes differ. `==` stops at the first byte that differs, so
+    /// how long it takes tells an observer how much of the address matched.
+    /// Prefer this when `other` is a value the caller should not learn
+    /// byte by byte, such as a hidden commitment, and `==` everywhere else.
+    #[must_use]
+    pub fn ct_eq(&self, other: &Address) -> bool {
+        let diff = self
+            .0
+            .iter()
+            .zip(&other.0)
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+        // keeps the compiler from turning the fold back into an early exit
+        std::hint::black_box(diff) == 0
+    }
+
+    /// Returns the address controlled by `public_key`. The address is derived
+    /// by the host with the chain's own scheme, so it matches the address
+    /// consensus assigns to the key and must be used instead of hashing the
+    /// key in the program.
+    /// # Errors
+    /// Returns [`StateError::HostError`] with the host's code if it does not
+    /// accept `public_key`, or a [`StateError`] if the key cannot be passed to
+    /// the host or the address it returns is malformed.
+    #[cfg(feature = "unstable")]
+    pub fn from_public_key(public_key: &[u8]) -> Result<Self, StateError> {
+        let ptr = metrics::record("address.from_public_key", public_key.len(), || {
+            host::from_public_key(public_key)
+        })?;
+        let ptr = decode_result(ptr)?;
+        from_host_ptr(ptr)
+    }
 }
 
 impl IntoIterator for Address {
@@ -27,3 +101,406 @@ impl IntoIterator for Address {
         IntoIterator::into_iter(self.0)
     }
 }
+
+/// A token amount backed by a `u128`, wide enough for supplies using 18 decimals.
+/// Arithmetic is checked and returns `None` instead of wrapping.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+)]
+pub struct Amount(u128);
+
+impl Amount {
+    pub const ZERO: Self = Self(0);
+    pub const MAX: Self = Self(u128::MAX);
+
+    #[must_use]
+    pub const fn new(value: u128) -> Self {
+        Self(value)
+    }
+
+    /// Returns the underlying `u128`.
+    #[must_use]
+    pub const fn get(self) -> u128 {
+        self.0
+    }
+
+    #[must_use]
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    #[must_use]
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    #[must_use]
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        self.0.checked_mul(rhs.0).map(Self)
+    }
+}
+
+impl From<u64> for Amount {
+    fn from(value: u64) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<u128> for Amount {
+    fn from(value: u128) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Amount> for u128 {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// Up to 64 boolean flags packed into a `u64`, serialized as the `u64` itself
+/// so they take 8 bytes instead of one byte per flag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, BorshSerialize, BorshDeserialize)]
+pub struct BitFlags(u64);
+
+impl BitFlags {
+    /// The number of flags that fit in the backing integer.
+    pub const BITS: u32 = u64::BITS;
+
+    #[must_use]
+    pub const fn new(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the backing `u64`.
+    #[must_use]
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the flag at `index`.
+    /// # Panics
+    /// Panics if `index` is not less than [`BitFlags::BITS`].
+    #[must_use]
+    pub fn get(self, index: u32) -> bool {
+        self.0 & Self::mask(index) != 0
+    }
+
+    /// Sets the flag at `index` to `value`.
+    /// # Panics
+    /// Panics if `index` is not less than [`BitFlags::BITS`].
+    pub fn set(&mut self, index: u32, value: bool) {
+        if value {
+            self.0 |= Self::mask(index);
+        } else {
+            self.0 &= !Self::mask(index);
+        }
+    }
+
+    fn mask(index: u32) -> u64 {
+        assert!(
+            index < Self::BITS,
+            "flag index {index} out of range for {} flags",
+            Self::BITS
+        );
+        1 << index
+    }
+}
+
+/// A `Vec` whose length is encoded as an LEB128 varint instead of borsh's
+/// fixed 4 bytes, so collections of fewer than 128 items only spend one byte
+/// on their length.
+///
+/// The encoding is not compatible with a plain `Vec`: a value stored as one
+/// cannot be read back as the other.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompactVec<T>(pub Vec<T>);
+
+impl<T> CompactVec<T> {
+    /// The longest varint a `u64` length can take.
+    const MAX_LEN_BYTES: usize = 10;
+}
+
+impl<T> From<Vec<T>> for CompactVec<T> {
+    fn from(items: Vec<T>) -> Self {
+        Self(items)
+    }
+}
+
+impl<T> Deref for CompactVec<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CompactVec<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T> BorshSerialize for CompactVec<T>
+where
+    T: BorshSerialize,
+{
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut len = self.0.len() as u64;
+        loop {
+            #[allow(clippy::cast_possible_truncation)] // masked to 7 bits
+            let byte = (len & 0x7f) as u8;
+            len >>= 7;
+            if len == 0 {
+                writer.write_all(&[byte])?;
+                break;
+            }
+            writer.write_all(&[byte | 0x80])?;
+        }
+
+        self.0.iter().try_for_each(|item| item.serialize(writer))
+    }
+}
+
+impl<T> BorshDeserialize for CompactVec<T>
+where
+    T: BorshDeserialize,
+{
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mut len = 0_u64;
+        for i in 0..Self::MAX_LEN_BYTES {
+            let byte = u8::deserialize_reader(reader)?;
+            let bits = u64::from(byte & 0x7f);
+            if i == Self::MAX_LEN_BYTES - 1 && bits > 1 {
+                return Err(invalid("length overflows a u64"));
+            }
+            len |= bits << (7 * i);
+
+            if byte & 0x80 == 0 {
+                // a trailing zero group could be dropped, so every length has
+                // exactly one encoding
+                if i > 0 && byte == 0 {
+                    return Err(invalid("length is not minimally encoded"));
+                }
+                let len = usize::try_from(len).map_err(|_| invalid("length overflows a usize"))?;
+                // don't trust the length for the allocation until the items are read
+                let mut items = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    items.push(T::deserialize_reader(reader)?);
+                }
+                return Ok(Self(items));
+            }
+        }
+
+        Err(invalid("length overflows a u64"))
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+use crate::mock::address as host;
+
+#[cfg(all(not(test), feature = "unstable"))]
+mod host {
+    use crate::{memory::to_host_ptr, state::Error};
+
+    #[link(wasm_import_module = "address")]
+    extern "C" {
+        #[link_name = "from_public_key"]
+        fn _from_public_key(public_key: i64) -> i64;
+    }
+
+    /// Returns a pointer to the address derived from `public_key`, or a
+    /// negative value if the key is invalid.
+    pub(super) fn from_public_key(public_key: &[u8]) -> Result<i64, Error> {
+        let public_key = to_host_ptr(public_key)?;
+
+        Ok(unsafe { _from_public_key(public_key) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Address, Amount, BitFlags, CompactVec};
+    #[cfg(feature = "unstable")]
+    use crate::{mock, state::Error as StateError};
+
+    #[test]
+    fn constant_time_equality_matches_eq() {
+        let address = Address::new([7; Address::LEN]);
+        assert!(address.ct_eq(&Address::new([7; Address::LEN])));
+        assert!(Address::zero().ct_eq(&Address::zero()));
+
+        for i in [0, 15, Address::LEN - 1] {
+            let mut bytes = [7; Address::LEN];
+            bytes[i] ^= 0x80;
+            assert!(!address.ct_eq(&Address::new(bytes)));
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn address_of_public_key_is_read_from_host() {
+        // the mock hands back the address it was given for the key, so this
+        // only checks that the binding passes the key and decodes the reply,
+        // not the derivation, which is the host's own
+        let public_key = [0x5a; 32];
+        let address = Address::new(std::array::from_fn(|i| u8::try_from(i).unwrap()));
+        mock::with(|host| host.add_account(&public_key, address));
+
+        assert_eq!(Address::from_public_key(&public_key).unwrap(), address);
+        assert_eq!(mock::with(|host| host.calls("address.from_public_key")), 1);
+        assert!(matches!(
+            Address::from_public_key(&[0x5b; 32]),
+            Err(StateError::HostError(-1))
+        ));
+    }
+
+    #[test]
+    fn addresses_are_ordered_by_bytes() {
+        let mut high = [0; Address::LEN];
+        high[0] = 1;
+        let mut low = [0xff; Address::LEN];
+        low[0] = 0;
+        let balances: std::collections::BTreeMap<_, _> = [
+            (Address::new(high), 3),
+            (Address::zero(), 1),
+            (Address::new(low), 2),
+        ]
+        .into_iter()
+        .collect();
+
+        let order: Vec<_> = balances.values().copied().collect();
+        assert_eq!(order, [1, 2, 3]);
+    }
+
+    #[test]
+    fn compact_vec_saves_length_bytes() {
+        for (len, len_bytes) in [(3, 1), (300, 2)] {
+            let items: Vec<u8> = (0..=u8::MAX).cycle().take(len).collect();
+            let compact = borsh::to_vec(&CompactVec(items.clone())).unwrap();
+
+            assert_eq!(compact.len(), len_bytes + len);
+            assert_eq!(borsh::to_vec(&items).unwrap().len(), 4 + len);
+            assert_eq!(
+                borsh::from_slice::<CompactVec<u8>>(&compact).unwrap().0,
+                items
+            );
+        }
+    }
+
+    #[test]
+    fn compact_vec_rejects_malformed_lengths() {
+        // 1 encoded with a redundant trailing group
+        assert!(borsh::from_slice::<CompactVec<u8>>(&[0x81, 0x00, 0x07]).is_err());
+        // more items announced than present
+        assert!(borsh::from_slice::<CompactVec<u8>>(&[0x02, 0x07]).is_err());
+        // longer than any u64
+        assert!(borsh::from_slice::<CompactVec<u8>>(&[0xff; 11]).is_err());
+    }
+
+    #[test]
+    fn bit_flags_set_and_clear() {
+        let mut flags = BitFlags::default();
+        flags.set(0, true);
+        flags.set(63, true);
+        assert!(flags.get(0) && flags.get(63));
+        assert!(!flags.get(1));
+        assert_eq!(flags.bits(), 1 << 63 | 1);
+
+        flags.set(0, false);
+        assert!(!flags.get(0));
+        assert_eq!(flags.bits(), 1 << 63);
+    }
+
+    #[test]
+    fn bit_flags_serialize_to_backing_integer() {
+        let flags = BitFlags::new(0b1011);
+        let bytes = borsh::to_vec(&flags).unwrap();
+
+        assert_eq!(bytes, 0b1011_u64.to_le_bytes());
+        assert_eq!(borsh::from_slice::<BitFlags>(&bytes).unwrap(), flags);
+    }
+
+    #[test]
+    #[should_panic = "flag index 64 out of range"]
+    fn bit_flags_index_is_bounds_checked() {
+        BitFlags::default().set(64, true);
+    }
+
+    #[test]
+    fn zero_address() {
+        assert_eq!(Address::zero().as_bytes(), [0; Address::LEN]);
+        assert_eq!(Address::default(), Address::zero());
+        assert!(Address::zero().is_zero());
+
+        let mut bytes = [0; Address::LEN];
+        bytes[Address::LEN - 1] = 1;
+        assert!(!Address::new(bytes).is_zero());
+    }
+
+    #[test]
+    fn amount_borsh_layout() {
+        let amount = Amount::new(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10);
+        let bytes = borsh::to_vec(&amount).unwrap();
+
+        assert_eq!(
+            bytes,
+            [16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1],
+            "amounts are encoded as 16 little-endian bytes"
+        );
+        assert_eq!(borsh::from_slice::<Amount>(&bytes).unwrap(), amount);
+    }
+
+    #[test]
+    fn amount_overflow() {
+        let one = Amount::from(1_u64);
+
+        assert_eq!(Amount::MAX.checked_add(one), None);
+        assert_eq!(Amount::MAX.checked_mul(Amount::new(2)), None);
+        assert_eq!(Amount::ZERO.checked_sub(one), None);
+        assert_eq!(
+            Amount::MAX
+                .checked_sub(one)
+                .and_then(|amount| amount.checked_add(one)),
+            Some(Amount::MAX)
+        );
+    }
+
+    #[test]
+    fn amount_parse_and_display() {
+        let max = u128::MAX.to_string();
+
+        assert_eq!(max.parse::<Amount>().unwrap(), Amount::MAX);
+        assert_eq!(Amount::MAX.to_string(), max);
+        assert!("340282366920938463463374607431768211456"
+            .parse::<Amount>()
+            .is_err());
+        assert!("-1".parse::<Amount>().is_err());
+    }
+}
@@ -1,50 +1,59 @@
+use borsh::BorshDeserialize;
 use std::{
     path::{Path, PathBuf},
     process::Command,
 };
 use wasmlanche_sdk::{Context, Program};
-use wasmtime::{Instance, Module, Store, TypedFunc};
+use wasmtime::{Instance, Linker, Module, Store, TypedFunc};
 
 const WASM_TARGET: &str = "wasm32-unknown-unknown";
 const TEST_PKG: &str = "test-crate";
 const PROFILE: &str = "release";
 
-#[test]
-fn public_functions() {
-    let wasm_path = {
-        let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
-        let manifest_dir = std::path::Path::new(&manifest_dir);
-        let test_crate_dir = manifest_dir.join("tests").join(TEST_PKG);
-        let target_dir = std::env::var("CARGO_TARGET_DIR")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| manifest_dir.join("target"));
-
-        let status = Command::new("cargo")
-            .arg("build")
-            .arg("--package")
-            .arg(TEST_PKG)
-            .arg("--target")
-            .arg(WASM_TARGET)
-            .arg("--profile")
-            .arg(PROFILE)
-            .arg("--target-dir")
-            .arg(&target_dir)
-            .current_dir(&test_crate_dir)
-            .status()
-            .expect("cargo build failed");
-
-        if !status.success() {
-            panic!("cargo build failed");
-        }
+/// Builds the test crate with `features` and returns the path to its wasm.
+/// Each set of features is built into its own target directory, so tests
+/// running in parallel don't overwrite each other's wasm.
+fn build_test_crate(features: &[&str]) -> PathBuf {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let manifest_dir = std::path::Path::new(&manifest_dir);
+    let test_crate_dir = manifest_dir.join("tests").join(TEST_PKG);
+    let mut target_dir = std::env::var("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| manifest_dir.join("target"));
+    if !features.is_empty() {
+        target_dir = target_dir.join(features.join("-"));
+    }
 
-        target_dir
-            .join(WASM_TARGET)
-            .join(PROFILE)
-            .join(TEST_PKG.replace('-', "_"))
-            .with_extension("wasm")
-    };
+    let status = Command::new("cargo")
+        .arg("build")
+        .arg("--package")
+        .arg(TEST_PKG)
+        .arg("--features")
+        .arg(features.join(","))
+        .arg("--target")
+        .arg(WASM_TARGET)
+        .arg("--profile")
+        .arg(PROFILE)
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .current_dir(&test_crate_dir)
+        .status()
+        .expect("cargo build failed");
+
+    if !status.success() {
+        panic!("cargo build failed");
+    }
+
+    target_dir
+        .join(WASM_TARGET)
+        .join(PROFILE)
+        .join(TEST_PKG.replace('-', "_"))
+        .with_extension("wasm")
+}
 
-    let mut test_crate = TestCrate::new(wasm_path);
+#[test]
+fn public_functions() {
+    let mut test_crate = TestCrate::new(build_test_crate(&[]));
 
     let context_ptr = test_crate.write_context();
     assert!(test_crate.always_true(context_ptr));
@@ -52,6 +61,138 @@ fn public_functions() {
     let context_ptr = test_crate.write_context();
     let combined_binary_digits = test_crate.combine_last_bit_of_each_id_byte(context_ptr);
     assert_eq!(combined_binary_digits, u32::MAX);
+
+    let result = test_crate.checked_div(7, 2);
+    assert_eq!(result, Ok(3));
+
+    let result = test_crate.checked_div(7, 0);
+    assert_eq!(result, Err(DivisionError::DivideByZero));
+
+    // the write is rejected before reaching the host, whose imports all trap
+    let context_ptr = test_crate.write_context();
+    assert!(test_crate.write_in_view(context_ptr));
+
+    // every block below is freed or handed over, or the program's leak check
+    // would trap
+    let result = test_crate.read_returned(b"host return".to_vec());
+    assert_eq!(result, Ok(b"host return".to_vec()));
+
+    let context_ptr = test_crate.write_context();
+    let returned = test_crate.host_writer_return(context_ptr);
+    let value: (u64, String) = borsh::from_slice(&returned).expect("failed to decode value");
+    assert_eq!(value, (7, "memo".to_owned()));
+
+    let context_ptr = test_crate.write_context();
+    assert_eq!(
+        test_crate.iterator_return(context_ptr),
+        [1, 2, 3, 42, 42, 42, 42]
+    );
+}
+
+#[test]
+fn returns_outlive_later_calls() {
+    let mut test_crate = TestCrate::new(build_test_crate(&[]));
+    let mut call = |numerator: i64, denominator: i64| {
+        test_crate.checked_div_ptr(
+            borsh::to_vec(&numerator).unwrap(),
+            borsh::to_vec(&denominator).unwrap(),
+        )
+    };
+
+    // the host may read a result after the program has returned again
+    let first = call(7, 2);
+    let second = call(9, 0);
+    assert_ne!(first, second);
+
+    let decode = |test_crate: &mut TestCrate, ptr| {
+        borsh::from_slice::<Result<i64, DivisionError>>(&test_crate.read_host_ptr(ptr))
+            .expect("failed to decode result")
+    };
+    assert_eq!(decode(&mut test_crate, first), Ok(3));
+    assert_eq!(
+        decode(&mut test_crate, second),
+        Err(DivisionError::DivideByZero)
+    );
+}
+
+#[test]
+fn the_host_frees_returned_blocks() {
+    let mut test_crate = TestCrate::new(build_test_crate(&[]));
+    let result = test_crate.checked_div_ptr(
+        borsh::to_vec(&7_i64).unwrap(),
+        borsh::to_vec(&2_i64).unwrap(),
+    );
+    test_crate.read_host_ptr(result);
+
+    // the block stays allocated until the host frees it, exactly once: the
+    // test crate is built with `strict-alloc`, so a double free traps
+    assert!(test_crate.deallocate(result).is_ok());
+    assert!(test_crate.deallocate(result).is_err());
+}
+
+#[test]
+fn multi_format_functions() {
+    const BORSH: u8 = 0;
+    const JSON: u8 = 1;
+
+    let mut test_crate = TestCrate::new(build_test_crate(&["multi-format"]));
+
+    // one tag for the whole call, followed by every argument
+    let tagged = |tag: u8, bytes: Vec<u8>| [vec![tag], bytes].concat();
+    let borsh = |numerator: u128, denominator: u128| {
+        tagged(BORSH, borsh::to_vec(&(numerator, denominator)).unwrap())
+    };
+    let json = |args: &str| tagged(JSON, args.as_bytes().to_vec());
+
+    let result = test_crate.checked_div_wide(borsh(7, 2));
+    assert_eq!(
+        result,
+        tagged(BORSH, borsh::to_vec(&Ok::<u128, ()>(3)).unwrap())
+    );
+
+    let result = test_crate.checked_div_wide(json("[7,2]"));
+    assert_eq!(result, tagged(JSON, br#"{"Ok":3}"#.to_vec()));
+
+    let result = test_crate.checked_div_wide(json(&format!("[{},1]", u128::MAX)));
+    assert_eq!(
+        result,
+        tagged(JSON, format!(r#"{{"Ok":{}}}"#, u128::MAX).into_bytes())
+    );
+
+    let result = test_crate.checked_div_wide(json("[7,0]"));
+    assert_eq!(result, tagged(JSON, br#"{"Err":"DivideByZero"}"#.to_vec()));
+
+    // functions that don't opt in still take borsh, one argument per pointer
+    assert_eq!(test_crate.checked_div(7, 2), Ok(3));
+}
+
+#[test]
+fn log_level_is_set_by_the_host() {
+    const DEBUG: i32 = 4;
+
+    let mut test_crate = TestCrate::new(build_test_crate(&[]));
+    let set_log_level = test_crate
+        .instance
+        .get_typed_func::<i32, i32>(&mut test_crate.store, "set_log_level")
+        .expect("set_log_level should be exported");
+
+    assert_eq!(set_log_level.call(&mut test_crate.store, DEBUG).unwrap(), 0);
+    assert_eq!(
+        set_log_level
+            .call(&mut test_crate.store, DEBUG + 1)
+            .unwrap(),
+        -1
+    );
+}
+
+#[derive(Debug, PartialEq, BorshDeserialize)]
+enum DivisionError {
+    DivideByZero,
+}
+
+#[derive(Debug, PartialEq, BorshDeserialize)]
+enum ReadError {
+    Rejected,
 }
 
 type AllocParam = i32;
@@ -63,13 +204,25 @@ struct TestCrate {
     allocate_func: TypedFunc<AllocParam, AllocReturn>,
     always_true_func: TypedFunc<i64, i64>,
     combine_last_bit_of_each_id_byte_func: TypedFunc<i64, u32>,
+    checked_div_func: TypedFunc<(i64, i64, i64), i64>,
+    checked_div_wide_func: Option<TypedFunc<(i64, i64), i64>>,
+    write_in_view_func: TypedFunc<i64, i64>,
+    read_returned_func: TypedFunc<(i64, i64), i64>,
+    host_writer_return_func: TypedFunc<i64, i64>,
+    iterator_return_func: TypedFunc<i64, i64>,
 }
 
 impl TestCrate {
     fn new(wasm_path: impl AsRef<Path>) -> Self {
         let mut store: Store<()> = Store::default();
         let module = Module::from_file(store.engine(), wasm_path).expect("failed to load wasm");
-        let instance = Instance::new(&mut store, &module, &[]).expect("failed to instantiate wasm");
+        let mut linker = Linker::new(store.engine());
+        linker
+            .define_unknown_imports_as_traps(&module)
+            .expect("failed to stub host imports");
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .expect("failed to instantiate wasm");
 
         let allocate_func = instance
             .get_typed_func::<AllocParam, AllocReturn>(&mut store, "alloc")
@@ -81,6 +234,25 @@ impl TestCrate {
         let combine_last_bit_of_each_id_byte_func = instance
             .get_typed_func::<i64, u32>(&mut store, "combine_last_bit_of_each_id_byte_guest")
             .expect("combine_last_bit_of_each_id_byte should be a function");
+        let checked_div_func = instance
+            .get_typed_func::<(i64, i64, i64), i64>(&mut store, "checked_div_guest")
+            .expect("checked_div should be a function");
+        // only exported when the test crate is built with `multi-format`
+        let checked_div_wide_func = instance
+            .get_typed_func::<(i64, i64), i64>(&mut store, "checked_div_wide_guest")
+            .ok();
+        let write_in_view_func = instance
+            .get_typed_func::<i64, i64>(&mut store, "write_in_view_guest")
+            .expect("write_in_view should be a function");
+        let read_returned_func = instance
+            .get_typed_func::<(i64, i64), i64>(&mut store, "read_returned_guest")
+            .expect("read_returned should be a function");
+        let host_writer_return_func = instance
+            .get_typed_func::<i64, i64>(&mut store, "host_writer_return_guest")
+            .expect("host_writer_return should be a function");
+        let iterator_return_func = instance
+            .get_typed_func::<i64, i64>(&mut store, "iterator_return_guest")
+            .expect("iterator_return should be a function");
 
         Self {
             store,
@@ -88,6 +260,12 @@ impl TestCrate {
             allocate_func,
             always_true_func,
             combine_last_bit_of_each_id_byte_func,
+            checked_div_func,
+            checked_div_wide_func,
+            write_in_view_func,
+            read_returned_func,
+            host_writer_return_func,
+            iterator_return_func,
         }
     }
 
@@ -119,6 +297,16 @@ impl TestCrate {
         offset
     }
 
+    /// Frees the block behind the packed `ptr` with the program's `dealloc`.
+    fn deallocate(&mut self, ptr: i64) -> wasmtime::Result<()> {
+        let dealloc = self
+            .instance
+            .get_typed_func::<i32, ()>(&mut self.store, "dealloc")
+            .expect("failed to find `dealloc` function");
+        // the address is the low half of the packed pointer
+        dealloc.call(&mut self.store, ptr as u32 as i32)
+    }
+
     fn always_true(&mut self, ptr: i32) -> bool {
         self.always_true_func
             .call(&mut self.store, ptr as i64)
@@ -131,4 +319,106 @@ impl TestCrate {
             .call(&mut self.store, ptr as i64)
             .expect("failed to call `combine_last_bit_of_each_id_byte` function")
     }
+
+    fn checked_div(&mut self, numerator: i64, denominator: i64) -> Result<i64, DivisionError> {
+        let result = self.checked_div_raw(
+            borsh::to_vec(&numerator).unwrap(),
+            borsh::to_vec(&denominator).unwrap(),
+        );
+
+        borsh::from_slice(&result).expect("failed to decode result")
+    }
+
+    fn checked_div_raw(&mut self, numerator: Vec<u8>, denominator: Vec<u8>) -> Vec<u8> {
+        let result_ptr = self.checked_div_ptr(numerator, denominator);
+        self.read_host_ptr(result_ptr)
+    }
+
+    /// Calls `checked_div` and returns the packed pointer to its result,
+    /// without reading it.
+    fn checked_div_ptr(&mut self, numerator: Vec<u8>, denominator: Vec<u8>) -> i64 {
+        let context_ptr = self.write_context();
+        let numerator_ptr = self.allocate(numerator);
+        let denominator_ptr = self.allocate(denominator);
+
+        self.checked_div_func
+            .call(
+                &mut self.store,
+                (
+                    context_ptr as i64,
+                    numerator_ptr as i64,
+                    denominator_ptr as i64,
+                ),
+            )
+            .expect("failed to call `checked_div` function")
+    }
+
+    /// Calls `checked_div_wide` with its tagged arguments and returns the raw
+    /// bytes of its result.
+    fn checked_div_wide(&mut self, args: Vec<u8>) -> Vec<u8> {
+        let context_ptr = self.write_context();
+        let args_ptr = self.allocate(args);
+
+        let result_ptr = self
+            .checked_div_wide_func
+            .expect("checked_div_wide should be a function")
+            .call(&mut self.store, (context_ptr as i64, args_ptr as i64))
+            .expect("failed to call `checked_div_wide` function");
+
+        self.read_host_ptr(result_ptr)
+    }
+
+    fn write_in_view(&mut self, ptr: i32) -> bool {
+        self.write_in_view_func
+            .call(&mut self.store, ptr as i64)
+            .expect("failed to call `write_in_view` function")
+            == true as i64
+    }
+
+    /// Calls `read_returned` with a packed pointer to a block holding
+    /// `bytes`, allocated the way the host allocates what it returns.
+    fn read_returned(&mut self, bytes: Vec<u8>) -> Result<Vec<u8>, ReadError> {
+        let len = bytes.len() as i64;
+        let offset = self.allocate(bytes);
+        let ret = i64::from(offset as u32) | (len << 32);
+
+        let context_ptr = self.write_context();
+        let ret_ptr = self.allocate(borsh::to_vec(&ret).unwrap());
+        let result_ptr = self
+            .read_returned_func
+            .call(&mut self.store, (context_ptr as i64, ret_ptr as i64))
+            .expect("failed to call `read_returned` function");
+
+        borsh::from_slice(&self.read_host_ptr(result_ptr)).expect("failed to decode result")
+    }
+
+    fn host_writer_return(&mut self, ptr: i32) -> Vec<u8> {
+        let result_ptr = self
+            .host_writer_return_func
+            .call(&mut self.store, ptr as i64)
+            .expect("failed to call `host_writer_return` function");
+
+        self.read_host_ptr(result_ptr)
+    }
+
+    fn iterator_return(&mut self, ptr: i32) -> Vec<u8> {
+        let result_ptr = self
+            .iterator_return_func
+            .call(&mut self.store, ptr as i64)
+            .expect("failed to call `iterator_return` function");
+
+        self.read_host_ptr(result_ptr)
+    }
+
+    /// Reads the bytes behind a packed `HostPtr` returned by the program.
+    fn read_host_ptr(&mut self, host_ptr: i64) -> Vec<u8> {
+        let offset = host_ptr as u32 as usize;
+        let len = (host_ptr >> 32) as usize;
+        let memory = self
+            .instance
+            .get_memory(&mut self.store, "memory")
+            .expect("failed to get memory");
+
+        memory.data(&self.store)[offset..offset + len].to_vec()
+    }
 }
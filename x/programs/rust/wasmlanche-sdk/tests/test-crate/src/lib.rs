@@ -1,6 +1,14 @@
 #![no_std]
 
-use wasmlanche_sdk::{public, Context};
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use borsh::BorshSerialize;
+use wasmlanche_sdk::{
+    memoize, public, read_host_return,
+    state::{Error as StateError, Key},
+    to_host_ptr_from_iter, view, Context, HostWriter,
+};
 
 #[public]
 pub fn always_true(_: Context) -> i64 {
@@ -16,3 +24,79 @@ pub fn combine_last_bit_of_each_id_byte(context: Context) -> u32 {
         .map(|byte| *byte as u32)
         .fold(0, |acc, byte| (acc << 1) + (byte & 1))
 }
+
+#[derive(BorshSerialize)]
+#[cfg_attr(feature = "multi-format", derive(serde::Serialize))]
+pub enum DivisionError {
+    DivideByZero,
+}
+
+#[public]
+pub fn checked_div(_: Context, numerator: i64, denominator: i64) -> Result<i64, DivisionError> {
+    numerator
+        .checked_div(denominator)
+        .ok_or(DivisionError::DivideByZero)
+}
+
+/// Like [`checked_div`], for callers that pick borsh or JSON, and with
+/// operands too wide for a JSON number to hold exactly elsewhere.
+#[cfg(feature = "multi-format")]
+#[public(multi_format)]
+pub fn checked_div_wide(
+    _: Context,
+    numerator: u128,
+    denominator: u128,
+) -> Result<u128, DivisionError> {
+    numerator
+        .checked_div(denominator)
+        .ok_or(DivisionError::DivideByZero)
+}
+
+#[derive(BorshSerialize)]
+pub enum ReadError {
+    Rejected,
+}
+
+/// Reads the bytes behind `ret`, a packed pointer to a block the host
+/// allocated, as a host import would return it. The block is freed by the
+/// read, so the leak check at the end of the call passes.
+#[public]
+pub fn read_returned(_: Context, ret: i64) -> Result<Vec<u8>, ReadError> {
+    read_host_return(ret).map_err(|_| ReadError::Rejected)
+}
+
+/// Returns `(7, "memo")` serialized through a [`HostWriter`]. The block is
+/// handed over to the host, so the leak check at the end of the call passes.
+#[public]
+pub fn host_writer_return(_: Context) -> i64 {
+    let mut writer = HostWriter::new();
+    (7_u64, "memo")
+        .serialize(&mut writer)
+        .expect("the writer has room");
+    writer.finish().expect("the block fits a host pointer")
+}
+
+/// Returns a block filled from an iterator, handed over to the host like the
+/// one [`host_writer_return`] returns.
+#[public]
+pub fn iterator_return(_: Context) -> i64 {
+    let bytes = (1_u8..=3).chain([42; 4]);
+    to_host_ptr_from_iter(bytes, 7).expect("the iterator yields 7 bytes")
+}
+
+/// A memoized value, which must expand without `std`.
+#[memoize]
+pub fn answer() -> i64 {
+    42
+}
+
+/// Attempts a write from a view, returning whether it was rejected.
+#[view]
+pub fn write_in_view(context: Context) -> i64 {
+    let mut state = context.program.state::<Key>();
+    let rejected = matches!(
+        state.store(Key::new(vec![0]), &1_u8),
+        Err(StateError::ReadOnly)
+    );
+    rejected as i64
+}